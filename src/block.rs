@@ -0,0 +1,386 @@
+use crate::data::*;
+use crate::solver::Board;
+
+// Blocks of any size, for building sets out of something other than 2x2 mega-tiles.
+// A block's side is described by its edge signature, the colours along it read clockwise round the block just like
+// MegaTile's bigrams, so two blocks fit side by side when one's signature is the other's read backwards.
+// The interior of the real puzzle is 14x14, which 3x3 blocks don't divide. plan_blocks covers it with the shape asked
+// for and fills the last row and column of blocks with narrower ones, 3x2, 2x3 and a 2x2 in the corner.
+
+pub const MAX_BLOCK_SIDE: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EdgeSignature {
+    len: u8,
+    colours: [i8; MAX_BLOCK_SIDE], // Unused places stay 0 so comparisons only see the real colours
+}
+
+impl EdgeSignature {
+    pub fn new(colours: &[i8]) -> EdgeSignature {
+        assert!(colours.len() <= MAX_BLOCK_SIDE, "blocks are at most {} pieces along a side", MAX_BLOCK_SIDE);
+        let mut signature = EdgeSignature { len: colours.len() as u8, colours: [0; MAX_BLOCK_SIDE] };
+        signature.colours[..colours.len()].copy_from_slice(colours);
+        signature
+    }
+
+    pub fn colours(&self) -> &[i8] {
+        &self.colours[..self.len as usize]
+    }
+
+    pub fn first(&self) -> i8 {
+        self.colours()[0]
+    }
+
+    pub fn last(&self) -> i8 {
+        self.colours()[self.len as usize - 1]
+    }
+
+    // What the neighbouring block has to show along the same edge
+    pub fn conjugate(&self) -> EdgeSignature {
+        let mut conjugate = *self;
+        conjugate.colours[..self.len as usize].reverse();
+        conjugate
+    }
+
+    // Reads the same both ways, so it pairs with itself
+    pub fn is_symmetric(&self) -> bool {
+        *self == self.conjugate()
+    }
+
+    // As letters, (A, B) for a bigram
+    pub fn letters(&self) -> String {
+        let letters: Vec<String> = self.colours().iter().map(|colour| ((colour + 65) as u8 as char).to_string()).collect();
+        format!("({})", letters.join(", "))
+    }
+}
+
+impl From<(i8, i8)> for EdgeSignature {
+    fn from(bigram: (i8, i8)) -> EdgeSignature {
+        EdgeSignature::new(&[bigram.0, bigram.1])
+    }
+}
+
+// Anything made of pieces with four sides to match, mega-tiles and blocks alike
+pub trait EdgeBlock {
+    fn pieces(&self) -> &[OrientedTile];
+    // Top, right, bottom, left
+    fn edges(&self) -> [EdgeSignature; 4];
+}
+
+impl EdgeBlock for MegaTile {
+    fn pieces(&self) -> &[OrientedTile] {
+        &self.tiles
+    }
+
+    fn edges(&self) -> [EdgeSignature; 4] {
+        [self.top().into(), self.right().into(), self.bottom().into(), self.left().into()]
+    }
+}
+
+// Rows and columns of pieces. Written WxH on the command line like --size.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockShape {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl BlockShape {
+    pub const MEGA_TILE: BlockShape = BlockShape { rows: 2, cols: 2 };
+
+    pub fn cells(&self) -> usize {
+        self.rows * self.cols
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub shape: BlockShape,
+    pub tiles: Vec<OrientedTile>, // Row by row from the top left
+}
+
+impl Block {
+    pub fn at(&self, row: usize, col: usize) -> &OrientedTile {
+        &self.tiles[row * self.shape.cols + col]
+    }
+
+    pub fn top(&self) -> EdgeSignature {
+        let colours: Vec<i8> = (0..self.shape.cols).map(|col| self.at(0, col).top()).collect();
+        EdgeSignature::new(&colours)
+    }
+
+    pub fn right(&self) -> EdgeSignature {
+        let colours: Vec<i8> = (0..self.shape.rows).map(|row| self.at(row, self.shape.cols - 1).right()).collect();
+        EdgeSignature::new(&colours)
+    }
+
+    pub fn bottom(&self) -> EdgeSignature {
+        let colours: Vec<i8> = (0..self.shape.cols).rev().map(|col| self.at(self.shape.rows - 1, col).bottom()).collect();
+        EdgeSignature::new(&colours)
+    }
+
+    pub fn left(&self) -> EdgeSignature {
+        let colours: Vec<i8> = (0..self.shape.rows).rev().map(|row| self.at(row, 0).left()).collect();
+        EdgeSignature::new(&colours)
+    }
+
+    // Every piece matches the ones beside it inside the block
+    pub fn holds_together(&self) -> bool {
+        (0..self.shape.rows).all(|row| (0..self.shape.cols).all(|col| {
+            let tile = self.at(row, col);
+            (col + 1 == self.shape.cols || tile.right() == self.at(row, col + 1).left())
+                && (row + 1 == self.shape.rows || tile.bottom() == self.at(row + 1, col).top())
+        }))
+    }
+}
+
+impl From<MegaTile> for Block {
+    fn from(mega_tile: MegaTile) -> Block {
+        Block { shape: BlockShape::MEGA_TILE, tiles: mega_tile.tiles.to_vec() }
+    }
+}
+
+impl EdgeBlock for Block {
+    fn pieces(&self) -> &[OrientedTile] {
+        &self.tiles
+    }
+
+    fn edges(&self) -> [EdgeSignature; 4] {
+        [self.top(), self.right(), self.bottom(), self.left()]
+    }
+}
+
+// Where a block goes, by the board square of its top left piece
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockPlacement {
+    pub row: usize,
+    pub col: usize,
+    pub shape: BlockShape,
+}
+
+impl BlockPlacement {
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        (self.row..self.row + self.shape.rows).contains(&row) && (self.col..self.col + self.shape.cols).contains(&col)
+    }
+
+    // Position in the block's tiles of a board square inside it
+    pub fn slot(&self, row: usize, col: usize) -> usize {
+        (row - self.row) * self.shape.cols + (col - self.col)
+    }
+}
+
+// Covers the interior with blocks of the given shape, row by row from the top left. Where the interior doesn't divide
+// evenly the last band of rows or columns is made up of blocks one piece narrower. None if even that doesn't work.
+pub fn plan_blocks(puzzle: &Puzzle, shape: BlockShape) -> Option<Vec<BlockPlacement>> {
+    let row_bands = split_length(puzzle.height - 2, shape.rows)?;
+    let col_bands = split_length(puzzle.width - 2, shape.cols)?;
+    let mut plan = Vec::with_capacity(row_bands.len() * col_bands.len());
+    let mut row = 1;
+    for rows in row_bands.iter() {
+        let mut col = 1;
+        for cols in col_bands.iter() {
+            plan.push(BlockPlacement { row, col, shape: BlockShape { rows: *rows, cols: *cols } });
+            col += cols;
+        }
+        row += rows;
+    }
+    Some(plan)
+}
+
+// As many bands of size as will go, then size - 1 for the rest: 14 in threes is 3, 3, 3, 3, 2
+fn split_length(length: usize, size: usize) -> Option<Vec<usize>> {
+    if size == 0 || size > MAX_BLOCK_SIDE {
+        return None;
+    }
+    if size == 1 {
+        return Some(vec![1; length]);
+    }
+    (0..=length / size).rev().find_map(|full| {
+        let rest = length - full * size;
+        rest.is_multiple_of(size - 1).then(|| {
+            let mut bands = vec![size; full];
+            bands.extend(vec![size - 1; rest / (size - 1)]);
+            bands
+        })
+    })
+}
+
+// The blocks as a full size board with the rim left empty
+pub fn expand_blocks(plan: &[BlockPlacement], blocks: &[Block], puzzle: &Puzzle) -> Board {
+    let mut board = vec![vec![None; puzzle.width]; puzzle.height];
+    for (placement, block) in plan.iter().zip(blocks.iter()) {
+        for row in 0..block.shape.rows {
+            for col in 0..block.shape.cols {
+                board[placement.row + row][placement.col + col] = Some(*block.at(row, col));
+            }
+        }
+    }
+    board
+}
+
+// Every block of the shape that can be made from the available pieces starting from one of the seeds in the top left,
+// with any preset pieces in their slots. Stops after limit blocks, so shuffle the seeds to get a fair sample.
+// Also stops once work_left runs out, which goes down by one for every piece tried. Deep shapes can spend a long time
+// on dead ends without turning anything up, so the caller shares it out over a whole search.
+pub fn enumerate_blocks(shape: BlockShape,
+                        seeds: &[OrientedTile],
+                        preset: &[(usize, OrientedTile)],
+                        available: &PieceSet,
+                        tile_lookup: &TileLookup,
+                        limit: usize,
+                        work_left: &mut u64) -> Vec<Block> {
+    let mut enumeration = BlockEnumeration { shape, preset, available, tile_lookup, limit, work_left, found: Vec::new() };
+    let mut tiles = Vec::with_capacity(shape.cells());
+    for seed in seeds.iter() {
+        if enumeration.is_done() {
+            break;
+        }
+        if !available.contains(seed.id()) || preset.iter().any(|(slot, tile)| *slot == 0 && tile != seed) {
+            continue;
+        }
+        tiles.push(*seed);
+        enumerate_blocks_recursive(&mut enumeration, &mut tiles);
+        tiles.pop();
+    }
+    enumeration.found
+}
+
+// What stays the same all the way down one enumerate_blocks, and what it's found so far
+struct BlockEnumeration<'a> {
+    shape: BlockShape,
+    preset: &'a [(usize, OrientedTile)],
+    available: &'a PieceSet,
+    tile_lookup: &'a TileLookup,
+    limit: usize,
+    work_left: &'a mut u64,
+    found: Vec<Block>,
+}
+
+impl BlockEnumeration<'_> {
+    fn is_done(&self) -> bool {
+        self.found.len() >= self.limit || *self.work_left == 0
+    }
+}
+
+fn enumerate_blocks_recursive(enumeration: &mut BlockEnumeration, tiles: &mut Vec<OrientedTile>) {
+    let shape = enumeration.shape;
+    let slot = tiles.len();
+    if slot == shape.cells() {
+        enumeration.found.push(Block { shape, tiles: tiles.clone() });
+        return;
+    }
+    let (row, col) = (slot / shape.cols, slot % shape.cols);
+    let top = if row == 0 { ANY_SIDE } else { tiles[slot - shape.cols].bottom() };
+    let left = if col == 0 { ANY_SIDE } else { tiles[slot - 1].right() };
+
+    let preset_tile = enumeration.preset.iter().find(|(preset_slot, _)| *preset_slot == slot).map(|(_, tile)| *tile);
+    let preset_candidates;
+    let candidates: &[OrientedTile] = match preset_tile {
+        Some(tile) => {
+            preset_candidates = [tile];
+            &preset_candidates
+        }
+        None => enumeration.tile_lookup.get(top, ANY_SIDE, ANY_SIDE, left),
+    };
+    for tile in candidates.iter() {
+        if enumeration.is_done() {
+            return;
+        }
+        *enumeration.work_left -= 1;
+        let fits = (top == ANY_SIDE || tile.top() == top) && (left == ANY_SIDE || tile.left() == left);
+        let free = preset_tile.is_some() || (enumeration.available.contains(tile.id()) && tiles.iter().all(|placed| placed.id() != tile.id()));
+        if fits && free {
+            tiles.push(*tile);
+            enumerate_blocks_recursive(enumeration, tiles);
+            tiles.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{solved_puzzle, solution_mega_tiles};
+
+    #[test]
+    fn lengths_split_into_full_bands_then_narrower_ones() {
+        assert_eq!(split_length(14, 3), Some(vec![3, 3, 3, 3, 2]));
+        assert_eq!(split_length(14, 2), Some(vec![2; 7]));
+        assert_eq!(split_length(14, 4), Some(vec![4, 4, 3, 3]));
+        assert_eq!(split_length(14, 0), None);
+        assert_eq!(split_length(14, MAX_BLOCK_SIDE + 1), None);
+    }
+
+    #[test]
+    fn plans_cover_the_interior_once() {
+        let puzzle = Puzzle::eternity_ii();
+        for shape in [BlockShape { rows: 3, cols: 3 }, BlockShape { rows: 4, cols: 3 }, BlockShape::MEGA_TILE] {
+            let plan = plan_blocks(&puzzle, shape).unwrap();
+            let mut covered = vec![vec![0; puzzle.width]; puzzle.height];
+            for placement in plan.iter() {
+                for (row, counts) in covered.iter_mut().enumerate() {
+                    for (col, count) in counts.iter_mut().enumerate() {
+                        if placement.contains(row, col) {
+                            *count += 1;
+                        }
+                    }
+                }
+            }
+            for (row, counts) in covered.iter().enumerate() {
+                for (col, count) in counts.iter().enumerate() {
+                    let on_rim = row == 0 || col == 0 || row == puzzle.height - 1 || col == puzzle.width - 1;
+                    assert_eq!(*count, if on_rim { 0 } else { 1 }, "{:?} at row {} col {}", shape, row, col);
+                }
+            }
+        }
+    }
+
+    // Every interior piece in every turn, as seeds, and the lookup to go with them
+    fn interior_seeds(tiles: &[Tile], puzzle: &Puzzle) -> (Vec<OrientedTile>, TileLookup, PieceSet) {
+        let tile_lookup = build_indices(tiles, puzzle.border_piece_count());
+        let seeds = tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, ANY_SIDE).to_vec();
+        let available = (puzzle.border_piece_count()..puzzle.piece_count()).collect();
+        (seeds, tile_lookup, available)
+    }
+
+    #[test]
+    fn preset_slots_hold_their_pieces() {
+        let (tiles, puzzle, solution) = solved_puzzle(8, 8, 10, 3, 4);
+        let (seeds, tile_lookup, available) = interior_seeds(&tiles, &puzzle);
+        let shape = BlockShape { rows: 2, cols: 3 };
+        let preset = [(4, solution[2][2].unwrap())];
+        let mut work_left = u64::MAX;
+        let blocks = enumerate_blocks(shape, &seeds, &preset, &available, &tile_lookup, usize::MAX, &mut work_left);
+        assert!(blocks.iter().all(|block| block.tiles[4] == preset[0].1 && block.holds_together()));
+
+        // The solution's own block is among them
+        let in_solution = Block { shape, tiles: (1..3).flat_map(|row| (1..4).map(move |col| (row, col))).map(|(row, col)| solution[row][col].unwrap()).collect() };
+        assert!(blocks.contains(&in_solution));
+    }
+
+    #[test]
+    fn enumeration_stops_when_the_work_runs_out() {
+        let (tiles, puzzle, _) = solved_puzzle(8, 8, 10, 3, 4);
+        let (seeds, tile_lookup, available) = interior_seeds(&tiles, &puzzle);
+        let shape = BlockShape::MEGA_TILE;
+
+        let mut work_left = 0;
+        assert!(enumerate_blocks(shape, &seeds, &[], &available, &tile_lookup, usize::MAX, &mut work_left).is_empty());
+
+        let mut plenty = u64::MAX;
+        let everything = enumerate_blocks(shape, &seeds, &[], &available, &tile_lookup, usize::MAX, &mut plenty);
+        let needed = u64::MAX - plenty;
+        let mut work_left = needed / 2;
+        let some = enumerate_blocks(shape, &seeds, &[], &available, &tile_lookup, usize::MAX, &mut work_left);
+        assert_eq!(work_left, 0);
+        assert!(some.len() < everything.len());
+        assert_eq!(some[..], everything[..some.len()]);
+    }
+
+    #[test]
+    fn mega_tiles_keep_their_edges_as_blocks() {
+        let (_, puzzle, solution) = solved_puzzle(8, 8, 10, 3, 4);
+        for mega_tile in solution_mega_tiles(&solution, &puzzle) {
+            assert_eq!(Block::from(mega_tile).edges(), mega_tile.edges());
+        }
+    }
+}
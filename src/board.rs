@@ -0,0 +1,257 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::ser::{CompactFormatter, Formatter, PrettyFormatter};
+use crate::data::*;
+use crate::solver::Board;
+
+// Saving and loading boards, so a run's best board outlives the terminal it was printed to.
+// Only the piece id and rotation of each square are stored. Loading looks the sides back up in a tile set.
+//
+// Text, one line per row, '-' for an empty square. Lines starting with '#' are comments.
+//
+//   # 3x2 board, piece id/clockwise quarter turns
+//   0/1 4/1 1/2
+//   2/0 - 3/3
+//
+// JSON, the same grid with null for an empty square:
+//
+//   { "width": 3, "height": 2, "rows": [[{ "id": 0, "rotation": 1 }, ...], [...]] }
+
+// What a file says about one square before it's checked against a tile set
+type Cell = Option<(usize, Orientation)>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoardFileFormat {
+    Text,
+    Json,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoardFile {
+    width: usize,
+    height: usize,
+    rows: Vec<Vec<Option<PlacedPiece>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlacedPiece {
+    id: usize,
+    rotation: u8,
+}
+
+// .json files are JSON, anything else is text
+pub fn board_file_format(path: &Path) -> BoardFileFormat {
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json")) {
+        BoardFileFormat::Json
+    } else {
+        BoardFileFormat::Text
+    }
+}
+
+pub fn board_to_text(grid: &Board) -> String {
+    let mut text = format!("# {}x{} board, piece id/clockwise quarter turns\n", grid.first().map_or(0, |row| row.len()), grid.len());
+    for row in grid.iter() {
+        let cells: Vec<String> = row.iter().map(|tile| match tile {
+            Some(oriented_tile) => format!("{}/{}", oriented_tile.id(), oriented_tile.orientation().quarter_turns()),
+            None => String::from("-"),
+        }).collect();
+        text.push_str(&cells.join(" "));
+        text.push('\n');
+    }
+    text
+}
+
+pub fn board_to_json(grid: &Board) -> String {
+    let file = BoardFile {
+        width: grid.first().map_or(0, |row| row.len()),
+        height: grid.len(),
+        rows: grid.iter().map(|row| row.iter().map(|tile| tile.map(|oriented_tile| PlacedPiece {
+            id: oriented_tile.id(),
+            rotation: oriented_tile.orientation().quarter_turns(),
+        })).collect()).collect(),
+    };
+    let mut json = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut json, RowPerLine { pretty: PrettyFormatter::new(), depth: 0 });
+    file.serialize(&mut serializer).expect("a board always serialises");
+    json.push(b'\n');
+    String::from_utf8(json).expect("serde_json writes UTF-8")
+}
+
+// Pretty printing the whole file puts every square on lines of its own, so everything inside a row is written compactly
+// and a 16x16 board stays 16 lines of squares
+struct RowPerLine {
+    pretty: PrettyFormatter<'static>,
+    depth: usize, // The file's object is 1, rows 2, and a row 3
+}
+
+impl RowPerLine {
+    fn in_row(&self) -> bool {
+        self.depth > 2
+    }
+}
+
+impl Formatter for RowPerLine {
+    fn begin_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.depth += 1;
+        if self.in_row() { CompactFormatter.begin_array(writer) } else { self.pretty.begin_array(writer) }
+    }
+
+    fn end_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        let result = if self.in_row() { CompactFormatter.end_array(writer) } else { self.pretty.end_array(writer) };
+        self.depth -= 1;
+        result
+    }
+
+    fn begin_array_value<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if self.in_row() { CompactFormatter.begin_array_value(writer, first) } else { self.pretty.begin_array_value(writer, first) }
+    }
+
+    fn end_array_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.in_row() { CompactFormatter.end_array_value(writer) } else { self.pretty.end_array_value(writer) }
+    }
+
+    fn begin_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.depth += 1;
+        if self.in_row() { CompactFormatter.begin_object(writer) } else { self.pretty.begin_object(writer) }
+    }
+
+    fn end_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        let result = if self.in_row() { CompactFormatter.end_object(writer) } else { self.pretty.end_object(writer) };
+        self.depth -= 1;
+        result
+    }
+
+    fn begin_object_key<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if self.in_row() { CompactFormatter.begin_object_key(writer, first) } else { self.pretty.begin_object_key(writer, first) }
+    }
+
+    fn begin_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.in_row() { CompactFormatter.begin_object_value(writer) } else { self.pretty.begin_object_value(writer) }
+    }
+
+    fn end_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.in_row() { CompactFormatter.end_object_value(writer) } else { self.pretty.end_object_value(writer) }
+    }
+}
+
+pub fn save_board(grid: &Board, path: &Path) -> Result<(), String> {
+    let contents = match board_file_format(path) {
+        BoardFileFormat::Text => board_to_text(grid),
+        BoardFileFormat::Json => board_to_json(grid),
+    };
+    fs::write(path, contents).map_err(|e| format!("could not write board {}: {}", path.display(), e))
+}
+
+pub fn load_board(path: &Path, tiles: &[Tile]) -> Result<Board, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("could not read board {}: {}", path.display(), e))?;
+    parse_board(&contents, board_file_format(path), tiles).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn parse_board(contents: &str, format: BoardFileFormat, tiles: &[Tile]) -> Result<Board, String> {
+    let cells = match format {
+        BoardFileFormat::Text => parse_text_cells(contents)?,
+        BoardFileFormat::Json => parse_json_cells(contents)?,
+    };
+    if cells.is_empty() || cells[0].is_empty() {
+        return Err(String::from("the board is empty"));
+    }
+    if let Some(row) = cells.iter().position(|row| row.len() != cells[0].len()) {
+        return Err(format!("row {} has {} squares, the first row has {}", row, cells[row].len(), cells[0].len()));
+    }
+
+    let mut grid = vec![vec![None; cells[0].len()]; cells.len()];
+    for (row, row_cells) in cells.iter().enumerate() {
+        for (col, cell) in row_cells.iter().enumerate() {
+            if let Some((id, orientation)) = cell {
+                if *id >= tiles.len() {
+                    return Err(format!("row {} col {} has piece {}, but the tile set only has {}", row, col, id, tiles.len()));
+                }
+                grid[row][col] = Some(OrientedTile::new(*id, tiles, *orientation));
+            }
+        }
+    }
+    Ok(grid)
+}
+
+fn parse_text_cells(contents: &str) -> Result<Vec<Vec<Cell>>, String> {
+    let mut cells = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let row: Option<Vec<Cell>> = line.split_whitespace().map(|cell| match cell {
+            "-" => Some(None),
+            _ => parse_piece(cell).map(Some),
+        }).collect();
+        cells.push(row.ok_or_else(|| format!("line {}: can't read '{}'", i + 1, line))?);
+    }
+    Ok(cells)
+}
+
+fn parse_json_cells(contents: &str) -> Result<Vec<Vec<Cell>>, String> {
+    let file: BoardFile = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    if file.rows.len() != file.height || file.rows.iter().any(|row| row.len() != file.width) {
+        return Err(format!("rows don't add up to a {}x{} board", file.width, file.height));
+    }
+    file.rows.iter().map(|row| row.iter().map(|cell| match cell {
+        Some(piece) => Orientation::from_quarter_turns(piece.rotation)
+            .map(|orientation| Some((piece.id, orientation)))
+            .ok_or_else(|| format!("piece {} has rotation {}, expected 0 to 3", piece.id, piece.rotation)),
+        None => Ok(None),
+    }).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::solved_puzzle;
+
+    // The solution with a few squares emptied
+    fn sample_board() -> (Vec<Tile>, Board) {
+        let (tiles, _, mut board) = solved_puzzle(6, 5, 12, 3, 5);
+        board[0][0] = None;
+        board[2][3] = None;
+        board[4][5] = None;
+        (tiles, board)
+    }
+
+    #[test]
+    fn text_boards_read_back_the_same() {
+        let (tiles, board) = sample_board();
+        let text = board_to_text(&board);
+        assert_eq!(text.lines().count(), 1 + board.len());
+        assert_eq!(parse_board(&text, BoardFileFormat::Text, &tiles), Ok(board));
+    }
+
+    #[test]
+    fn json_boards_read_back_the_same() {
+        let (tiles, board) = sample_board();
+        let json = board_to_json(&board);
+        assert_eq!(json.lines().count(), 6 + board.len(), "{}", json); // Braces, width, height, and the rows brackets
+        assert!(json.contains("\n    [null,{\"id\":"), "{}", json);
+        assert_eq!(parse_board(&json, BoardFileFormat::Json, &tiles), Ok(board));
+    }
+
+    #[test]
+    fn json_sizes_have_to_match_the_rows() {
+        let (tiles, board) = sample_board();
+        let json = board_to_json(&board);
+        for (from, to) in [("\"width\": 6", "\"width\": 5"), ("\"height\": 5", "\"height\": 6")] {
+            let message = parse_board(&json.replace(from, to), BoardFileFormat::Json, &tiles).unwrap_err();
+            assert!(message.contains("rows don't add up"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn text_rows_have_to_be_the_same_length() {
+        let (tiles, board) = sample_board();
+        let mut text = board_to_text(&board);
+        text.push_str("0/0\n");
+        assert_eq!(parse_board(&text, BoardFileFormat::Text, &tiles), Err(String::from("row 5 has 1 squares, the first row has 6")));
+    }
+}
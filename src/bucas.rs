@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::data::*;
+use crate::solver::Board;
+
+// Boards in the form the online viewer at e2.bucas.name shares them, so they can be swapped with the rest of the community.
+// The board_edges parameter has four letters per square, top right bottom left, row by row from the top left.
+// 'a' is grey and the colours follow on from 'b', i.e. the same letters colour_symbol prints shifted up one and lower case.
+// An empty square is written as "aaaa", which no real piece can be.
+
+pub const BUCAS_VIEWER_URL: &str = "https://e2.bucas.name/";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BucasImportError {
+    MissingEdges,
+    BadSize(String),
+    WrongLength { expected: usize, found: usize },
+    BadLetter { row: usize, col: usize, letter: char },
+    UnmatchedSquare { row: usize, col: usize, edges: String },
+}
+
+impl fmt::Display for BucasImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BucasImportError::MissingEdges => write!(f, "no board_edges in the url"),
+            BucasImportError::BadSize(value) => write!(f, "bad board size '{}'", value),
+            BucasImportError::WrongLength { expected, found } => write!(f, "expected {} edge letters, found {}", expected, found),
+            BucasImportError::BadLetter { row, col, letter } => write!(f, "row {} col {}: '{}' isn't an edge letter", row, col, letter),
+            BucasImportError::UnmatchedSquare { row, col, edges } => write!(f, "row {} col {}: no piece has the edges {}", row, col, edges),
+        }
+    }
+}
+
+fn side_letter(side: i8) -> char {
+    (side + 1 + b'a' as i8) as u8 as char
+}
+
+fn letter_side(letter: char) -> Option<i8> {
+    if letter.is_ascii_lowercase() { Some(letter as i8 - b'a' as i8 - 1) } else { None }
+}
+
+pub fn board_to_bucas_edges(grid: &Board) -> String {
+    let mut edges = String::new();
+    for oriented_tile in grid.iter().flatten() {
+        match oriented_tile {
+            Some(oriented_tile) => edges.extend(oriented_tile.sides().iter().map(|side| side_letter(*side))),
+            None => edges.push_str("aaaa"),
+        }
+    }
+    edges
+}
+
+pub fn board_to_bucas_url(grid: &Board) -> String {
+    let width = grid.first().map_or(0, |row| row.len());
+    format!("{}#board_w={}&board_h={}&board_edges={}", BUCAS_VIEWER_URL, width, grid.len(), board_to_bucas_edges(grid))
+}
+
+// Takes a whole url, or just the query part, or just the edge letters of a square board
+pub fn parse_bucas_board(text: &str, tiles: &[Tile]) -> Result<Board, Vec<BucasImportError>> {
+    let text = text.trim();
+    let query = text.rsplit(['#', '?']).next().unwrap_or(text);
+    let parameters: HashMap<&str, &str> = query.split('&').filter_map(|parameter| parameter.split_once('=')).collect();
+    let edges = match parameters.get("board_edges") {
+        Some(edges) => *edges,
+        None if !query.contains('=') => query,
+        None => return Err(vec![BucasImportError::MissingEdges]),
+    };
+
+    // The size is optional, without it the board has to be square
+    let square_size = (1..=edges.len() / 4).find(|size| size * size * 4 >= edges.len()).unwrap_or(0);
+    let size_parameter = |name: &str| -> Result<usize, Vec<BucasImportError>> {
+        match parameters.get(name) {
+            Some(value) => value.parse().ok().filter(|size| *size > 0).ok_or_else(|| vec![BucasImportError::BadSize(value.to_string())]),
+            None => Ok(square_size),
+        }
+    };
+    let width = size_parameter("board_w")?;
+    let height = size_parameter("board_h")?;
+    let letters: Vec<char> = edges.chars().collect();
+    if width == 0 || letters.len() != width * height * 4 {
+        return Err(vec![BucasImportError::WrongLength { expected: width * height * 4, found: letters.len() }]);
+    }
+
+    // Every rotation of every piece, keyed by its sides. Symmetric pieces keep their first rotation.
+    let mut pieces: HashMap<[i8; 4], OrientedTile> = HashMap::new();
+    for tile in tiles.iter() {
+        for orientation in Orientation::ALL {
+            let oriented_tile = OrientedTile::new(tile.id, tiles, orientation);
+            pieces.entry(oriented_tile.sides()).or_insert(oriented_tile);
+        }
+    }
+
+    // Every square is checked so that all the problems get reported in one go
+    let mut errors = Vec::new();
+    let mut grid = vec![vec![None; width]; height];
+    for (i, square) in letters.chunks(4).enumerate() {
+        let (row, col) = (i / width, i % width);
+        let mut sides = [0; 4];
+        for (side, letter) in sides.iter_mut().zip(square.iter()) {
+            match letter_side(*letter) {
+                Some(value) => *side = value,
+                None => {
+                    errors.push(BucasImportError::BadLetter { row, col, letter: *letter });
+                    *side = ANY_SIDE;
+                }
+            }
+        }
+        if sides.contains(&ANY_SIDE) || sides == [-1; 4] {
+            continue; // Empty, or already reported
+        }
+        match pieces.get(&sides) {
+            Some(oriented_tile) => grid[row][col] = Some(*oriented_tile),
+            None => errors.push(BucasImportError::UnmatchedSquare { row, col, edges: square.iter().collect() }),
+        }
+    }
+    if errors.is_empty() { Ok(grid) } else { Err(errors) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::solved_puzzle;
+
+    // Piece and sides of every square. Symmetric pieces can come back in another rotation that looks the same.
+    fn squares(grid: &Board) -> Vec<Option<(usize, [i8; 4])>> {
+        grid.iter().flatten().map(|tile| tile.map(|oriented_tile| (oriented_tile.id(), oriented_tile.sides()))).collect()
+    }
+
+    #[test]
+    fn exported_boards_import_unchanged() {
+        let (tiles, _, mut board) = solved_puzzle(6, 5, 12, 3, 5);
+        board[1][1] = None;
+        board[4][0] = None;
+        let url = board_to_bucas_url(&board);
+        assert!(url.starts_with(BUCAS_VIEWER_URL) && url.contains("board_w=6&board_h=5"), "{}", url);
+        let imported = parse_bucas_board(&url, &tiles).unwrap();
+        assert_eq!((imported[0].len(), imported.len()), (6, 5));
+        assert_eq!(squares(&imported), squares(&board));
+    }
+
+    #[test]
+    fn every_bad_square_is_reported() {
+        let (tiles, _, board) = solved_puzzle(6, 5, 12, 3, 5);
+        let mut letters: Vec<char> = board_to_bucas_edges(&board).chars().collect();
+        letters[4] = 'Z';                   // Row 0 col 1
+        letters[(2 * 6 + 2) * 4] = 'z';     // Row 2 col 2, a colour no piece has
+        letters[(4 * 6 + 5) * 4 + 3] = '9'; // Row 4 col 5
+        let url = format!("{}#board_w=6&board_h=5&board_edges={}", BUCAS_VIEWER_URL, letters.iter().collect::<String>());
+        let edges: String = letters[(2 * 6 + 2) * 4..(2 * 6 + 3) * 4].iter().collect();
+        assert_eq!(parse_bucas_board(&url, &tiles), Err(vec![
+            BucasImportError::BadLetter { row: 0, col: 1, letter: 'Z' },
+            BucasImportError::UnmatchedSquare { row: 2, col: 2, edges },
+            BucasImportError::BadLetter { row: 4, col: 5, letter: '9' },
+        ]));
+    }
+}
@@ -0,0 +1,454 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::data::*;
+
+// Periodic snapshots of a long search so it can carry on after the process dies.
+// A checkpoint is the path from the root of the search tree down to the node being worked on. For every level it keeps
+// which candidate was being tried, where the rng was when that level's candidate list was drawn up, and the pieces that
+// candidate put down. Resuming walks the same path: each level rebuilds its candidate list with the rng rewound to the
+// same spot, jumps straight to the saved candidate and carries on from there, so the run continues exactly as before.
+//
+// The file is plain text, one "key values..." line per field:
+//
+//   version 1                                see CHECKPOINT_VERSION
+//   kind megatiles 16 16 22 5                width, height, colours and border colours of the puzzle
+//   fixed 138 7 8 1                          megatiles only, one line per fixed piece: id, row, col and quarter turns
+//   kind bruteforce 7 24/0                   or the grid size and centre piece/quarter turns for brute_force
+//   tiles 256 9230834402315432617            piece count and a checksum of the tile set
+//   seed 1234
+//   rng 5678                                 position in the rng stream when the checkpoint was written
+//   nodes 1000
+//   frame 3 5120 138/1 12/0 45/2 99/3        candidate index, rng position, then the pieces placed at this level
+
+pub const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Goes up whenever a search changes in a way that means an older checkpoint wouldn't replay, e.g. candidates coming out
+// in a different order
+const CHECKPOINT_VERSION: u32 = 1;
+
+// Hot searches only look at the clock once every this many nodes
+const CLOCK_CHECK_NODES: u64 = 65536;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchKind {
+    MegaTiles { puzzle: Puzzle, fixed_pieces: Vec<FixedPiece> },
+    BruteForce { grid_size: usize, centre: (usize, Orientation) },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckpointFrame {
+    pub candidate_index: usize,
+    pub rng_position: u128,
+    pub pieces: Vec<(usize, Orientation)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub kind: SearchKind,
+    pub tile_count: usize,
+    pub tile_checksum: u64,
+    pub seed: u64,
+    pub rng_position: u128,
+    pub nodes: u64,
+    pub frames: Vec<CheckpointFrame>,
+}
+
+impl Checkpoint {
+    pub fn new(kind: SearchKind, tiles: &[Tile], seed: u64) -> Checkpoint {
+        Checkpoint {
+            kind,
+            tile_count: tiles.len(),
+            tile_checksum: tile_set_checksum(tiles),
+            seed,
+            rng_position: 0,
+            nodes: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let piece = |(id, orientation): &(usize, Orientation)| format!("{}/{}", id, orientation.quarter_turns());
+        let mut text = format!("version {}\n", CHECKPOINT_VERSION);
+        match &self.kind {
+            SearchKind::MegaTiles { puzzle, fixed_pieces } => {
+                text.push_str(&format!("kind megatiles {} {} {} {}\n", puzzle.width, puzzle.height, puzzle.colour_count, puzzle.border_colour_count));
+                for fixed in fixed_pieces.iter() {
+                    text.push_str(&format!("fixed {} {} {} {}\n", fixed.id, fixed.row, fixed.col, fixed.orientation.quarter_turns()));
+                }
+            }
+            SearchKind::BruteForce { grid_size, centre } => text.push_str(&format!("kind bruteforce {} {}\n", grid_size, piece(centre))),
+        }
+        text.push_str(&format!("tiles {} {}\n", self.tile_count, self.tile_checksum));
+        text.push_str(&format!("seed {}\n", self.seed));
+        text.push_str(&format!("rng {}\n", self.rng_position));
+        text.push_str(&format!("nodes {}\n", self.nodes));
+        for frame in self.frames.iter() {
+            let pieces: Vec<String> = frame.pieces.iter().map(piece).collect();
+            text.push_str(&format!("frame {} {} {}\n", frame.candidate_index, frame.rng_position, pieces.join(" ")));
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Checkpoint, String> {
+        let mut version = None;
+        let mut kind = None;
+        let mut tiles = None;
+        let mut seed = None;
+        let mut rng_position = None;
+        let mut nodes = None;
+        let mut frames = Vec::new();
+        let mut fixed_pieces = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let Some((key, values)) = fields.split_first() else { continue };
+            let bad_line = || format!("line {}: can't read '{}'", i + 1, line);
+            match (*key, values) {
+                ("version", [value]) => version = Some(value.parse::<u32>().map_err(|_| bad_line())?),
+                ("kind", ["megatiles", width, height, colours, border_colours]) => {
+                    let numbers: Result<Vec<usize>, _> = [width, height, colours, border_colours].iter().map(|n| n.parse()).collect();
+                    let numbers = numbers.map_err(|_| bad_line())?;
+                    let puzzle = Puzzle { width: numbers[0], height: numbers[1], colour_count: numbers[2], border_colour_count: numbers[3] };
+                    kind = Some(SearchKind::MegaTiles { puzzle, fixed_pieces: Vec::new() });
+                }
+                ("kind", ["bruteforce", grid_size, centre]) => {
+                    let grid_size = grid_size.parse().map_err(|_| bad_line())?;
+                    kind = Some(SearchKind::BruteForce { grid_size, centre: parse_piece(centre).ok_or_else(bad_line)? });
+                }
+                ("fixed", [id, row, col, turns]) => {
+                    fixed_pieces.push(FixedPiece {
+                        id: id.parse().map_err(|_| bad_line())?,
+                        row: row.parse().map_err(|_| bad_line())?,
+                        col: col.parse().map_err(|_| bad_line())?,
+                        orientation: turns.parse().ok().and_then(Orientation::from_quarter_turns).ok_or_else(bad_line)?,
+                    });
+                }
+                ("tiles", [count, checksum]) => tiles = Some((count.parse().map_err(|_| bad_line())?, checksum.parse().map_err(|_| bad_line())?)),
+                ("seed", [value]) => seed = Some(value.parse().map_err(|_| bad_line())?),
+                ("rng", [value]) => rng_position = Some(value.parse().map_err(|_| bad_line())?),
+                ("nodes", [value]) => nodes = Some(value.parse().map_err(|_| bad_line())?),
+                ("frame", [candidate_index, rng_position, pieces @ ..]) => {
+                    let pieces: Option<Vec<(usize, Orientation)>> = pieces.iter().map(|p| parse_piece(p)).collect();
+                    frames.push(CheckpointFrame {
+                        candidate_index: candidate_index.parse().map_err(|_| bad_line())?,
+                        rng_position: rng_position.parse().map_err(|_| bad_line())?,
+                        pieces: pieces.ok_or_else(bad_line)?,
+                    });
+                }
+                _ => return Err(bad_line()),
+            }
+        }
+
+        let missing = |field: &str| format!("checkpoint has no '{}' line", field);
+        match version {
+            Some(CHECKPOINT_VERSION) => {}
+            Some(version) => return Err(format!("checkpoint is version {}, this build only resumes version {}", version, CHECKPOINT_VERSION)),
+            None => return Err(missing("version")),
+        }
+        let (tile_count, tile_checksum) = tiles.ok_or_else(|| missing("tiles"))?;
+        let kind = match kind.ok_or_else(|| missing("kind"))? {
+            SearchKind::MegaTiles { puzzle, .. } => SearchKind::MegaTiles { puzzle, fixed_pieces },
+            SearchKind::BruteForce { .. } if !fixed_pieces.is_empty() => return Err(String::from("brute force checkpoints don't have fixed pieces")),
+            kind => kind,
+        };
+        Ok(Checkpoint {
+            kind,
+            tile_count,
+            tile_checksum,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            rng_position: rng_position.ok_or_else(|| missing("rng"))?,
+            nodes: nodes.ok_or_else(|| missing("nodes"))?,
+            frames,
+        })
+    }
+
+    // Written next to the real file and then renamed over it, so dying halfway through a save can't leave a broken checkpoint
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, self.to_text())
+            .and_then(|_| fs::rename(&temporary_path, path))
+            .map_err(|e| format!("could not write checkpoint {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read checkpoint {}: {}", path.display(), e))?;
+        Checkpoint::from_text(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Resuming against a different tile set would quietly search the wrong tree
+    pub fn matches_tiles(&self, tiles: &[Tile]) -> bool {
+        self.tile_count == tiles.len() && self.tile_checksum == tile_set_checksum(tiles)
+    }
+}
+
+// FNV-1a over every side of every piece, in order
+pub fn tile_set_checksum(tiles: &[Tile]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for tile in tiles.iter() {
+        for side in [tile.top, tile.right, tile.bottom, tile.left] {
+            hash ^= side as u8 as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// Follows a search as it runs, writing the current path out every so often and feeding a saved path back in on resume.
+// Either the search tells it which candidate it's trying at each depth with set_frame and calls arrive on entering a node,
+// or it keeps track of its own path and hands over the frames with save_frames whenever save_due says so.
+pub struct Checkpointer {
+    file: Option<(PathBuf, Checkpoint)>, // None when checkpoints are switched off, everything's a no-op then
+    interval: Duration,
+    last_saved: Instant,
+    resuming: bool,
+    error: Option<String>, // Set when the search has to stop, the checkpoint file is left as it was
+    saves_left: Option<usize>,
+}
+
+impl Checkpointer {
+    pub fn disabled() -> Checkpointer {
+        Checkpointer { file: None, interval: DEFAULT_CHECKPOINT_INTERVAL, last_saved: Instant::now(), resuming: false, error: None, saves_left: None }
+    }
+
+    // Start a fresh search that saves to path every interval
+    pub fn new(path: &Path, interval: Duration, checkpoint: Checkpoint) -> Checkpointer {
+        Checkpointer { file: Some((path.to_path_buf(), checkpoint)), interval, ..Checkpointer::disabled() }
+    }
+
+    // Pick up a saved search. Later checkpoints go back to the same file.
+    pub fn resume(path: &Path, interval: Duration, checkpoint: Checkpoint) -> Checkpointer {
+        Checkpointer { resuming: true, ..Checkpointer::new(path, interval, checkpoint) }
+    }
+
+    // None when checkpoints are switched off
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.file.as_ref().map(|(_, checkpoint)| checkpoint)
+    }
+
+    // Nodes already searched before the checkpoint being resumed, 0 for a fresh search
+    pub fn nodes_so_far(&self) -> u64 {
+        self.checkpoint().map_or(0, |checkpoint| checkpoint.nodes)
+    }
+
+    pub fn is_resuming(&self) -> bool {
+        self.resuming
+    }
+
+    // The frame to replay at this depth, if we're still walking back down a saved path
+    pub fn resume_frame(&self, depth: usize) -> Option<&CheckpointFrame> {
+        if self.resuming { self.checkpoint()?.frames.get(depth) } else { None }
+    }
+
+    // Once the replay reaches the end of the saved path the search is back where it stopped.
+    // Returns the rng position to carry on from.
+    pub fn finish_resume(&mut self, depth: usize) -> Option<u128> {
+        let checkpoint = self.checkpoint()?;
+        if !self.resuming || depth != checkpoint.frames.len() {
+            return None;
+        }
+        println!("Resumed at depth {} after {} nodes", depth, checkpoint.nodes);
+        let rng_position = checkpoint.rng_position;
+        self.resuming = false;
+        Some(rng_position)
+    }
+
+    // A resume that doesn't line up with the search, e.g. because the code changed underneath it, can't carry on from
+    // anywhere else without skipping whatever the saved path led to. The search unwinds and the file stays as it was.
+    pub fn fail_resume(&mut self, depth: usize) {
+        self.error = Some(format!("checkpoint doesn't match the search at depth {}, can't resume", depth));
+    }
+
+    // The search should unwind without trying anything else
+    pub fn stopped(&self) -> bool {
+        self.error.is_some() || self.saves_left == Some(0)
+    }
+
+    // Whether the search stopped because it couldn't resume, checked once it's returned
+    pub fn result(&self) -> Result<(), String> {
+        self.error.clone().map_or(Ok(()), Err)
+    }
+
+    // Stop the search once it's saved this many times, as if the process had died straight after
+    #[cfg(test)]
+    pub fn stop_after_saves(&mut self, saves: usize) {
+        self.saves_left = Some(saves);
+    }
+
+    pub fn save_due(&self) -> bool {
+        self.file.is_some() && !self.resuming && !self.stopped() && self.last_saved.elapsed() >= self.interval
+    }
+
+    // save_due for hot searches, which only look at the clock every so often
+    pub fn save_due_after(&self, nodes: u64) -> bool {
+        (self.interval.is_zero() || nodes.is_multiple_of(CLOCK_CHECK_NODES)) && self.save_due()
+    }
+
+    // For searches that report every candidate they try. Anything deeper belongs to the previous candidate and is dropped.
+    pub fn set_frame(&mut self, depth: usize, frame: CheckpointFrame) {
+        if let (Some((_, checkpoint)), false) = (&mut self.file, self.resuming) {
+            checkpoint.frames.truncate(depth);
+            checkpoint.frames.push(frame);
+        }
+    }
+
+    // Call on entering a node. Finishes off a resume once the saved path runs out, otherwise saves if it's time.
+    pub fn arrive(&mut self, depth: usize, nodes: u64, rng: &mut SearchRng) {
+        if let Some(rng_position) = self.finish_resume(depth) {
+            rng.set_word_pos(rng_position);
+        } else if self.save_due() {
+            if let Some((_, checkpoint)) = &mut self.file {
+                checkpoint.frames.truncate(depth);
+            }
+            self.save(nodes, rng.get_word_pos());
+        }
+    }
+
+    // For hot searches that keep their own path and only build frames when a save is due
+    pub fn save_frames(&mut self, frames: Vec<CheckpointFrame>, nodes: u64, rng_position: u128) {
+        if let Some((_, checkpoint)) = &mut self.file {
+            checkpoint.frames = frames;
+        }
+        self.save(nodes, rng_position);
+    }
+
+    fn save(&mut self, nodes: u64, rng_position: u128) {
+        if let Some((path, checkpoint)) = &mut self.file {
+            checkpoint.nodes = nodes;
+            checkpoint.rng_position = rng_position;
+            match checkpoint.save(path) {
+                Ok(()) => println!("Saved checkpoint to {} at depth {} after {} nodes", path.display(), checkpoint.frames.len(), checkpoint.nodes),
+                Err(e) => println!("{}", e),
+            }
+        }
+        self.last_saved = Instant::now();
+        if let Some(saves_left) = &mut self.saves_left {
+            *saves_left = saves_left.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use rand::SeedableRng;
+    use super::*;
+    use crate::dashboard::Dashboard;
+    use crate::mega::{generate_initial_mega_tiles, StepOneRun};
+    use crate::solver::SearchStats;
+    use crate::testing::solved_puzzle;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("eternity-{}-{}.checkpoint", name, std::process::id()))
+    }
+
+    fn sample_checkpoint() -> Checkpoint {
+        let kind = SearchKind::MegaTiles { puzzle: Puzzle::eternity_ii(), fixed_pieces: vec![MANDATORY_PIECE] };
+        let mut checkpoint = Checkpoint::new(kind, &TILE_SET, 1234);
+        checkpoint.rng_position = 5678;
+        checkpoint.nodes = 1000;
+        checkpoint.frames = vec![
+            CheckpointFrame { candidate_index: 0, rng_position: 12, pieces: vec![(138, Orientation::Deg90), (12, Orientation::Deg0), (45, Orientation::Deg180), (99, Orientation::Deg270)] },
+            CheckpointFrame { candidate_index: 3, rng_position: 5120, pieces: vec![(60, Orientation::Deg0), (61, Orientation::Deg90), (62, Orientation::Deg0), (63, Orientation::Deg90)] },
+        ];
+        checkpoint
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let checkpoint = sample_checkpoint();
+        assert_eq!(Checkpoint::from_text(&checkpoint.to_text()), Ok(checkpoint.clone()));
+
+        let brute_force = Checkpoint::new(SearchKind::BruteForce { grid_size: 7, centre: (24, Orientation::Deg0) }, &MINI_TILE_SET, 7);
+        assert_eq!(Checkpoint::from_text(&brute_force.to_text()), Ok(brute_force));
+
+        let path = temp_path("round-trip");
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path), Ok(checkpoint));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let text = sample_checkpoint().to_text();
+        let unversioned: String = text.lines().skip(1).map(|line| format!("{}\n", line)).collect();
+        assert!(Checkpoint::from_text(&unversioned).is_err());
+        assert!(Checkpoint::from_text(&text.replace("version 1", "version 2")).is_err());
+    }
+
+    #[test]
+    fn brute_force_resumes_where_it_stopped() {
+        let centre = OrientedTile::new(MINI_TILE_SET.len() / 2, &MINI_TILE_SET, Orientation::Deg0);
+        let kind = SearchKind::BruteForce { grid_size: 7, centre: (centre.id(), centre.orientation()) };
+        let (solution, nodes) = crate::brute_force(7, &MINI_TILE_SET, centre, &mut Checkpointer::disabled());
+        assert!(solution.is_some());
+
+        // Saving after every piece, stop halfway
+        let path = temp_path("brute-force");
+        let mut checkpointer = Checkpointer::new(&path, Duration::ZERO, Checkpoint::new(kind, &MINI_TILE_SET, 0));
+        checkpointer.stop_after_saves(nodes as usize / 2);
+        assert_eq!(crate::brute_force(7, &MINI_TILE_SET, centre, &mut checkpointer).0, None);
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.nodes, nodes / 2);
+        let mut checkpointer = Checkpointer::resume(&path, Duration::ZERO, checkpoint);
+        let (resumed, resumed_nodes) = crate::brute_force(7, &MINI_TILE_SET, centre, &mut checkpointer);
+        assert_eq!(checkpointer.result(), Ok(()));
+        assert_eq!(resumed, solution);
+        assert_eq!(resumed_nodes, nodes);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn build_mega_tiles(tiles: &[Tile], puzzle: &Puzzle, checkpointer: &mut Checkpointer) -> (Vec<Vec<(usize, Orientation)>>, u64) {
+        let mut rng = SearchRng::seed_from_u64(checkpointer.checkpoint().unwrap().seed);
+        let mut stats = SearchStats { nodes: checkpointer.nodes_so_far(), deepest: 0 };
+        let mut run = StepOneRun { rng: &mut rng, cancel: &AtomicBool::new(false), stats: &mut stats, checkpointer, dashboard: &mut Dashboard::disabled() };
+        let mega_tiles = generate_initial_mega_tiles(tiles, puzzle, &[], None, &mut run);
+        let pieces = mega_tiles.iter().map(|mega_tile| mega_tile.tiles.iter().map(|tile| (tile.id(), tile.orientation())).collect()).collect();
+        (pieces, stats.nodes)
+    }
+
+    #[test]
+    fn mega_tiles_resume_where_they_stopped() {
+        let (tiles, puzzle, _) = solved_puzzle(8, 8, 10, 3, 1);
+        let checkpoint = Checkpoint::new(SearchKind::MegaTiles { puzzle, fixed_pieces: Vec::new() }, &tiles, 42);
+        let path = temp_path("mega-tiles");
+        let (mega_tiles, nodes) = build_mega_tiles(&tiles, &puzzle, &mut Checkpointer::new(&path, Duration::from_secs(3600), checkpoint.clone()));
+        assert_eq!(mega_tiles.len(), puzzle.mega_tile_count());
+
+        // A save on entering every node that passes the health checks, 13 of them for this puzzle. Stop about halfway.
+        let mut checkpointer = Checkpointer::new(&path, Duration::ZERO, checkpoint);
+        checkpointer.stop_after_saves(7);
+        assert!(build_mega_tiles(&tiles, &puzzle, &mut checkpointer).0.is_empty());
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert!(checkpoint.nodes > 0 && checkpoint.nodes < nodes);
+        let mut checkpointer = Checkpointer::resume(&path, Duration::ZERO, checkpoint);
+        assert_eq!(build_mega_tiles(&tiles, &puzzle, &mut checkpointer), (mega_tiles, nodes));
+        assert_eq!(checkpointer.result(), Ok(()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mismatched_resume_stops_and_keeps_the_file() {
+        let centre = OrientedTile::new(MINI_TILE_SET.len() / 2, &MINI_TILE_SET, Orientation::Deg0);
+        let kind = SearchKind::BruteForce { grid_size: 7, centre: (centre.id(), centre.orientation()) };
+        let path = temp_path("mismatch");
+        let mut checkpointer = Checkpointer::new(&path, Duration::ZERO, Checkpoint::new(kind, &MINI_TILE_SET, 0));
+        checkpointer.stop_after_saves(20);
+        crate::brute_force(7, &MINI_TILE_SET, centre, &mut checkpointer);
+
+        // Point the deepest frame at a piece the search wouldn't have put there
+        let mut checkpoint = Checkpoint::load(&path).unwrap();
+        let frame = checkpoint.frames.last_mut().unwrap();
+        frame.pieces[0].1 = Orientation::from_quarter_turns((frame.pieces[0].1.quarter_turns() + 1) % 4).unwrap();
+        checkpoint.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+
+        let mut checkpointer = Checkpointer::resume(&path, Duration::ZERO, checkpoint);
+        assert_eq!(crate::brute_force(7, &MINI_TILE_SET, centre, &mut checkpointer).0, None);
+        assert!(checkpointer.result().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+        fs::remove_file(&path).unwrap();
+    }
+}
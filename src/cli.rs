@@ -0,0 +1,309 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::block::{BlockShape, MAX_BLOCK_SIDE};
+use crate::data::*;
+use crate::display::{BoardFormat, ColourChoice};
+
+// Command line handling, so experiments don't need main.rs edited and rebuilt.
+// Parsed by hand, there aren't enough flags to be worth pulling in a crate for it.
+
+pub const USAGE: &str = "\
+Usage: eternity <command> [flags]
+
+Commands:
+  solve            Search for a full board
+  megatiles        Build a set of 2x2 mega-tiles covering the interior, fit the rim round them and arrange them
+  stats            Print colour counts and the tile set report
+  megadb <file>    Work out every 2x2 mega-tile the interior pieces can make, save them to file for --mega-db and
+                   print how many there are per edge
+  render           Print a saved board, or the tile set laid out in order if there isn't one
+  verify           Check the tile set for problems, or with --board check a saved board against it
+  resume <file>    Carry on a search from a checkpoint. --resume <file> does the same, with or without the command
+  import <url>     Read a board from an e2.bucas.name link, or a file holding one
+  help             Show this message
+
+Flags:
+  --tiles <file>         Tile file to load, or e2 / mini for the built in sets (default e2)
+  --size <W>x<H>         Board size, e.g. 16x16 or 7 (default: worked out from the tile set)
+  --config <file>        JSON file listing the fixed pieces (default: the starter piece on the real puzzle)
+  --hints                Fix the four clue pieces as well as the starter piece
+  --threads <n>          Search threads (default: every core)
+  --seed <n>             Base seed for the random choices (default: random)
+  --format <format>      How boards are printed: terminal, text, json, bucas or svg (default terminal)
+  --output <file>        solve, render and import: save the board, as JSON, SVG, PNG or PPM going by the file
+                         extension and text otherwise. megatiles: save as much of the board as got placed the
+                         same way, or the mega-tiles as SVG, PNG or PPM if nothing did
+  --palette <file>       JSON file of the colours pictures use, see svg.rs (default 22 built in colours)
+  --ids                  Write the piece ids on SVG pictures
+  --colour <when>        auto, always or never (default auto: only on a terminal, and not if NO_COLOR is set)
+  --ascii                Draw boards with +, - and | instead of box drawing characters
+  --dashboard            megatiles, best and resuming megatiles: redraw a live view of the search in place instead
+                         of scrolling. megatiles runs on one thread with it. Brute force resumes still scroll
+  --board <file>         render and verify only: the saved board to show or check
+  --method <method>      solve only: exact, best, brute or split (default exact)
+  --budget <spec>        best only: mismatches allowed per row range, e.g. 0-11:0,12-15:13
+  --nodes <n>            best only: stop after placing this many pieces
+  --save-every <secs>    best only: keep --output up to date with the best board so far, writing it at most this
+                         often
+  --centre <id>/<turns>  brute and split only: the piece in the middle, unless --config fixes one (default the
+                         middle piece, unturned)
+  --split-depth <n>      split only: squares filled in before the work is shared out (default 4)
+  --checkpoint <file>    megatiles and brute only: save progress to this file every minute
+  --block <WxH>          megatiles only: build blocks this size instead of 2x2 mega-tiles, up to 4x4. Where the
+                         interior doesn't divide evenly the last row and column of blocks are one narrower
+  --mega-db <file>       megatiles: look mega-tiles up in a file saved by megadb instead of building them at every
+                         step. stats: print how many the file holds per edge as well";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Solve,
+    MegaTiles,
+    Stats,
+    MegaDb,
+    Render,
+    Verify,
+    Resume,
+    Import,
+    Help,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SolveMethod {
+    Exact, // solve_board_seeded on every thread, first one to finish wins
+    Best,  // search_best_board with a mismatch budget
+    Brute, // brute_force from a fixed centre piece
+    Split, // brute_force_split, counting every solution
+}
+
+#[derive(Clone, Debug)]
+pub struct CliOptions {
+    pub command: Command,
+    pub tiles: Option<String>, // None for the built in Eternity II set
+    pub size: Option<(usize, usize)>,
+    pub config: Option<String>, // None for the default fixed pieces
+    pub use_hints: bool,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub format: BoardFormat,
+    pub output: Option<PathBuf>,
+    pub board: Option<PathBuf>,
+    pub palette: Option<String>, // None for the built in colours
+    pub show_ids: bool,
+    pub colour: ColourChoice,
+    pub ascii: bool,
+    pub dashboard: bool,
+    pub method: SolveMethod,
+    pub budget: Option<String>,
+    pub node_limit: Option<u64>,
+    pub save_every: Option<Duration>, // None to only save the best board at the end
+    pub centre: Option<(usize, Orientation)>,
+    pub split_depth: usize,
+    pub checkpoint: Option<PathBuf>,
+    pub block: Option<BlockShape>, // None for 2x2 mega-tiles
+    pub mega_db: Option<PathBuf>, // Where megadb saves to, or the one megatiles and stats read
+    pub resume: Option<PathBuf>,
+    pub import: Option<String>,
+}
+
+// args is everything after the program name
+pub fn parse_args(args: &[String]) -> Result<CliOptions, String> {
+    // Checkpoints were picked up with --resume <file> before there were commands, so that still starts a resume
+    let (command, flags) = match args.split_first() {
+        Some((first, _)) if first.starts_with("--") && args.iter().any(|arg| arg == "--resume") => ("resume", args),
+        Some((command, flags)) => (command.as_str(), flags),
+        None => return Err(String::from("No command given")),
+    };
+    let mut options = CliOptions {
+        command: match command {
+            "solve" => Command::Solve,
+            "megatiles" => Command::MegaTiles,
+            "stats" => Command::Stats,
+            "megadb" => Command::MegaDb,
+            "render" => Command::Render,
+            "verify" => Command::Verify,
+            "resume" => Command::Resume,
+            "import" => Command::Import,
+            "help" | "--help" | "-h" => Command::Help,
+            _ => return Err(format!("Unknown command '{}'", command)),
+        },
+        tiles: None,
+        size: None,
+        config: None,
+        use_hints: false,
+        threads: None,
+        seed: None,
+        format: BoardFormat::Terminal,
+        output: None,
+        board: None,
+        palette: None,
+        show_ids: false,
+        colour: ColourChoice::Auto,
+        ascii: false,
+        dashboard: false,
+        method: SolveMethod::Exact,
+        budget: None,
+        node_limit: None,
+        save_every: None,
+        centre: None,
+        split_depth: 4,
+        checkpoint: None,
+        block: None,
+        mega_db: None,
+        resume: None,
+        import: None,
+    };
+
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        // resume, import and megadb take their file or link straight after the command
+        if options.command == Command::Resume && options.resume.is_none() && !flag.starts_with("--") {
+            options.resume = Some(PathBuf::from(flag));
+            continue;
+        }
+        if options.command == Command::Import && options.import.is_none() && !flag.starts_with("--") {
+            options.import = Some(flag.to_string());
+            continue;
+        }
+        if options.command == Command::MegaDb && options.mega_db.is_none() && !flag.starts_with("--") {
+            options.mega_db = Some(PathBuf::from(flag));
+            continue;
+        }
+
+        let mut value = || flags.next().map(|value| value.as_str()).ok_or_else(|| format!("{} needs a value", flag));
+        let bad_value = |value: &str| format!("Can't use '{}' for {}", value, flag);
+        match flag.as_str() {
+            "--tiles" => options.tiles = Some(value()?.to_string()),
+            "--size" => {
+                let value = value()?;
+                options.size = Some(parse_size(value).ok_or_else(|| bad_value(value))?);
+            }
+            "--config" => options.config = Some(value()?.to_string()),
+            "--hints" => options.use_hints = true,
+            "--threads" => {
+                let value = value()?;
+                options.threads = Some(value.parse().ok().filter(|threads| *threads > 0).ok_or_else(|| bad_value(value))?);
+            }
+            "--seed" => {
+                let value = value()?;
+                options.seed = Some(value.parse().map_err(|_| bad_value(value))?);
+            }
+            "--format" => {
+                options.format = match value()? {
+                    "terminal" => BoardFormat::Terminal,
+                    "text" => BoardFormat::Text,
+                    "json" => BoardFormat::Json,
+                    "bucas" => BoardFormat::Bucas,
+                    "svg" => BoardFormat::Svg,
+                    other => return Err(bad_value(other)),
+                };
+            }
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--board" => options.board = Some(PathBuf::from(value()?)),
+            "--palette" => options.palette = Some(value()?.to_string()),
+            "--ids" => options.show_ids = true,
+            "--colour" | "--color" => {
+                options.colour = match value()? {
+                    "auto" => ColourChoice::Auto,
+                    "always" => ColourChoice::Always,
+                    "never" => ColourChoice::Never,
+                    other => return Err(bad_value(other)),
+                };
+            }
+            "--ascii" => options.ascii = true,
+            "--dashboard" => options.dashboard = true,
+            "--method" => {
+                options.method = match value()? {
+                    "exact" => SolveMethod::Exact,
+                    "best" => SolveMethod::Best,
+                    "brute" => SolveMethod::Brute,
+                    "split" => SolveMethod::Split,
+                    other => return Err(bad_value(other)),
+                };
+            }
+            "--budget" => options.budget = Some(value()?.to_string()),
+            "--nodes" => {
+                let value = value()?;
+                options.node_limit = Some(value.parse().map_err(|_| bad_value(value))?);
+            }
+            "--save-every" => {
+                let value = value()?;
+                options.save_every = Some(Duration::from_secs(value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| bad_value(value))?));
+            }
+            "--centre" => {
+                let value = value()?;
+                options.centre = Some(parse_piece(value).ok_or_else(|| bad_value(value))?);
+            }
+            "--split-depth" => {
+                let value = value()?;
+                options.split_depth = value.parse().map_err(|_| bad_value(value))?;
+            }
+            "--checkpoint" => options.checkpoint = Some(PathBuf::from(value()?)),
+            "--block" => {
+                let value = value()?;
+                let (cols, rows) = parse_size(value).filter(|(cols, rows)| *cols <= MAX_BLOCK_SIDE && *rows <= MAX_BLOCK_SIDE).ok_or_else(|| bad_value(value))?;
+                options.block = Some(BlockShape { rows, cols }).filter(|shape| *shape != BlockShape::MEGA_TILE);
+            }
+            "--mega-db" => options.mega_db = Some(PathBuf::from(value()?)),
+            "--resume" if options.command == Command::Resume => options.resume = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("Unknown flag '{}'", flag)),
+        }
+    }
+
+    if options.use_hints && options.config.is_some() {
+        return Err(String::from("--hints can't be used with --config, list the clue pieces in the config instead"));
+    }
+    if options.block.is_some() && options.checkpoint.is_some() {
+        return Err(String::from("--checkpoint only works with 2x2 mega-tiles"));
+    }
+    if options.command == Command::MegaTiles && options.mega_db.is_some() && (options.block.is_some() || options.checkpoint.is_some()) {
+        return Err(String::from("--mega-db only works with 2x2 mega-tiles and without --checkpoint"));
+    }
+    if options.save_every.is_some() && options.output.is_none() {
+        return Err(String::from("--save-every needs --output to save to"));
+    }
+    if options.command == Command::Resume && options.resume.is_none() {
+        return Err(String::from("resume needs a checkpoint file"));
+    }
+    if options.command == Command::Import && options.import.is_none() {
+        return Err(String::from("import needs a link or a file"));
+    }
+    if options.command == Command::MegaDb && options.mega_db.is_none() {
+        return Err(String::from("megadb needs a file to save to"));
+    }
+    Ok(options)
+}
+
+// Either WxH or a single number for a square board
+fn parse_size(text: &str) -> Option<(usize, usize)> {
+    let (width, height) = text.split_once('x').unwrap_or((text, text));
+    let size = (width.parse().ok()?, height.parse().ok()?);
+    if size.0 == 0 || size.1 == 0 { None } else { Some(size) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<CliOptions, String> {
+        parse_args(&line.split_whitespace().map(String::from).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn resume_takes_the_file_as_a_command_or_a_flag() {
+        for line in ["resume run.checkpoint", "--resume run.checkpoint", "--tiles mini --resume run.checkpoint", "resume --resume run.checkpoint"] {
+            let options = parse(line).unwrap();
+            assert_eq!(options.command, Command::Resume, "{}", line);
+            assert_eq!(options.resume, Some(PathBuf::from("run.checkpoint")), "{}", line);
+        }
+        assert!(parse("resume").is_err());
+        assert!(parse("solve --resume run.checkpoint").is_err());
+    }
+
+    #[test]
+    fn save_every_needs_somewhere_to_save() {
+        let options = parse("solve --method best --output best.png --save-every 3600").unwrap();
+        assert_eq!(options.save_every, Some(Duration::from_secs(3600)));
+        assert!(parse("solve --method best --save-every 3600").is_err());
+        assert!(parse("solve --method best --output best.png --save-every 0").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use serde::Deserialize;
+use crate::data::*;
+
+// Which pieces are pinned to which squares, read from a JSON file so clue sets can be tried without recompiling:
+//
+//   {
+//     "fixed_pieces": [
+//       { "id": 138, "row": 7, "col": 8, "rotation": 1 },
+//       { "id": 207, "row": 2, "col": 2, "rotation": 3 }
+//     ]
+//   }
+//
+// Piece ids are 0 indexed like TILE_SET, so 138 is the puzzle's piece 139. Rows and columns are 0 indexed from the top left
+// and rotation is the number of clockwise quarter turns. eternity_ii.json has the starter piece and the four clues.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PuzzleConfig {
+    pub fixed_pieces: Vec<FixedPiece>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    fixed_pieces: Vec<FixedPieceEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixedPieceEntry {
+    id: usize,
+    row: usize,
+    col: usize,
+    rotation: u8,
+}
+
+impl PuzzleConfig {
+    // What the solvers have always used: the starter piece on the real puzzle, nothing on the clones.
+    // with_clues adds the four clue pieces.
+    pub fn default_for(puzzle: &Puzzle, with_clues: bool) -> PuzzleConfig {
+        let mut fixed_pieces = Vec::new();
+        if puzzle.is_eternity_ii() {
+            fixed_pieces.push(MANDATORY_PIECE);
+            if with_clues {
+                fixed_pieces.extend_from_slice(&CLUE_PIECES);
+            }
+        }
+        PuzzleConfig { fixed_pieces }
+    }
+
+    pub fn from_json(text: &str) -> Result<PuzzleConfig, String> {
+        let file: ConfigFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut fixed_pieces = Vec::with_capacity(file.fixed_pieces.len());
+        for entry in file.fixed_pieces.iter() {
+            let orientation = Orientation::from_quarter_turns(entry.rotation)
+                .ok_or_else(|| format!("piece {} has rotation {}, expected 0 to 3", entry.id, entry.rotation))?;
+            fixed_pieces.push(FixedPiece { id: entry.id, row: entry.row, col: entry.col, orientation });
+        }
+        Ok(PuzzleConfig { fixed_pieces })
+    }
+
+    pub fn load(path: &str) -> Result<PuzzleConfig, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read config {}: {}", path, e))?;
+        PuzzleConfig::from_json(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Catches the mistakes that would otherwise turn up as a search that can never succeed
+    pub fn check(&self, tiles: &[Tile], puzzle: &Puzzle) -> Result<(), String> {
+        for (i, fixed) in self.fixed_pieces.iter().enumerate() {
+            if fixed.id >= tiles.len() {
+                return Err(format!("there's no piece {} in a set of {}", fixed.id, tiles.len()));
+            }
+            if fixed.row >= puzzle.height || fixed.col >= puzzle.width {
+                return Err(format!("piece {} is at row {} col {}, off the {}x{} board", fixed.id, fixed.row, fixed.col, puzzle.width, puzzle.height));
+            }
+            if let Some(other) = self.fixed_pieces[..i].iter().find(|other| other.id == fixed.id || (other.row, other.col) == (fixed.row, fixed.col)) {
+                return Err(format!("piece {} at row {} col {} clashes with piece {} at row {} col {}", fixed.id, fixed.row, fixed.col, other.id, other.row, other.col));
+            }
+            // Grey on exactly the sides that face off the board, so a rim piece has to be turned to point them outward
+            let oriented_tile = OrientedTile::new(fixed.id, tiles, fixed.orientation);
+            for (side, on_rim, colour) in [("top", fixed.row == 0, oriented_tile.top()),
+                                           ("right", fixed.col == puzzle.width - 1, oriented_tile.right()),
+                                           ("bottom", fixed.row == puzzle.height - 1, oriented_tile.bottom()),
+                                           ("left", fixed.col == 0, oriented_tile.left())] {
+                if on_rim != (colour == -1) {
+                    let problem = if on_rim { "a colour facing off the board" } else { "grey facing into the board" };
+                    return Err(format!("piece {} at row {} col {} with rotation {} has {} on its {} side",
+                                       fixed.id, fixed.row, fixed.col, fixed.orientation.quarter_turns(), problem, side));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::solved_puzzle;
+
+    // Pins the pieces at these squares the way they sit in the solution, then gives the first of them extra turns
+    fn pinned_from_solution(squares: &[(usize, usize)], extra_turns: u8) -> Result<(), String> {
+        let (tiles, puzzle, solution) = solved_puzzle(6, 6, 10, 3, 3);
+        let fixed_pieces = squares.iter().enumerate().map(|(i, &(row, col))| {
+            let oriented_tile = solution[row][col].unwrap().turned(if i == 0 { extra_turns } else { 0 });
+            FixedPiece { id: oriented_tile.id(), row, col, orientation: oriented_tile.orientation() }
+        }).collect();
+        PuzzleConfig { fixed_pieces }.check(&tiles, &puzzle)
+    }
+
+    #[test]
+    fn pieces_turned_as_in_the_solution_pass() {
+        assert_eq!(pinned_from_solution(&[(0, 0), (0, 3), (5, 2), (2, 2)], 0), Ok(()));
+    }
+
+    #[test]
+    fn rim_pieces_have_to_point_their_grey_outward() {
+        for square in [(0, 0), (0, 3), (3, 5), (5, 5), (5, 2), (2, 0)] {
+            for turns in 1..4 {
+                let result = pinned_from_solution(&[square], turns);
+                assert!(result.as_ref().is_err_and(|message| message.contains(" facing ")),
+                        "{:?} turned {} more gave {:?}", square, turns, result);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_pieces_cant_go_in_the_interior() {
+        let (tiles, puzzle, solution) = solved_puzzle(6, 6, 10, 3, 3);
+        let edge = solution[0][3].unwrap();
+        let fixed = FixedPiece { id: edge.id(), row: 2, col: 3, orientation: edge.orientation() };
+        let message = PuzzleConfig { fixed_pieces: vec![fixed] }.check(&tiles, &puzzle).unwrap_err();
+        assert!(message.contains("grey facing into the board"), "{}", message);
+    }
+}
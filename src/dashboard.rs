@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+use crate::data::*;
+use crate::solver::SearchStats;
+
+// A view of a running search that redraws in place instead of scrolling: how deep it has got, how fast it's going,
+// the best score so far, where it keeps backing up, and what it's holding right now.
+// Searches take one and tick it as they go. A disabled one does nothing, and log lines print as they always have.
+
+const REFRESH: Duration = Duration::from_millis(250);
+const CALLS_PER_CLOCK_CHECK: u64 = 256;  // Reading the clock on every node slows the solver down noticeably
+const MEGA_TILES_PER_ROW: usize = 8;
+
+pub struct Dashboard {
+    enabled: bool,
+    title: String,
+    target_depth: usize, // What deepest is out of
+    start: Instant,
+    last_draw: Instant,
+    last_nodes: u64,
+    calls: u64,
+    best: Option<(usize, usize)>, // Score out of a maximum
+    status: String,
+    backtracks: Vec<u64>, // How many times a placement at each depth was taken back
+}
+
+impl Dashboard {
+    pub fn new(title: &str, target_depth: usize) -> Dashboard {
+        Dashboard {
+            enabled: true,
+            title: title.to_string(),
+            target_depth,
+            start: Instant::now(),
+            last_draw: Instant::now(),
+            last_nodes: 0,
+            calls: 0,
+            best: None,
+            status: String::new(),
+            backtracks: vec![0; target_depth + 1],
+        }
+    }
+
+    pub fn disabled() -> Dashboard {
+        Dashboard { enabled: false, ..Dashboard::new("", 0) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // True every REFRESH or so, so the caller only builds the picture when it's going to be drawn
+    pub fn due(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.calls += 1;
+        self.calls.is_multiple_of(CALLS_PER_CLOCK_CHECK) && self.last_draw.elapsed() >= REFRESH
+    }
+
+    pub fn backtrack(&mut self, depth: usize) {
+        if self.enabled {
+            if depth >= self.backtracks.len() {
+                self.backtracks.resize(depth + 1, 0);
+            }
+            self.backtracks[depth] += 1;
+        }
+    }
+
+    pub fn set_best(&mut self, score: usize, max_score: usize) {
+        self.best = Some((score, max_score));
+    }
+
+    // Goes in the status panel when the dashboard is up, otherwise it's printed like any other line
+    pub fn log(&mut self, line: String) {
+        if self.enabled {
+            self.status = line;
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    pub fn draw(&mut self, stats: &SearchStats, picture: &[String]) {
+        if !self.enabled {
+            return;
+        }
+        let since_last = self.last_draw.elapsed().as_secs_f64().max(1e-9);
+        let rate = stats.nodes.saturating_sub(self.last_nodes) as f64 / since_last;
+        self.last_draw = Instant::now();
+        self.last_nodes = stats.nodes;
+
+        let mut lines = vec![
+            format!("== {} == {:.0}s", self.title, self.start.elapsed().as_secs_f64()),
+            format!("Nodes    {} ({:.0} per second)", stats.nodes, rate),
+            format!("Deepest  {}/{}", stats.deepest, self.target_depth),
+            match self.best {
+                Some((score, max_score)) => format!("Best     {}/{}", score, max_score),
+                None => String::from("Best     -"),
+            },
+            format!("Status   {}", self.status),
+            String::new(),
+            String::from("Backtracks per depth"),
+        ];
+        let deepest_backtrack = self.backtracks.iter().rposition(|count| *count > 0).map_or(0, |depth| depth + 1);
+        for (chunk_index, counts) in self.backtracks[..deepest_backtrack].chunks(8).enumerate() {
+            let cells: Vec<String> = counts.iter().enumerate().map(|(i, count)| format!("{:3}:{:<9}", chunk_index * 8 + i, count)).collect();
+            lines.push(cells.join(" "));
+        }
+        lines.push(String::new());
+        lines.extend(picture.iter().cloned());
+
+        // Home the cursor, write over the old frame clearing each line's leftovers, then clear anything below
+        let mut frame = String::from("\x1b[H");
+        for line in lines.iter() {
+            frame.push_str(line);
+            frame.push_str("\x1b[K\n");
+        }
+        frame.push_str("\x1b[J");
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(frame.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+// Piece ids, '.' for an empty square
+pub fn board_picture(grid: &[Vec<Option<OrientedTile>>]) -> Vec<String> {
+    grid.iter().map(|row| row.iter().map(|tile| match tile {
+        Some(oriented_tile) => format!("{:>4}", oriented_tile.id()),
+        None => String::from("   ."),
+    }).collect()).collect()
+}
+
+// Each mega-tile as its four piece ids in a 2x2 block, a few blocks to a row
+pub fn mega_tile_picture(mega_tiles: &[MegaTile]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for row in mega_tiles.chunks(MEGA_TILES_PER_ROW) {
+        for (left, right) in [(0, 1), (2, 3)] {
+            let cells: Vec<String> = row.iter().map(|mega_tile| format!("{:>4}{:>4}", mega_tile.tiles[left].id(), mega_tile.tiles[right].id())).collect();
+            lines.push(cells.join("   "));
+        }
+        lines.push(String::new());
+    }
+    lines
+}
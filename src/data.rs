@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use rand::seq::SliceRandom;
 
-#[derive(Copy, Clone)]
+// Every random choice in the searches comes from one of these. ChaCha can report and jump to its position in the
+// stream, which is what lets a checkpoint put the rng back exactly where it was.
+pub type SearchRng = rand_chacha::ChaCha12Rng;
+
+#[derive(Copy, Clone, Debug)]
 pub struct Tile {
     pub top: i8,
     pub right: i8,
@@ -10,66 +14,239 @@ pub struct Tile {
 }
 
 
-#[derive(Copy, Clone)]
+// How far a piece has been turned clockwise from the way it's listed in the tile set
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Orientation {
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 4] = [Orientation::Deg0, Orientation::Deg90, Orientation::Deg180, Orientation::Deg270];
+
+    pub fn quarter_turns(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_quarter_turns(turns: u8) -> Option<Orientation> {
+        Orientation::ALL.get(turns as usize).copied()
+    }
+}
+
+// Pieces are written as id/quarter turns in checkpoints, board files and on the command line, e.g. 138/1
+pub fn parse_piece(text: &str) -> Option<(usize, Orientation)> {
+    let (id, turns) = text.split_once('/')?;
+    Some((id.parse().ok()?, Orientation::from_quarter_turns(turns.parse().ok()?)?))
+}
+
+// Piece ids are stored in a byte, which is exactly enough for the 16x16 board
+pub const MAX_PIECES: usize = 256;
+
+// Piece ids as a bitset, one bit each. Checking whether any of a group of pieces is still free is four ANDs however
+// many pieces are in it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PieceSet {
+    words: [u64; MAX_PIECES / 64],
+}
+
+impl PieceSet {
+    pub const EMPTY: PieceSet = PieceSet { words: [0; MAX_PIECES / 64] };
+
+    // Pieces 0 to count - 1
+    pub fn first(count: usize) -> PieceSet {
+        (0..count).collect()
+    }
+
+    #[inline]
+    pub fn contains(&self, id: usize) -> bool {
+        self.words[id / 64] & (1 << (id % 64)) != 0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, id: usize) {
+        self.words[id / 64] |= 1 << (id % 64);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, id: usize) {
+        self.words[id / 64] &= !(1 << (id % 64));
+    }
+
+    #[inline]
+    pub fn intersects(&self, other: &PieceSet) -> bool {
+        self.words.iter().zip(other.words.iter()).any(|(a, b)| a & b != 0)
+    }
+}
+
+impl FromIterator<usize> for PieceSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(ids: I) -> PieceSet {
+        let mut set = PieceSet::EMPTY;
+        for id in ids {
+            set.insert(id);
+        }
+        set
+    }
+}
+
+// A piece in a particular rotation. The sides are worked out once up front so reading one is just a field load.
+// Six bytes: top, right, bottom, left, the piece id and the orientation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct OrientedTile {
-    pub tile: Tile,
-    pub orientation: u8, // clockwise: 0 = normal, 1 = 90 degrees , 2 = 180 degrees, 3 = 270 degrees
+    sides: [i8; 4],
+    id: u8,
+    orientation: Orientation,
 }
 
-pub const IS_USING_HINTS: bool = false;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Puzzle {
+    // Everything the solvers need to know about the board shape
+    // Colours are numbered 0 to colour_count - 1 with the border colours first, grey is -1 and not counted
+    pub width: usize,
+    pub height: usize,
+    pub colour_count: usize,
+    pub border_colour_count: usize,
+}
 
-impl OrientedTile {
-    // Easily get the top, right, bottom, left sides of a tile given its rotation
-    pub fn tile_as_rotated(&self) -> Tile {
-        match self.orientation {
-            0 => self.tile,
-            1 => Tile {
-                top: self.tile.left,
-                right: self.tile.top,
-                bottom: self.tile.right,
-                left: self.tile.bottom,
-                id: self.tile.id,
-            },
-            2 => Tile {
-                top: self.tile.bottom,
-                right: self.tile.left,
-                bottom: self.tile.top,
-                left: self.tile.right,
-                id: self.tile.id,
-            },
-            3 => Tile {
-                top: self.tile.right,
-                right: self.tile.bottom,
-                bottom: self.tile.left,
-                left: self.tile.top,
-                id: self.tile.id,
-            },
-            _ => panic!("Invalid orientation"),
+impl Puzzle {
+    pub const fn eternity_ii() -> Puzzle {
+        Puzzle { width: 16, height: 16, colour_count: 22, border_colour_count: 5 }
+    }
+
+    // Works out the board shape from the corner, edge and interior counts of a tile set
+    // Rectangular boards come out landscape, i.e. width >= height
+    pub fn from_tile_set(tile_set: &[Tile]) -> Option<Puzzle> {
+        let edge_count = tile_set.iter().filter(|t| t.bottom != -1 && t.left == -1).count();
+        let interior_count = tile_set.iter().filter(|t| t.bottom != -1 && t.left != -1).count();
+        if tile_set.len() != 4 + edge_count + interior_count || edge_count % 2 == 1 {
+            return None;
+        }
+
+        // (width - 2) + (height - 2) = edge_count / 2 and (width - 2) * (height - 2) = interior_count
+        let inner_sum = edge_count / 2;
+        let inner_width = (inner_sum / 2..=inner_sum).rev().find(|w| w * (inner_sum - w) == interior_count)?;
+        let inner_height = inner_sum - inner_width;
+        if inner_height == 0 {
+            return None;
         }
+
+        let mut colour_count = 0;
+        let mut border_colour_count = 0;
+        for tile in tile_set.iter() {
+            for side in [tile.top, tile.right, tile.bottom, tile.left].iter() {
+                colour_count = colour_count.max(*side + 1);
+            }
+            // Colours running along the rim are the border colours
+            if tile.left == -1 {
+                let rim_sides = if tile.bottom == -1 { [tile.top, tile.right] } else { [tile.top, tile.bottom] };
+                for side in rim_sides.iter() {
+                    border_colour_count = border_colour_count.max(*side + 1);
+                }
+            }
+        }
+
+        Some(Puzzle {
+            width: inner_width + 2,
+            height: inner_height + 2,
+            colour_count: colour_count as usize,
+            border_colour_count: border_colour_count as usize,
+        })
     }
 
+    pub fn piece_count(&self) -> usize { self.width * self.height }
+    pub fn corner_count(&self) -> usize { 4 }
+    pub fn edge_count(&self) -> usize { 2 * (self.width - 2) + 2 * (self.height - 2) }
+    pub fn border_piece_count(&self) -> usize { self.corner_count() + self.edge_count() }
+    pub fn interior_count(&self) -> usize { (self.width - 2) * (self.height - 2) }
+
+    // Number of 2x2 mega-tiles needed to cover the interior
+    pub fn mega_tile_count(&self) -> usize { ((self.width - 2) / 2) * ((self.height - 2) / 2) }
+
+    // Number of internal edges where two tiles touch. 480 for the real puzzle.
+    pub fn max_matches(&self) -> usize { self.width * (self.height - 1) + self.height * (self.width - 1) }
+
+    // The mandatory starter piece and the four clue pieces only exist in the real Eternity II set
+    pub fn is_eternity_ii(&self) -> bool { *self == Puzzle::eternity_ii() }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FixedPiece {
+    // A piece that has to sit in a particular square. Rows and columns are 0 indexed from the top left.
+    pub id: usize,
+    pub row: usize,
+    pub col: usize,
+    pub orientation: Orientation,
+}
+
+// Piece 139 (138 for us) has to go in square I8, i.e. the 9th column of the 8th row
+pub const MANDATORY_PIECE: FixedPiece = FixedPiece { id: 138, row: 7, col: 8, orientation: Orientation::Deg90 };
+
+// The four clue pieces, 208 in C3, 255 in C14, 181 in N3 and 249 in N14
+pub const CLUE_PIECES: [FixedPiece; 4] = [
+    FixedPiece { id: 207, row: 2, col: 2, orientation: Orientation::Deg270 },
+    FixedPiece { id: 254, row: 13, col: 2, orientation: Orientation::Deg180 },
+    FixedPiece { id: 180, row: 2, col: 13, orientation: Orientation::Deg0 },
+    FixedPiece { id: 248, row: 13, col: 13, orientation: Orientation::Deg180 },
+];
+
+impl OrientedTile {
     pub fn top(&self) -> i8 {
-        self.tile_as_rotated().top
+        self.sides[0]
     }
     pub fn right(&self) -> i8 {
-        self.tile_as_rotated().right
+        self.sides[1]
     }
     pub fn bottom(&self) -> i8 {
-        self.tile_as_rotated().bottom
+        self.sides[2]
     }
     pub fn left(&self) -> i8 {
-        self.tile_as_rotated().left
+        self.sides[3]
+    }
+    pub fn sides(&self) -> [i8; 4] {
+        self.sides
     }
     pub fn id(&self) -> usize {
-        self.tile.id
+        self.id as usize
+    }
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
     }
 
-    pub fn new(tileId: usize, tileset: &[Tile], orientation: u8) -> OrientedTile {
+    pub fn new(tile_id: usize, tileset: &[Tile], orientation: Orientation) -> OrientedTile {
+        let tile = tileset[tile_id];
+        let unrotated = [tile.top, tile.right, tile.bottom, tile.left];
+        // Turning clockwise moves each side one place round: after a quarter turn the old left side is on top
+        let turns = orientation.quarter_turns() as usize;
+        let mut sides = [0; 4];
+        for (i, side) in sides.iter_mut().enumerate() {
+            *side = unrotated[(i + 4 - turns) % 4];
+        }
         OrientedTile {
-            tile: tileset[tileId],
+            sides,
+            // The loader won't hand out more than MAX_PIECES pieces, so the id always fits
+            id: u8::try_from(tile.id).expect("piece ids have to fit in a byte"),
             orientation,
         }
     }
+
+    // The same piece given some more clockwise quarter turns
+    pub fn turned(&self, turns: u8) -> OrientedTile {
+        let turns = (turns % 4) as usize;
+        let mut sides = [0; 4];
+        for (i, side) in sides.iter_mut().enumerate() {
+            *side = self.sides[(i + 4 - turns) % 4];
+        }
+        let orientation = Orientation::from_quarter_turns((self.orientation.quarter_turns() + turns as u8) % 4).expect("always 0 to 3");
+        OrientedTile { sides, id: self.id, orientation }
+    }
+
+    // Whether this is the piece turned that way, or looks exactly as if it was. A symmetric piece shows the same sides in
+    // more than one orientation and the lookups only keep the first of them, so the orientations alone can't be compared.
+    pub fn is_placed_as(&self, id: usize, orientation: Orientation) -> bool {
+        let turns = (orientation.quarter_turns() + 4 - self.orientation.quarter_turns()) % 4;
+        self.id() == id && self.turned(turns).sides == self.sides
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -85,36 +262,54 @@ impl MegaTile {
     pub fn right(&self) -> (i8, i8) { (self.tiles[1].right(), self.tiles[3].right()) }
     pub fn bottom(&self) -> (i8, i8) { (self.tiles[3].bottom(), self.tiles[2].bottom()) }
     pub fn left(&self) -> (i8, i8) { (self.tiles[2].left(), self.tiles[0].left()) }
+
+    // The whole 2x2 block given clockwise quarter turns, each tile moving round a corner and turning with it
+    pub fn turned(&self, turns: u8) -> MegaTile {
+        let mut mega_tile = *self;
+        for _ in 0..turns % 4 {
+            let [top_left, top_right, bottom_left, bottom_right] = mega_tile.tiles;
+            mega_tile.tiles = [bottom_left.turned(1), top_left.turned(1), bottom_right.turned(1), top_right.turned(1)];
+        }
+        mega_tile
+    }
 }
 
 
-pub fn create_filled_grid(tileset: &[Tile], grid_size: usize) -> Vec<Vec<Option<OrientedTile>>> {
-    let mut grid= vec![vec![None; grid_size]; grid_size];
+pub fn create_filled_grid(tileset: &[Tile], puzzle: &Puzzle) -> Vec<Vec<Option<OrientedTile>>> {
+    let width = puzzle.width;
+    let height = puzzle.height;
+    let mut grid = vec![vec![None; width]; height];
 
     // Corners
-    grid[0][0] = Some(OrientedTile::new(0, tileset, 1));
-    grid[0][15] = Some(OrientedTile::new(1, tileset, 2));
-    grid[15][0] = Some(OrientedTile::new(2, tileset, 0));
-    grid[15][15] = Some(OrientedTile::new(3, tileset, 3));
+    grid[0][0] = Some(OrientedTile::new(0, tileset, Orientation::Deg90));
+    grid[0][width - 1] = Some(OrientedTile::new(1, tileset, Orientation::Deg180));
+    grid[height - 1][0] = Some(OrientedTile::new(2, tileset, Orientation::Deg0));
+    grid[height - 1][width - 1] = Some(OrientedTile::new(3, tileset, Orientation::Deg270));
 
-    // Edges
-    for i in 1..15 {  // Fill the top edge. Use tiles 4 to 17
-        grid [0][i] = Some(OrientedTile::new(4 + i - 1, tileset, 1));
+    // Edges, handed out in order straight after the corners
+    let mut next_tile = 4;
+    for i in 1..width - 1 {  // Fill the top edge
+        grid[0][i] = Some(OrientedTile::new(next_tile, tileset, Orientation::Deg90));
+        next_tile += 1;
     }
-    for i in 1..15 {  // Fill the bottom edge. Use tiles 18 to 31
-        grid [grid_size-1][i] = Some(OrientedTile::new(18 + i - 1, tileset, 3));
+    for i in 1..width - 1 {  // Fill the bottom edge
+        grid[height - 1][i] = Some(OrientedTile::new(next_tile, tileset, Orientation::Deg270));
+        next_tile += 1;
     }
-    for i in 1..15 { // Fill the left edge. Use tiles 32 to 45
-        grid [i][0] = Some(OrientedTile::new(32 + i - 1, tileset, 0));
+    for i in 1..height - 1 { // Fill the left edge
+        grid[i][0] = Some(OrientedTile::new(next_tile, tileset, Orientation::Deg0));
+        next_tile += 1;
     }
-    for i in 1..15 { // Fill the right edge. Use tiles 46 to 59
-        grid [i][grid_size-1] = Some(OrientedTile::new(46 + i - 1, tileset, 2));
+    for i in 1..height - 1 { // Fill the right edge
+        grid[i][width - 1] = Some(OrientedTile::new(next_tile, tileset, Orientation::Deg180));
+        next_tile += 1;
     }
 
     // Center
-    for i in 1..15 {
-        for j in 1..15 {
-            grid [i][j] = Some(OrientedTile::new(60 + (i - 1) * 14 + j - 1, tileset, 0));
+    for i in 1..height - 1 {
+        for j in 1..width - 1 {
+            grid[i][j] = Some(OrientedTile::new(next_tile, tileset, Orientation::Deg0));
+            next_tile += 1;
         }
     }
 
@@ -153,30 +348,106 @@ pub fn create_filled_grid(tileset: &[Tile], grid_size: usize) -> Vec<Vec<Option<
 //     (index_unigram, index_bigram)
 // }
 
-pub fn build_indices(tile_set: &[Tile], skip_n: usize) -> HashMap<(i8, i8, i8, i8), Vec<OrientedTile>> {
-    let mut index = HashMap::new();
+// Looking pieces up by their sides is the innermost step of every search, so rather than hashing a 4-tuple
+// each side is turned into a small code and the four codes index straight into a flat table.
+// Codes: grey is 0, colours are 1 to colour_count and ANY_SIDE is the last one.
+// Every oriented piece is stored once under each of the 16 combinations of real sides and wildcards,
+// and each key owns one contiguous slice of the candidate list.
+// Each key also has the set of pieces in its slice, so a search can tell nothing free fits without walking it.
+pub const ANY_SIDE: i8 = -2;
+
+pub struct TileLookup {
+    side_codes: usize,
+    offsets: Vec<u32>, // Candidates for key k live in candidates[offsets[k]..offsets[k + 1]]
+    candidates: Vec<OrientedTile>,
+    mask_index: Vec<u32>, // The pieces for key k are masks[mask_index[k]]. Empty keys share masks[0].
+    masks: Vec<PieceSet>,
+}
 
-    for (i, tile) in tile_set.iter().enumerate() {
-        // skip over the first 60
-        if i < skip_n {
-            continue;
+impl TileLookup {
+    // Pass ANY_SIDE for sides that can be anything. Grey (-1) is a real side here.
+    #[inline]
+    pub fn get(&self, top: i8, right: i8, bottom: i8, left: i8) -> &[OrientedTile] {
+        let key = self.key(top, right, bottom, left);
+        &self.candidates[self.offsets[key] as usize..self.offsets[key + 1] as usize]
+    }
+
+    // Every piece that get would hand back for these sides, in any of its orientations
+    #[inline]
+    pub fn pieces(&self, top: i8, right: i8, bottom: i8, left: i8) -> &PieceSet {
+        &self.masks[self.mask_index[self.key(top, right, bottom, left)] as usize]
+    }
+
+    // Mixes up the order within every bucket so that differently seeded searches try pieces in different orders
+    pub fn shuffle_candidates(&mut self, rng: &mut SearchRng) {
+        for key in 0..self.offsets.len() - 1 {
+            self.candidates[self.offsets[key] as usize..self.offsets[key + 1] as usize].shuffle(rng);
         }
+    }
+
+    #[inline]
+    fn side_code(&self, side: i8) -> usize {
+        if side == ANY_SIDE { self.side_codes - 1 } else { (side + 1) as usize }
+    }
 
-        for orientation in 0..4 {
+    #[inline]
+    fn key(&self, top: i8, right: i8, bottom: i8, left: i8) -> usize {
+        ((self.side_code(top) * self.side_codes + self.side_code(right)) * self.side_codes + self.side_code(bottom)) * self.side_codes + self.side_code(left)
+    }
+}
+
+pub fn build_indices(tile_set: &[Tile], skip_n: usize) -> TileLookup {
+    let colour_count = tile_set.iter().flat_map(|t| [t.top, t.right, t.bottom, t.left]).max().map_or(0, |c| c as usize + 1);
+    let side_codes = colour_count + 2;
+    let mut lookup = TileLookup { side_codes, offsets: Vec::new(), candidates: Vec::new(), mask_index: Vec::new(), masks: vec![PieceSet::EMPTY] };
+
+    // Symmetric tiles look the same in several orientations, no point trying them twice
+    let mut oriented_tiles: Vec<OrientedTile> = Vec::new();
+    for i in skip_n..tile_set.len() {
+        for orientation in Orientation::ALL {
             let oriented_tile = OrientedTile::new(i, tile_set, orientation);
-            // The cache treats -1 as a wildcard
-            for top in vec![oriented_tile.top(), -1] {
-                for right in vec![oriented_tile.right(), -1] {
-                    for bottom in vec![oriented_tile.bottom(), -1] {
-                        for left in vec![oriented_tile.left(), -1] {
-                            index.entry((top, right, bottom, left)).or_insert(Vec::new()).push(oriented_tile);
-                        }
-                    }
-                }
+            if !oriented_tiles.iter().any(|o| o.id() == i && o.sides() == oriented_tile.sides()) {
+                oriented_tiles.push(oriented_tile);
             }
         }
     }
-    index
+
+    // Every key a piece answers to: each side is either its real value or the wildcard
+    let keys_for = |lookup: &TileLookup, oriented_tile: &OrientedTile| {
+        let mut keys = [0; 16];
+        for (mask, key) in keys.iter_mut().enumerate() {
+            let pick = |bit: usize, side: i8| if mask & (1 << bit) == 0 { side } else { ANY_SIDE };
+            *key = lookup.key(pick(0, oriented_tile.top()), pick(1, oriented_tile.right()), pick(2, oriented_tile.bottom()), pick(3, oriented_tile.left()));
+        }
+        keys
+    };
+
+    // Group the entries by key so every bucket is a slice of one big list. The sort is stable, so buckets stay in tile order.
+    let mut entries: Vec<(usize, OrientedTile)> = oriented_tiles.iter()
+        .flat_map(|oriented_tile| keys_for(&lookup, oriented_tile).map(|key| (key, *oriented_tile)))
+        .collect();
+    entries.sort_by_key(|(key, _)| *key);
+
+    let key_count = side_codes.pow(4);
+    let mut offsets = vec![0u32; key_count + 1];
+    for (key, _) in entries.iter() {
+        offsets[key + 1] += 1;
+    }
+    for key in 0..key_count {
+        offsets[key + 1] += offsets[key];
+    }
+    lookup.candidates = entries.into_iter().map(|(_, oriented_tile)| oriented_tile).collect();
+
+    lookup.mask_index = vec![0; key_count];
+    for key in 0..key_count {
+        let bucket = &lookup.candidates[offsets[key] as usize..offsets[key + 1] as usize];
+        if !bucket.is_empty() {
+            lookup.mask_index[key] = lookup.masks.len() as u32;
+            lookup.masks.push(bucket.iter().map(|oriented_tile| oriented_tile.id()).collect());
+        }
+    }
+    lookup.offsets = offsets;
+    lookup
 }
 
 // make a global array of tiles
@@ -493,55 +764,102 @@ pub const TILE_SET: [Tile; 256] = [
     Tile {top: 11, right: 20, bottom: 12, left: 20, id: 255},
 ];
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn symmetric_pieces_are_placed_as_their_look_alike_turns() {
+        let tiles = [Tile { top: 1, right: 2, bottom: 1, left: 2, id: 0 }, Tile { top: 1, right: 2, bottom: 3, left: 4, id: 1 }];
+        let symmetric = OrientedTile::new(0, &tiles, Orientation::Deg0);
+        assert!(symmetric.is_placed_as(0, Orientation::Deg180));
+        assert!(!symmetric.is_placed_as(0, Orientation::Deg90));
+        assert!(!symmetric.is_placed_as(1, Orientation::Deg0));
 
+        let plain = OrientedTile::new(1, &tiles, Orientation::Deg90);
+        assert!(plain.is_placed_as(1, Orientation::Deg90));
+        assert!(!plain.is_placed_as(1, Orientation::Deg270));
+    }
 
-//
-// fn load_tiles() -> [Tile; 256] {
-//     // Tiles are stored inside data.csv
-//     // Each row is a tile
-//     // The first  column is the tileID, the next 4 columns are the top, right, bottom, left sides
-//
-//     // Load the data. data.csv is in the same directory as the the src
-//     let mut reader = csv::Reader::from_path("data.csv").unwrap();
-//
-//     // Create a thing to store the tiles. We know the size to be 256, so can stack allocate
-//     let mut tiles = [EMPTY_TILE; 256];
-//
-//
-//     for (i, result) in reader.records().enumerate() {
-//
-//         // Get the row
-//         let record = result.unwrap();
-//
-//         // The first 4 tiles are corner tiles with no bottom or left side
-//         // The next 56 tiles are edge tiles with no left side
-//         // The next 196 tiles are normal tiles
-//
-//         // The edges of tiles are stored as a number from 1 to 22. I want 0 indexing, with -1 as empty
-//         // Rust doesn't like going straight to an i8, so we have to do some weird stuff
-//         if i < 4 { // Corner tile
-//             tiles [i] = Tile {
-//                 top: i8::from_str_radix(&record[1], 10).unwrap() - 1,
-//                 right: i8::from_str_radix(&record[2], 10).unwrap() - 1,
-//                 bottom: -1,
-//                 left: -1,
-//             };
-//         } else if i < 60 { // Edge tile
-//             tiles [i] = Tile {
-//                 top: i8::from_str_radix(&record[1], 10).unwrap() - 1,
-//                 right: i8::from_str_radix(&record[2], 10).unwrap() - 1,
-//                 bottom: i8::from_str_radix(&record[3], 10).unwrap() - 1,
-//                 left: -1,
-//             };
-//         } else { // Normal tile
-//             tiles [i] = Tile {
-//                 top: i8::from_str_radix(&record[1], 10).unwrap() - 1,
-//                 right: i8::from_str_radix(&record[2], 10).unwrap() - 1,
-//                 bottom: i8::from_str_radix(&record[3], 10).unwrap() - 1,
-//                 left: i8::from_str_radix(&record[4], 10).unwrap() - 1,
-//             };
-//         }
-//     }
-//     tiles
-// }
\ No newline at end of file
+    #[test]
+    fn lookups_keep_one_of_each_look_alike_turn() {
+        let tiles = [Tile { top: 1, right: 2, bottom: 1, left: 2, id: 0 }];
+        let candidates = build_indices(&tiles, 0).get(ANY_SIDE, ANY_SIDE, ANY_SIDE, ANY_SIDE).to_vec();
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates.iter().any(|tile| tile.is_placed_as(0, Orientation::Deg180)));
+    }
+
+    fn sides(mega_tile: &MegaTile) -> Vec<(usize, [i8; 4])> {
+        mega_tile.tiles.iter().map(|tile| (tile.id(), tile.sides())).collect()
+    }
+
+    #[test]
+    fn turning_four_times_goes_all_the_way_round() {
+        let tiles: Vec<Tile> = (0..4).map(|id| Tile { top: id as i8, right: 4 + id as i8, bottom: 8 + id as i8, left: 12 + id as i8, id }).collect();
+        let tile = OrientedTile::new(2, &tiles, Orientation::Deg90);
+        assert_eq!(tile.turned(1).turned(1).turned(1).turned(1), tile);
+        assert_eq!(tile.turned(3), OrientedTile::new(2, &tiles, Orientation::Deg0));
+
+        let mega_tile = MegaTile { tiles: [0, 1, 2, 3].map(|id| OrientedTile::new(id, &tiles, Orientation::ALL[id])) };
+        assert_eq!(sides(&mega_tile.turned(1).turned(1).turned(1).turned(1)), sides(&mega_tile));
+        assert_eq!(sides(&mega_tile.turned(4)), sides(&mega_tile));
+    }
+
+    #[test]
+    fn a_quarter_turn_moves_each_piece_round_a_corner() {
+        let tiles: Vec<Tile> = (0..4).map(|id| Tile { top: id as i8, right: 4 + id as i8, bottom: 8 + id as i8, left: 12 + id as i8, id }).collect();
+        let mega_tile = MegaTile { tiles: [0, 1, 2, 3].map(|id| OrientedTile::new(id, &tiles, Orientation::Deg0)) };
+        let turned = mega_tile.turned(1);
+
+        // Bottom left goes to the top left, top left to the top right, bottom right to the bottom left and top right to
+        // the bottom right, each turned a quarter
+        let [top_left, top_right, bottom_left, bottom_right] = turned.tiles;
+        assert_eq!(top_left, OrientedTile::new(2, &tiles, Orientation::Deg90));
+        assert_eq!(top_right, OrientedTile::new(0, &tiles, Orientation::Deg90));
+        assert_eq!(bottom_left, OrientedTile::new(3, &tiles, Orientation::Deg90));
+        assert_eq!(bottom_right, OrientedTile::new(1, &tiles, Orientation::Deg90));
+
+        // The left bigram comes round to the top
+        assert_eq!(turned.top(), mega_tile.left());
+        assert_eq!(turned.right(), mega_tile.top());
+    }
+
+    #[test]
+    fn piece_sets_hold_ids_either_side_of_a_word() {
+        let mut set = PieceSet::EMPTY;
+        for id in [0, 63, 64, 255] {
+            assert!(!set.contains(id));
+            set.insert(id);
+            assert!(set.contains(id));
+        }
+        assert!(!set.contains(1) && !set.contains(62) && !set.contains(65) && !set.contains(254));
+        assert_eq!(set, [255, 64, 63, 0].into_iter().collect());
+
+        set.remove(63);
+        assert!(!set.contains(63) && set.contains(64));
+        set.remove(63); // Already gone
+        assert_eq!(set, [0, 64, 255].into_iter().collect());
+        assert_eq!(PieceSet::first(65), (0..65).collect());
+        assert!(PieceSet::first(64).contains(63) && !PieceSet::first(64).contains(64));
+    }
+
+    #[test]
+    fn lookup_masks_intersect_the_free_pieces_that_fit() {
+        let lookup = build_indices(&TILE_SET, 0);
+        let (top, left) = (TILE_SET[100].top, TILE_SET[100].left);
+        let fitting = lookup.pieces(top, ANY_SIDE, ANY_SIDE, left);
+        let ids: PieceSet = lookup.get(top, ANY_SIDE, ANY_SIDE, left).iter().map(|tile| tile.id()).collect();
+        assert_eq!(*fitting, ids);
+        assert!(fitting.contains(100));
+
+        let mut available = PieceSet::first(TILE_SET.len());
+        assert!(fitting.intersects(&available));
+        for tile in lookup.get(top, ANY_SIDE, ANY_SIDE, left).iter() {
+            available.remove(tile.id());
+        }
+        assert!(!fitting.intersects(&available));
+        available.insert(100);
+        assert!(fitting.intersects(&available));
+        assert!(!PieceSet::EMPTY.intersects(&PieceSet::first(TILE_SET.len())));
+    }
+}
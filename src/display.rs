@@ -1,28 +1,82 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use colored::Colorize;
+use crate::board::*;
+use crate::bucas::*;
 use crate::data::*;
+use crate::parallel::*;
+use crate::svg::*;
+use crate::validate::*;
+
+// Colour is only used when it will show up: not when NO_COLOR is set or output is going to a file.
+// Without colour, mismatches are marked with a '*' instead. ASCII swaps the box drawing for +, - and |.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColourChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+static ASCII_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn configure_display(colour: ColourChoice, ascii: bool) {
+    let use_colour = match colour {
+        ColourChoice::Always => true,
+        ColourChoice::Never => false,
+        ColourChoice::Auto => std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::io::stdout().is_terminal(),
+    };
+    colored::control::set_override(use_colour);
+    ASCII_ONLY.store(ascii, Ordering::Relaxed);
+}
+
+fn use_colour() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
+}
+
+// Box drawing, or the ASCII stand in for it
+fn frame(unicode: &'static str, ascii: &'static str) -> &'static str {
+    if ASCII_ONLY.load(Ordering::Relaxed) { ascii } else { unicode }
+}
+
+// Green or red when there's colour, left alone when there isn't
+pub fn paint(text: &str, good: bool) -> String {
+    if good { text.green().to_string() } else { text.red().to_string() }
+}
+
+// Like paint, but a bad line still stands out without colour
+pub fn flag(text: &str, good: bool) -> String {
+    if good || use_colour() { paint(text, good) } else { format!("{} *", text) }
+}
+
+// Put between a square's side letter and its middle, so a mismatch shows up without colour. Grey is never marked.
+fn mismatch_mark(num: i8, matches: bool) -> &'static str {
+    if matches || num == -1 || use_colour() { " " } else { "*" }
+}
 
 pub fn colour_symbol(num: i8, matches: bool, vertical: bool, is_megatile: bool) -> String {
     if num == -1 {
         if is_megatile {
             return String::from(" ")
         } else if vertical {
-            return String::from("│")
+            return String::from(frame("│", "|"))
         } else {
-            return String::from("───")
+            return String::from(frame("───", "---"))
         }
     }
     let tile_char = (num + 65) as u8 as char;
-    if matches {
-        if vertical || is_megatile{
-            format!("\x1b[32m{}\x1b[0m", tile_char)
-        } else {
-            format!("\x1b[32m {} \x1b[0m", tile_char)
+    if !matches && !use_colour() {
+        // Single width squares have no room for the letter and a mark, the megatile info line says which colours they were
+        if is_megatile {
+            return String::from("*")
+        } else if !vertical {
+            return format!("*{}*", tile_char)
         }
+    }
+    if vertical || is_megatile {
+        paint(&tile_char.to_string(), matches)
     } else {
-        if vertical || is_megatile {
-            format!("\x1b[31m{}\x1b[0m", tile_char)
-        } else {
-            format!("\x1b[31m {} \x1b[0m", tile_char)
-        }
+        paint(&format!(" {} ", tile_char), matches)
     }
 }
 
@@ -32,13 +86,14 @@ pub fn print_grid(grid: &Vec<Vec<Option<OrientedTile>>>) {
     R   J
     └ R ┘
     Correct matches are coloured green
-    Bad matches are coloured red
+    Bad matches are coloured red, or marked with * when there's no colour
     Only the number itself is coloured, not the whole tile.
     */
 
-    let grid_size = grid.len();
+    let height = grid.len();
+    let width = grid[0].len();
     let mut match_count = 0;
-    let max_matches = grid_size * (grid_size-1) * 2;
+    let max_matches = width * (height - 1) + height * (width - 1);
 
     for(i, row) in grid.iter().enumerate() {
         // The top line empty string we build on
@@ -49,8 +104,8 @@ pub fn print_grid(grid: &Vec<Vec<Option<OrientedTile>>>) {
             if let Some(oriented_tile) = tile {
                 // Check whether a match exists on the top side
                 let top_matches = i > 0 && grid[i - 1][j].is_some() && oriented_tile.top() == grid[i - 1][j].unwrap().bottom();
-                let right_matches = j < grid_size - 1 && grid[i][j + 1].is_some() && oriented_tile.right() == grid[i][j + 1].unwrap().left();
-                let bottom_matches = i < grid_size - 1 && grid[i + 1][j].is_some() && oriented_tile.bottom() == grid[i + 1][j].unwrap().top();
+                let right_matches = j < width - 1 && grid[i][j + 1].is_some() && oriented_tile.right() == grid[i][j + 1].unwrap().left();
+                let bottom_matches = i < height - 1 && grid[i + 1][j].is_some() && oriented_tile.bottom() == grid[i + 1][j].unwrap().top();
                 let left_matches = j > 0 && grid[i][j - 1].is_some() && oriented_tile.left() == grid[i][j - 1].unwrap().right();
 
                 match_count += (top_matches as i32) + (right_matches as i32) + (bottom_matches as i32) + (left_matches as i32);
@@ -60,13 +115,13 @@ pub fn print_grid(grid: &Vec<Vec<Option<OrientedTile>>>) {
                 let bottom_coloured_symbol = colour_symbol(oriented_tile.bottom(), bottom_matches, false, false);
                 let left_coloured_symbol = colour_symbol(oriented_tile.left(), left_matches, true, false);
 
-                top_line.push_str(&format!("┌{}┐ ", top_coloured_symbol));
-                middle_line.push_str(&format!("{}   {} ", left_coloured_symbol, right_coloured_symbol));
-                bottom_line.push_str(&format!("└{}┘ ", bottom_coloured_symbol));
+                top_line.push_str(&format!("{}{}{} ", frame("┌", "+"), top_coloured_symbol, frame("┐", "+")));
+                middle_line.push_str(&format!("{}{} {}{} ", left_coloured_symbol, mismatch_mark(oriented_tile.left(), left_matches), mismatch_mark(oriented_tile.right(), right_matches), right_coloured_symbol));
+                bottom_line.push_str(&format!("{}{}{} ", frame("└", "+"), bottom_coloured_symbol, frame("┘", "+")));
             } else {
-                top_line.push_str("┌───┐ ");
-                middle_line.push_str("│   │ ");
-                bottom_line.push_str("└───┘ ");
+                top_line.push_str(frame("┌───┐ ", "+---+ "));
+                middle_line.push_str(frame("│   │ ", "|   | "));
+                bottom_line.push_str(frame("└───┘ ", "+---+ "));
             }
         }
         println!("{}", top_line);
@@ -79,6 +134,26 @@ pub fn print_grid(grid: &Vec<Vec<Option<OrientedTile>>>) {
 }
 
 
+// How a finished board gets written out
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoardFormat {
+    Terminal, // The coloured picture from print_grid
+    Text,     // The board file layouts from board.rs, which can be loaded back in
+    Json,
+    Bucas,    // A link that opens the board in the e2.bucas.name viewer
+    Svg,      // A picture for reports, drawn in style
+}
+
+pub fn print_board(grid: &Vec<Vec<Option<OrientedTile>>>, format: BoardFormat, style: &SvgStyle) {
+    match format {
+        BoardFormat::Terminal => print_grid(grid),
+        BoardFormat::Text => print!("{}", board_to_text(grid)),
+        BoardFormat::Json => print!("{}", board_to_json(grid)),
+        BoardFormat::Bucas => println!("{}", board_to_bucas_url(grid)),
+        BoardFormat::Svg => print!("{}", board_to_svg(grid, style)),
+    }
+}
+
 pub fn visualise_mega_tile(megatile: &MegaTile) {
     /*
     ┌ L F ┐
@@ -128,11 +203,11 @@ pub fn visualise_mega_tile(megatile: &MegaTile) {
     let left_middle_symbol = colour_symbol(megatile.tiles[0].bottom(), left_middle_matches, false, true);
     let right_middle_symbol = colour_symbol(megatile.tiles[1].bottom(), right_middle_matches, false, true);
 
-    top_line.push_str(&format!("┌{}─{}┐ ", top_left_symbol, top_right_symbol));
+    top_line.push_str(&format!("{}{}{}{}{} ", frame("┌", "+"), top_left_symbol, frame("─", "-"), top_right_symbol, frame("┐", "+")));
     top_middle_line.push_str(&format!("{} {} {} ", left_top_symbol, top_middle_symbol, right_top_symbol));
     middle_line.push_str(&format!("|{} {}|", left_middle_symbol, right_middle_symbol));
     bottom_middle_line.push_str(&format!("{} {} {} ", left_bottom_symbol, bottom_middle_symbol, right_bottom_symbol));
-    bottom_line.push_str(&format!("└{}─{}┘ ", bottom_left_symbol, bottom_right_symbol));
+    bottom_line.push_str(&format!("{}{}{}{}{} ", frame("└", "+"), bottom_left_symbol, frame("─", "-"), bottom_right_symbol, frame("┘", "+")));
 
     println!("{}", top_line);
     println!("{}", top_middle_line);
@@ -142,4 +217,91 @@ pub fn visualise_mega_tile(megatile: &MegaTile) {
     if info_line.len() > 0 {
         println!("{}", info_line);
     }
-}
\ No newline at end of file
+}
+pub fn print_tileset_report(report: &TileSetReport) {
+    let letters = |colours: &Vec<i8>| colours.iter().map(|c| ((c + 65) as u8 as char).to_string()).collect::<Vec<_>>().join(" ");
+
+    println!("Tile set for a {}x{} board with {} colours ({} border)", report.puzzle.width, report.puzzle.height, report.puzzle.colour_count, report.puzzle.border_colour_count);
+    println!("Corners: {}, Edges: {}, Interior: {}", report.corner_count, report.edge_count, report.interior_count);
+    println!("Border colours:   {}", letters(&report.border_colours));
+    println!("Interior colours: {}", letters(&report.interior_colours));
+    println!("Colour counts: {:?}", report.colour_counts);
+
+    if report.is_valid() {
+        println!("{}", paint("No problems found", true));
+        return;
+    }
+    println!("{}", paint(&format!("Found {} problems", report.issues.len()), false));
+    for issue in report.issues.iter() {
+        let description = match issue {
+            TileSetIssue::BadGreyPlacement { id } => format!("Tile {} has grey somewhere other than its bottom/left", id),
+            TileSetIssue::BadColour { id, colour } => format!("Tile {} has an unknown colour {}", id, colour),
+            TileSetIssue::OutOfOrder { id, class, after } => format!("Tile {} is a {:?} piece but comes after a {:?} piece", id, class, after),
+            TileSetIssue::OddColourCount { colour, count } => format!("Colour {} appears {} times, which can't pair up", (colour + 65) as u8 as char, count),
+            TileSetIssue::SharedBorderColour { colour } => format!("Colour {} is used on both the rim and the interior", (colour + 65) as u8 as char),
+            TileSetIssue::WrongTileCount { expected, found } => format!("Expected {} tiles, found {}", expected, found),
+            TileSetIssue::WrongPieceCount { class, expected, found } => format!("Expected {} {:?} pieces, found {}", expected, class, found),
+        };
+        println!("  {}", description);
+    }
+}
+
+pub fn print_board_report(report: &BoardReport) {
+    let letter = |colour: &i8| if *colour == -1 { String::from("grey") } else { ((colour + 65) as u8 as char).to_string() };
+
+    println!("{}x{} board, {} pieces placed, score {}/{}", report.width, report.height, report.placed, report.score, report.max_score);
+    if report.is_valid() {
+        println!("{}", paint("No problems found", true));
+        return;
+    }
+    println!("{}", paint(&format!("Found {} problems, {} of them mismatched edges", report.issues.len(), report.mismatch_count()), false));
+    for issue in report.issues.iter() {
+        let description = match issue {
+            BoardIssue::WrongSize { expected, found } => format!("Board is {}x{}, the tile set is for {}x{}", found.0, found.1, expected.0, expected.1),
+            BoardIssue::EmptySquare { row, col } => format!("Row {} col {} is empty", row, col),
+            BoardIssue::DuplicatePiece { id, first, again } => format!("Piece {} is at row {} col {} and again at row {} col {}", id, first.0, first.1, again.0, again.1),
+            BoardIssue::UnusedPiece { id } => format!("Piece {} isn't on the board", id),
+            BoardIssue::NonGreyRim { row, col, side, colour } => format!("Row {} col {} has {} on its {} side, the rim should be grey", row, col, letter(colour), format!("{:?}", side).to_lowercase()),
+            BoardIssue::Mismatch { row, col, side, colours } => {
+                let (other_row, other_col) = if *side == BoardSide::Right { (*row, col + 1) } else { (row + 1, *col) };
+                format!("Row {} col {} against row {} col {}: {} meets {}", row, col, other_row, other_col, letter(&colours.0), letter(&colours.1))
+            }
+            BoardIssue::FixedPieceMoved { fixed, found } => format!("Piece {}/{} should be at row {} col {}, found {}", fixed.id, fixed.orientation.quarter_turns(), fixed.row, fixed.col,
+                                                                     found.map_or(String::from("nothing"), |(id, orientation)| format!("{}/{}", id, orientation.quarter_turns()))),
+        };
+        println!("  {}", description);
+    }
+}
+
+pub fn print_parallel_report(report: &ParallelReport) {
+    let seconds = report.elapsed.as_secs_f64().max(1e-9);
+    println!("{} threads from base seed {} placed {} pieces in {:.1}s ({:.0} per second), deepest {}",
+             report.per_thread.len(), report.base_seed, report.total_nodes(), seconds, report.total_nodes() as f64 / seconds, report.deepest());
+    for (thread_index, stats) in report.per_thread.iter().enumerate() {
+        let marker = if report.winner == Some(thread_index) { " <- found it" } else { "" };
+        println!("  Thread {:2} (seed {}): {} placed, deepest {}{}", thread_index, thread_seed(report.base_seed, thread_index), stats.nodes, stats.deepest, marker);
+    }
+    // Which thread wins is down to timing, but each thread on its own is repeatable
+    if let Some(winner) = report.winner {
+        println!("Run with --seed {} --threads 1 to repeat the winning search", thread_seed(report.base_seed, winner));
+    }
+}
+
+// How evenly the work split up. A few huge units means the split depth wants to go up.
+pub fn print_work_unit_report(unit_stats: &[WorkUnitStats]) {
+    let total_nodes: u64 = unit_stats.iter().map(|stats| stats.nodes).sum();
+    let total_solutions: u64 = unit_stats.iter().map(|stats| stats.solutions).sum();
+    println!("{} work units, {} pieces placed below the split, {} solutions", unit_stats.len(), total_nodes, total_solutions);
+    if unit_stats.is_empty() {
+        return;
+    }
+
+    let mut nodes: Vec<u64> = unit_stats.iter().map(|stats| stats.nodes).collect();
+    nodes.sort_unstable();
+    let mean = total_nodes as f64 / nodes.len() as f64;
+    println!("Nodes per unit: min {}, median {}, mean {:.0}, max {} ({:.1}% of all nodes)",
+             nodes[0], nodes[nodes.len() / 2], mean, nodes[nodes.len() - 1], 100.0 * nodes[nodes.len() - 1] as f64 / total_nodes.max(1) as f64);
+    for (unit_index, stats) in unit_stats.iter().enumerate() {
+        println!("  Unit {:4}: {:12} nodes, {} solutions", unit_index, stats.nodes, stats.solutions);
+    }
+}
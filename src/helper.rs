@@ -1,47 +1,42 @@
 use crate::data::*;
 use crate::display::*;
+use crate::validate::*;
 
 
 
-pub fn print_random_data() {
+pub fn print_random_data(tiles: &[Tile], puzzle: &Puzzle) {
     // Fill the grid
-    let grid = create_filled_grid(&TILE_SET, 16);
+    let grid = create_filled_grid(tiles, puzzle);
 
     // Lets print some data on all the various tile types
-    println!("There are {} tiles", TILE_SET.len());
-    println!("There are {} corner tiles", TILE_SET.iter().filter(|t| t.bottom == -1 && t.left == -1).count());
-    println!("There are {} edge tiles", TILE_SET.iter().filter(|t| t.bottom != -1 && t.left == -1).count());
-    println!("There are {} normal tiles", TILE_SET.iter().filter(|t| t.bottom != -1 && t.left != -1).count());
+    println!("There are {} tiles", tiles.len());
+    println!("There are {} corner tiles", tiles.iter().filter(|t| t.bottom == -1 && t.left == -1).count());
+    println!("There are {} edge tiles", tiles.iter().filter(|t| t.bottom != -1 && t.left == -1).count());
+    println!("There are {} normal tiles", tiles.iter().filter(|t| t.bottom != -1 && t.left != -1).count());
+    print_tileset_report(&validate_tileset(tiles, puzzle));
 
     print_grid(&grid);
 
     // Now try to do a merge kinda thing. We want to make a bunch of 2x2 MegaTiles
-    // Let's only worry about the middle 14x14 grid for now.
+    // Let's only worry about the middle of the grid for now.
 
     // Create some constraints for the megatiles based on the edge pieces
-    let required_outside_matches = count_tile_types( &TILE_SET, true, true, false, false);
+    let required_outside_matches = count_tile_types(tiles, puzzle, true, true, false, false);
     println!("Required outside matches: {:?}", required_outside_matches);
 
-    let required_inside_matches = count_tile_types( &TILE_SET, false, false, true, false);
+    let required_inside_matches = count_tile_types(tiles, puzzle, false, false, true, false);
     println!("Required inside matches:       {:?}", required_inside_matches);
 
     // Matches that shouldn't touch any edge pieces
-    let mut required_matches = [0; 22];
-    for i in 5..22 {
+    let mut required_matches = vec![0; puzzle.colour_count];
+    for i in puzzle.border_colour_count..puzzle.colour_count {
         required_matches[i] =  required_inside_matches[i] - required_outside_matches[i];
     }
     println!("Required matches:              {:?}", required_matches);
 }
 
-pub fn generate_wanted_hint_sides() -> [i32; 22] {
-    let hints = [
-        OrientedTile::new(207, &TILE_SET, 3),
-        OrientedTile::new(254, &TILE_SET, 2),
-        OrientedTile::new(180, &TILE_SET, 0),
-        OrientedTile::new(248, &TILE_SET, 2),
-    ];
-
-    let mut hint_requirements = [0; 22];
+pub fn generate_wanted_hint_sides(hints: &[OrientedTile], puzzle: &Puzzle) -> Vec<i32> {
+    let mut hint_requirements = vec![0; puzzle.colour_count];
     for hint in hints.iter() {
         // Want to make sure the bottom and left pieces required by the hints are available.
         hint_requirements[hint.bottom() as usize] += 1;
@@ -50,8 +45,8 @@ pub fn generate_wanted_hint_sides() -> [i32; 22] {
     hint_requirements
 }
 
-pub fn count_tile_types(tileset: &[Tile], corners: bool, edges: bool, center: bool, trim_edge_only: bool) -> [i32; 22] {
-    let mut tile_counts = [0; 22];
+pub fn count_tile_types(tileset: &[Tile], puzzle: &Puzzle, corners: bool, edges: bool, center: bool, trim_edge_only: bool) -> Vec<i32> {
+    let mut tile_counts = vec![0; puzzle.colour_count];
     for tile in tileset.iter() {
         if corners && tile.bottom == -1 && tile.left == -1 {
             tile_counts[tile.top as usize] += 1;
@@ -68,11 +63,9 @@ pub fn count_tile_types(tileset: &[Tile], corners: bool, edges: bool, center: bo
         }
     }
     if trim_edge_only {
-        tile_counts[0] = 0;
-        tile_counts[1] = 0;
-        tile_counts[2] = 0;
-        tile_counts[3] = 0;
-        tile_counts[4] = 0;
+        for count in tile_counts.iter_mut().take(puzzle.border_colour_count) {
+            *count = 0;
+        }
     }
     tile_counts
 }
@@ -112,7 +105,7 @@ pub fn generate_spiral_search_order(grid_size: usize) -> Vec<(usize, usize)> {
     }
 
     // pretty print the search order onto a 2d grid with 2 digit numbers displaying the index
-    let mut grid = [[0; 7]; 7];
+    let mut grid = vec![vec![0; grid_size]; grid_size];
     for (i, (x, y)) in search_order.iter().enumerate() {
         grid[*x][*y] = i;
     }
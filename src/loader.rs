@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::data::*;
+
+// Loads tile sets at runtime so clone puzzles and benchmark sets can be swapped in without recompiling.
+// Three layouts are understood:
+//
+//  Csv      - data.csv. The first row is a header (width, height, border colours, interior colours).
+//             Every other row is "id,top,right,bottom,left" with colours 1 up to the header's total and an empty
+//             field for grey. The header has to agree with the pieces.
+//  Plain    - One piece per line "top right bottom left", whitespace separated, 0 for grey and 1 up for colours.
+//  Numbered - The e2pieces.txt layout. Same as Plain but each line starts with the 1 indexed piece number.
+//
+// Blank lines and lines starting with '#' are skipped in the text layouts.
+// Colours are shifted down by one on the way in so that grey is -1, matching TILE_SET. The text layouts don't say how
+// many colours there are, so anything up to HIGHEST_COLOUR goes and the tile set ends up with as many as it uses.
+// Border pieces are rotated so their grey sides end up on the bottom/left, which is what create_filled_grid expects.
+
+pub const HIGHEST_COLOUR: i8 = i8::MAX; // Sides are stored as i8
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileFileFormat {
+    Csv,
+    Plain,
+    Numbered,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TileLoadErrorKind {
+    Io(String),
+    BadHeader(String),
+    HeaderMismatch(String),
+    BadColour { value: String, highest: i8 },
+    WrongColumnCount { expected: usize, found: usize },
+    OutOfSequence { expected: usize, found: usize },
+    BadGreySides,
+    Duplicate { first_line: usize },
+    TooManyPieces { found: usize },
+    Empty,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileLoadError {
+    pub line: usize, // 1 indexed, 0 if the error isn't tied to a line
+    pub kind: TileLoadErrorKind,
+}
+
+impl fmt::Display for TileLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: ", self.line)?;
+        }
+        match &self.kind {
+            TileLoadErrorKind::Io(message) => write!(f, "could not read tile file: {}", message),
+            TileLoadErrorKind::BadHeader(message) => write!(f, "bad header: {}", message),
+            TileLoadErrorKind::HeaderMismatch(message) => write!(f, "header doesn't match the pieces: {}", message),
+            TileLoadErrorKind::BadColour { value, highest } => write!(f, "bad colour value '{}', expected grey or 1 to {}", value, highest),
+            TileLoadErrorKind::WrongColumnCount { expected, found } => write!(f, "expected {} columns, found {}", expected, found),
+            TileLoadErrorKind::OutOfSequence { expected, found } => write!(f, "expected piece number {}, found {}", expected, found),
+            TileLoadErrorKind::BadGreySides => write!(f, "grey sides must be a single side or two adjacent sides"),
+            TileLoadErrorKind::Duplicate { first_line } => write!(f, "duplicate of the piece on line {}", first_line),
+            TileLoadErrorKind::TooManyPieces { found } => write!(f, "found {} pieces, at most {} are supported", found, MAX_PIECES),
+            TileLoadErrorKind::Empty => write!(f, "no pieces found"),
+        }
+    }
+}
+
+pub fn detect_tile_file_format(path: &Path, contents: &str) -> TileFileFormat {
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv")) {
+        return TileFileFormat::Csv;
+    }
+    // Otherwise go by the number of columns on the first real line
+    let first_line = contents.lines().map(|line| line.trim()).find(|line| !line.is_empty() && !line.starts_with('#'));
+    match first_line.map(|line| line.split_whitespace().count()) {
+        Some(5) => TileFileFormat::Numbered,
+        _ => TileFileFormat::Plain,
+    }
+}
+
+pub fn load_tile_set(path: &str) -> Result<Vec<Tile>, Vec<TileLoadError>> {
+    let contents = read_tile_file(path)?;
+    let format = detect_tile_file_format(Path::new(path), &contents);
+    parse_tile_set(&contents, format)
+}
+
+fn read_tile_file(path: &str) -> Result<String, Vec<TileLoadError>> {
+    fs::read_to_string(path).map_err(|e| vec![TileLoadError { line: 0, kind: TileLoadErrorKind::Io(format!("{}: {}", path, e)) }])
+}
+
+pub fn parse_tile_set(contents: &str, format: TileFileFormat) -> Result<Vec<Tile>, Vec<TileLoadError>> {
+    // Every line is checked so that all the problems in a file get reported in one go
+    let mut errors = Vec::new();
+    let (header, pieces) = match format {
+        TileFileFormat::Csv => parse_csv_pieces(contents, &mut errors),
+        TileFileFormat::Plain => (None, parse_text_pieces(contents, false, &mut errors)),
+        TileFileFormat::Numbered => (None, parse_text_pieces(contents, true, &mut errors)),
+    };
+
+    let mut tiles = Vec::with_capacity(pieces.len());
+    let mut first_seen: HashMap<[i8; 4], usize> = HashMap::new();
+    for (line, sides) in pieces {
+        let normalised = match normalise_grey_sides(sides) {
+            Some(normalised) => normalised,
+            None => {
+                errors.push(TileLoadError { line, kind: TileLoadErrorKind::BadGreySides });
+                continue;
+            }
+        };
+        if let Some(first_line) = first_seen.get(&canonical_rotation(normalised)) {
+            errors.push(TileLoadError { line, kind: TileLoadErrorKind::Duplicate { first_line: *first_line } });
+            continue;
+        }
+        first_seen.insert(canonical_rotation(normalised), line);
+        tiles.push(Tile { top: normalised[0], right: normalised[1], bottom: normalised[2], left: normalised[3], id: tiles.len() });
+    }
+
+    if tiles.len() > MAX_PIECES {
+        errors.push(TileLoadError { line: 0, kind: TileLoadErrorKind::TooManyPieces { found: tiles.len() } });
+    }
+    if tiles.is_empty() && errors.is_empty() {
+        errors.push(TileLoadError { line: 0, kind: TileLoadErrorKind::Empty });
+    }
+    if let (Some(header), false) = (&header, tiles.is_empty()) {
+        check_header(header, &tiles, &mut errors);
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| e.line);
+        return Err(errors);
+    }
+    Ok(tiles)
+}
+
+// The first row of a CSV file
+struct CsvHeader {
+    line: usize,
+    width: usize,
+    height: usize,
+    border_colours: usize,
+    interior_colours: usize,
+}
+
+// The board has to have a square for every piece, and the colours have to be the ones the pieces use
+fn check_header(header: &CsvHeader, tiles: &[Tile], errors: &mut Vec<TileLoadError>) {
+    let mismatch = |message: String| TileLoadError { line: header.line, kind: TileLoadErrorKind::HeaderMismatch(message) };
+    if header.width * header.height != tiles.len() {
+        errors.push(mismatch(format!("a {}x{} board needs {} pieces, found {}", header.width, header.height, header.width * header.height, tiles.len())));
+    }
+    if let Some(puzzle) = Puzzle::from_tile_set(tiles) {
+        let used = (puzzle.border_colour_count, puzzle.colour_count - puzzle.border_colour_count);
+        if used != (header.border_colours, header.interior_colours) {
+            errors.push(mismatch(format!("{} border and {} interior colours, the pieces use {} and {}", header.border_colours, header.interior_colours, used.0, used.1)));
+        }
+    }
+}
+
+fn parse_csv_pieces(contents: &str, errors: &mut Vec<TileLoadError>) -> (Option<CsvHeader>, Vec<(usize, [i8; 4])>) {
+    let mut header = None;
+    let mut pieces = Vec::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+
+    for (i, result) in reader.records().enumerate() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line() as usize);
+                errors.push(TileLoadError { line, kind: TileLoadErrorKind::Io(e.to_string()) });
+                continue;
+            }
+        };
+        let line = record.position().map_or(i + 1, |p| p.line() as usize);
+
+        // The header row describes the board: width, height, border colour count, interior colour count
+        if i == 0 {
+            let numbers: Result<Vec<usize>, _> = record.iter().map(|f| f.trim()).filter(|f| !f.is_empty()).map(|f| f.parse::<usize>()).collect();
+            match numbers.as_deref() {
+                Ok(&[width, height, border_colours, interior_colours]) => {
+                    header = Some(CsvHeader { line, width, height, border_colours, interior_colours });
+                }
+                _ => errors.push(TileLoadError { line, kind: TileLoadErrorKind::BadHeader(record.iter().collect::<Vec<_>>().join(",")) }),
+            }
+            continue;
+        }
+
+        if record.len() != 5 {
+            errors.push(TileLoadError { line, kind: TileLoadErrorKind::WrongColumnCount { expected: 5, found: record.len() } });
+            continue;
+        }
+        // Without a header there's nothing to go on, so take anything and let the header error speak for itself
+        let highest = header.as_ref().map_or(HIGHEST_COLOUR, |header| (header.border_colours + header.interior_colours).min(HIGHEST_COLOUR as usize) as i8);
+        if let Some(sides) = parse_sides(&record.iter().skip(1).collect::<Vec<_>>(), line, true, highest, errors) {
+            pieces.push((line, sides));
+        }
+    }
+    (header, pieces)
+}
+
+fn parse_text_pieces(contents: &str, numbered: bool, errors: &mut Vec<TileLoadError>) -> Vec<(usize, [i8; 4])> {
+    let mut pieces = Vec::new();
+    let expected_columns = if numbered { 5 } else { 4 };
+    let mut piece_lines = 0;
+
+    for (i, raw_line) in contents.lines().enumerate() {
+        let line = i + 1;
+        let trimmed = raw_line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        piece_lines += 1;
+        let fields: Vec<&str> = trimmed.split_whitespace().collect();
+        if fields.len() != expected_columns {
+            errors.push(TileLoadError { line, kind: TileLoadErrorKind::WrongColumnCount { expected: expected_columns, found: fields.len() } });
+            continue;
+        }
+
+        let side_fields = if numbered {
+            // Piece numbers are 1 indexed and have to run in order, otherwise the ids would silently shift
+            let expected = piece_lines;
+            match fields[0].parse::<usize>() {
+                Ok(found) if found == expected => {}
+                Ok(found) => {
+                    errors.push(TileLoadError { line, kind: TileLoadErrorKind::OutOfSequence { expected, found } });
+                    continue;
+                }
+                Err(_) => {
+                    errors.push(TileLoadError { line, kind: TileLoadErrorKind::BadHeader(format!("'{}' is not a piece number", fields[0])) });
+                    continue;
+                }
+            }
+            &fields[1..]
+        } else {
+            &fields[..]
+        };
+
+        if let Some(sides) = parse_sides(side_fields, line, false, HIGHEST_COLOUR, errors) {
+            pieces.push((line, sides));
+        }
+    }
+    pieces
+}
+
+fn parse_sides(fields: &[&str], line: usize, empty_is_grey: bool, highest: i8, errors: &mut Vec<TileLoadError>) -> Option<[i8; 4]> {
+    let mut sides = [-1; 4];
+    let mut ok = true;
+    for (side, field) in sides.iter_mut().zip(fields.iter()) {
+        let field = field.trim();
+        if empty_is_grey && field.is_empty() {
+            continue;
+        }
+        match field.parse::<i8>() {
+            // The CSV uses an empty field for grey, the text layouts use 0
+            Ok(0) if !empty_is_grey => {}
+            Ok(value) if (1..=highest).contains(&value) => *side = value - 1,
+            _ => {
+                errors.push(TileLoadError { line, kind: TileLoadErrorKind::BadColour { value: field.to_string(), highest } });
+                ok = false;
+            }
+        }
+    }
+    if ok { Some(sides) } else { None }
+}
+
+// Rotates a piece so any grey sides are on the bottom/left. Returns None if the greys can't be a border piece.
+fn normalise_grey_sides(sides: [i8; 4]) -> Option<[i8; 4]> {
+    let grey_count = sides.iter().filter(|side| **side == -1).count();
+    if grey_count == 0 {
+        return Some(sides);
+    }
+    for turns in 0..4 {
+        let rotated = rotate_sides(sides, turns);
+        let fits = match grey_count {
+            1 => rotated[3] == -1,
+            2 => rotated[2] == -1 && rotated[3] == -1,
+            _ => false,
+        };
+        if fits {
+            return Some(rotated);
+        }
+    }
+    None
+}
+
+// Sides in top, right, bottom, left order after turning the piece clockwise
+fn rotate_sides(sides: [i8; 4], turns: usize) -> [i8; 4] {
+    let mut rotated = [0; 4];
+    for i in 0..4 {
+        rotated[(i + turns) % 4] = sides[i];
+    }
+    rotated
+}
+
+// Two pieces are the same if one is a rotation of the other, so compare the smallest rotation
+fn canonical_rotation(sides: [i8; 4]) -> [i8; 4] {
+    (0..4).map(|turns| rotate_sides(sides, turns)).min().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::solved_puzzle;
+
+    // The pieces written out the way data.csv has them, under the given header
+    fn csv(tiles: &[Tile], header: &str) -> String {
+        let side = |colour: i8| if colour == -1 { String::new() } else { (colour + 1).to_string() };
+        let mut text = format!("{}\n", header);
+        for tile in tiles.iter() {
+            text.push_str(&format!("{},{},{},{},{}\n", tile.id + 1, side(tile.top), side(tile.right), side(tile.bottom), side(tile.left)));
+        }
+        text
+    }
+
+    fn kinds(result: Result<Vec<Tile>, Vec<TileLoadError>>) -> Vec<(usize, TileLoadErrorKind)> {
+        result.expect_err("should have been refused").into_iter().map(|e| (e.line, e.kind)).collect()
+    }
+
+    #[test]
+    fn reads_back_a_csv_with_a_matching_header() {
+        let (tiles, _, _) = solved_puzzle(6, 5, 12, 3, 5);
+        let sides = |tiles: &[Tile]| tiles.iter().map(|tile| (tile.id, [tile.top, tile.right, tile.bottom, tile.left])).collect::<Vec<_>>();
+        let loaded = parse_tile_set(&csv(&tiles, "6,5,3,9"), TileFileFormat::Csv).expect("loads");
+        assert_eq!(sides(&loaded), sides(&tiles));
+    }
+
+    #[test]
+    fn colours_past_the_headers_count_are_bad() {
+        let (tiles, _, _) = solved_puzzle(6, 5, 12, 3, 5);
+        let mut text = csv(&tiles, "6,5,3,9");
+        text.push_str("31,1,13,2,3\n");
+        let errors = kinds(parse_tile_set(&text, TileFileFormat::Csv));
+        assert!(errors.contains(&(32, TileLoadErrorKind::BadColour { value: String::from("13"), highest: 12 })), "{:?}", errors);
+    }
+
+    #[test]
+    fn text_layouts_take_as_many_colours_as_they_use() {
+        let tiles = parse_tile_set("30 2 3 4\n5 6 7 8\n", TileFileFormat::Plain).expect("loads");
+        assert_eq!(tiles[0].top, 29);
+        assert_eq!(kinds(parse_tile_set("1 2 3 128\n", TileFileFormat::Plain)),
+                   vec![(1, TileLoadErrorKind::BadColour { value: String::from("128"), highest: HIGHEST_COLOUR })]);
+        assert_eq!(kinds(parse_tile_set("1 2 3 -4\n", TileFileFormat::Plain)),
+                   vec![(1, TileLoadErrorKind::BadColour { value: String::from("-4"), highest: HIGHEST_COLOUR })]);
+    }
+
+    #[test]
+    fn wrong_column_counts_are_reported_on_their_line() {
+        let errors = kinds(parse_tile_set("# A comment\n1 2 3 4\n1 2 3\n", TileFileFormat::Plain));
+        assert_eq!(errors, vec![(3, TileLoadErrorKind::WrongColumnCount { expected: 4, found: 3 })]);
+        let errors = kinds(parse_tile_set("1 1 2 3 4\n2 5 6 7 8 9\n", TileFileFormat::Numbered));
+        assert_eq!(errors, vec![(2, TileLoadErrorKind::WrongColumnCount { expected: 5, found: 6 })]);
+    }
+
+    #[test]
+    fn a_turned_copy_is_a_duplicate() {
+        let errors = kinds(parse_tile_set("1 2 3 4\n5 6 7 8\n3 4 1 2\n", TileFileFormat::Plain));
+        assert_eq!(errors, vec![(3, TileLoadErrorKind::Duplicate { first_line: 1 })]);
+    }
+
+    #[test]
+    fn headers_have_to_be_four_numbers_that_match_the_pieces() {
+        let (tiles, _, _) = solved_puzzle(6, 5, 12, 3, 5);
+        let errors = kinds(parse_tile_set(&csv(&tiles, "6,5,three,9"), TileFileFormat::Csv));
+        assert_eq!(errors, vec![(1, TileLoadErrorKind::BadHeader(String::from("6,5,three,9")))]);
+        let errors = kinds(parse_tile_set(&csv(&tiles, "6,5,3"), TileFileFormat::Csv));
+        assert_eq!(errors, vec![(1, TileLoadErrorKind::BadHeader(String::from("6,5,3")))]);
+
+        let errors = kinds(parse_tile_set(&csv(&tiles, "6,6,3,9"), TileFileFormat::Csv));
+        assert_eq!(errors, vec![(1, TileLoadErrorKind::HeaderMismatch(String::from("a 6x6 board needs 36 pieces, found 30")))]);
+        let errors = kinds(parse_tile_set(&csv(&tiles, "6,5,4,9"), TileFileFormat::Csv));
+        assert_eq!(errors, vec![(1, TileLoadErrorKind::HeaderMismatch(String::from("4 border and 9 interior colours, the pieces use 3 and 9")))]);
+    }
+}
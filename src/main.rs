@@ -1,12 +1,10 @@
 // A solver for the eternity 2 puzzle
 // https://en.wikipedia.org/wiki/Eternity_II
 
-// The puzzle is a 16x16 grid of 4 sided tiles
+// The puzzle is a 16x16 grid of 4 sided tiles, though the code works off a Puzzle so smaller clones can be used too
 // THe goal is to fill the grid by aligning the tiles so that the pattersn match
 // The patterns will be denoted with 1 through 22
 
-use std::collections::HashMap;
-
 // Another file called data.rs to load in
 
 
@@ -14,26 +12,354 @@ mod data;
 mod mega;
 mod display;
 mod helper;
+mod loader;
+mod validate;
+mod solver;
+mod parallel;
+mod checkpoint;
+mod cli;
+mod board;
+mod bucas;
+mod config;
+mod svg;
+mod raster;
+mod dashboard;
+mod rim;
+mod megagrid;
+mod block;
+mod megadb;
+#[cfg(test)]
+mod testing;
 
 use crate::data::*;
 use crate::display::*;
 use crate::helper::*;
-use crate::mega::*;
-use std::thread;
+use crate::parallel::*;
+use crate::checkpoint::*;
+use crate::board::*;
+use crate::config::PuzzleConfig;
+use crate::svg::*;
+use crate::raster::*;
+use crate::dashboard::Dashboard;
+use crate::block::{expand_blocks, plan_blocks};
+use crate::megadb::*;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
 
 fn main() {
-    // create_mega_tiles();
-    // brute_force(7, &MINI_TILE_SET, OrientedTile::new(24, &MINI_TILE_SET, 0));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match cli::parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    configure_display(options.colour, options.ascii);
+    if let Err(e) = run(&options) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(options: &cli::CliOptions) -> Result<(), String> {
+    if options.command == cli::Command::Help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if let Some(path) = &options.resume {
+        return resume_search(path, options);
+    }
+
+    let tiles = load_tiles(options)?;
+    let threads = options.threads.unwrap_or_else(default_thread_count);
+    // Every random choice comes from this, so printing it is enough to be able to repeat a run
+    let seed = options.seed.unwrap_or_else(rand::random);
+    if matches!(options.command, cli::Command::Solve | cli::Command::MegaTiles) {
+        println!("Seed {}", seed);
+    }
+
+    match options.command {
+        cli::Command::Solve => match options.method {
+            cli::SolveMethod::Exact => {
+                let puzzle = puzzle_for(&tiles, options.size)?;
+                let solution = parallel_solve_board(&tiles, &puzzle, &fixed_pieces_for(&tiles, &puzzle, options)?, threads, seed);
+                output_solution(&solution, options)?;
+            }
+            cli::SolveMethod::Best => {
+                let puzzle = puzzle_for(&tiles, options.size)?;
+                let budget = match &options.budget {
+                    Some(spec) => solver::MismatchBudget::from_spec(spec)?,
+                    None => solver::MismatchBudget::none(),
+                };
+                let style = svg_style(options)?;
+                let best_saver = options.save_every.zip(options.output.as_deref()).map(|(interval, path)| {
+                    solver::BestBoardSaver::new(interval, move |best| save_board_as(&best.grid, path, &style))
+                });
+                if let Some(best) = solver::search_best_board(&tiles, &puzzle, &fixed_pieces_for(&tiles, &puzzle, options)?, &budget, options.node_limit,
+                                                                  dashboard_for(options, "Searching for the best board", puzzle.piece_count()), best_saver) {
+                    output_board(&best.grid, options)?;
+                }
+            }
+            cli::SolveMethod::Brute => {
+                let (grid_size, centre_tile) = brute_force_setup(&tiles, options)?;
+                let solution = match &options.checkpoint {
+                    Some(path) => {
+                        let kind = SearchKind::BruteForce { grid_size, centre: (centre_tile.id(), centre_tile.orientation()) };
+                        println!("Checkpointing to {}", path.display());
+                        brute_force(grid_size, &tiles, centre_tile, &mut Checkpointer::new(path, DEFAULT_CHECKPOINT_INTERVAL, Checkpoint::new(kind, &tiles, seed))).0
+                    }
+                    None => brute_force(grid_size, &tiles, centre_tile, &mut Checkpointer::disabled()).0,
+                };
+                output_solution(&solution, options)?;
+            }
+            cli::SolveMethod::Split => {
+                let (grid_size, centre_tile) = brute_force_setup(&tiles, options)?;
+                brute_force_split(grid_size, &tiles, centre_tile, options.split_depth, threads);
+            }
+        },
+        cli::Command::MegaTiles => {
+            let puzzle = puzzle_for(&tiles, options.size)?;
+            let fixed_pieces = fixed_pieces_for(&tiles, &puzzle, options)?;
+            if let Some(shape) = options.block {
+                let block_count = plan_blocks(&puzzle, shape).map_or(0, |plan| plan.len());
+                let mut dashboard = dashboard_for(options, &format!("Building {}x{} blocks, seed {}", shape.cols, shape.rows, seed), block_count);
+                // Blocks get one search on one thread, laid out where plan_blocks put them
+                if let Some((plan, blocks)) = mega::create_blocks(&tiles, &puzzle, &fixed_pieces, shape, seed, &mut dashboard) {
+                    output_board(&expand_blocks(&plan, &blocks, &puzzle), options)?;
+                }
+                return Ok(());
+            }
+            let mega_db = options.mega_db.as_ref().map(|path| MegaTileDb::open(path, &tiles)).transpose()?;
+            let mut dashboard = dashboard_for(options, &format!("Building mega-tiles, seed {}", seed), puzzle.mega_tile_count());
+            match &options.checkpoint {
+                // Checkpointed runs stay on one thread so there's a single search to save
+                Some(path) => {
+                    let checkpoint = Checkpoint::new(SearchKind::MegaTiles { puzzle, fixed_pieces: fixed_pieces.clone() }, &tiles, seed);
+                    println!("Checkpointing to {} with seed {}", path.display(), seed);
+                    let result = mega::create_mega_tiles_with_checkpoints(&tiles, &puzzle, &fixed_pieces, &mut Checkpointer::new(path, DEFAULT_CHECKPOINT_INTERVAL, checkpoint), &mut dashboard)?;
+                    output_mega_tiles(&result, &puzzle, options)?;
+                }
+                // So does a run with the dashboard up, there's only room to show one search
+                None if dashboard.is_enabled() => {
+                    let result = mega::create_mega_tiles(&tiles, &puzzle, &fixed_pieces, mega_db.as_ref(), seed, &mut dashboard);
+                    output_mega_tiles(&result, &puzzle, options)?;
+                }
+                // The first thread to complete stops the rest
+                None => {
+                    let result = parallel_create_mega_tiles(&tiles, &puzzle, &fixed_pieces, mega_db.as_ref(), threads, seed);
+                    output_mega_tiles(&result, &puzzle, options)?;
+                }
+            }
+        }
+        cli::Command::Stats => {
+            print_random_data(&tiles, &puzzle_for(&tiles, options.size)?);
+            if let Some(path) = &options.mega_db {
+                print_mega_tile_db_stats(&MegaTileDb::open(path, &tiles)?);
+            }
+        }
+        cli::Command::MegaDb => if let Some(path) = &options.mega_db {
+            let records = enumerate_mega_tiles(&tiles, &puzzle_for(&tiles, options.size)?);
+            save_mega_tile_db(path, &records, &tiles)?;
+            println!("Saved to {}", path.display());
+            print_mega_tile_db_stats(&MegaTileDb::open(path, &tiles)?);
+        },
+        cli::Command::Render => match &options.board {
+            Some(path) => output_board(&load_board(path, &tiles)?, options)?,
+            None => output_board(&create_filled_grid(&tiles, &puzzle_for(&tiles, options.size)?), options)?,
+        },
+        cli::Command::Verify => match &options.board {
+            Some(path) => {
+                let puzzle = puzzle_for(&tiles, options.size)?;
+                let report = validate::verify_board(&load_board(path, &tiles)?, &tiles, Some(&puzzle), &fixed_pieces_for(&tiles, &puzzle, options)?);
+                print_board_report(&report);
+                if !report.is_valid() {
+                    return Err(format!("Board has {} problems", report.issues.len()));
+                }
+            }
+            None => {
+                let report = validate::validate_tileset(&tiles, &puzzle_for(&tiles, options.size)?);
+                print_tileset_report(&report);
+                if !report.is_valid() {
+                    return Err(format!("Tile set has {} problems", report.issues.len()));
+                }
+            }
+        },
+        cli::Command::Import => {
+            // Links are long, so they can come from a file as well
+            let link = options.import.as_deref().unwrap_or_default();
+            let text = std::fs::read_to_string(link).unwrap_or_else(|_| link.to_string());
+            let grid = bucas::parse_bucas_board(&text, &tiles).map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))?;
+            output_board(&grid, options)?;
+        }
+        cli::Command::Resume | cli::Command::Help => {}
+    }
+    Ok(())
+}
+
+fn load_tiles(options: &cli::CliOptions) -> Result<Vec<Tile>, String> {
+    match options.tiles.as_deref() {
+        None | Some("e2") => Ok(TILE_SET.to_vec()),
+        Some("mini") => Ok(MINI_TILE_SET.to_vec()),
+        Some(path) => loader::load_tile_set(path).map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")),
+    }
+}
+
+// The board shape comes from the tile set. A size that only swaps width and height is fine, anything else is a mistake.
+fn puzzle_for(tiles: &[Tile], size: Option<(usize, usize)>) -> Result<Puzzle, String> {
+    let puzzle = Puzzle::from_tile_set(tiles).ok_or("Can't work out a board shape from the border pieces of this tile set")?;
+    match size {
+        None => Ok(puzzle),
+        Some((width, height)) if (width, height) == (puzzle.width, puzzle.height) || (height, width) == (puzzle.width, puzzle.height) => {
+            Ok(Puzzle { width, height, ..puzzle })
+        }
+        Some((width, height)) => Err(format!("Tile set is for a {}x{} board, not {}x{}", puzzle.width, puzzle.height, width, height)),
+    }
+}
 
-    // Spawn 8 different threads to create_mega_tiles();
-    // If any one thread completes, stop execution
+fn fixed_pieces_for(tiles: &[Tile], puzzle: &Puzzle, options: &cli::CliOptions) -> Result<Vec<FixedPiece>, String> {
+    if options.use_hints && !puzzle.is_eternity_ii() {
+        return Err(String::from("The clue pieces only exist in the real puzzle"));
+    }
+    let config = match &options.config {
+        Some(path) => PuzzleConfig::load(path)?,
+        None => PuzzleConfig::default_for(puzzle, options.use_hints),
+    };
+    config.check(tiles, puzzle)?;
+    Ok(config.fixed_pieces)
+}
 
-    create_mega_tiles();
+// brute_force doesn't care about borders, it just needs a square grid with one piece per square
+fn brute_force_setup(tiles: &[Tile], options: &cli::CliOptions) -> Result<(usize, OrientedTile), String> {
+    let grid_size = match options.size {
+        Some((width, height)) if width == height => width,
+        Some((width, height)) => return Err(format!("Brute force needs a square board, not {}x{}", width, height)),
+        None => (1..=tiles.len()).find(|size| size * size >= tiles.len()).unwrap_or(0),
+    };
+    if grid_size * grid_size != tiles.len() {
+        return Err(format!("{} pieces can't fill a {}x{} board", tiles.len(), grid_size, grid_size));
+    }
+    // The spiral starts from the centre square, so that's the only place a fixed piece can go
+    let config_centre = match &options.config {
+        Some(path) => match PuzzleConfig::load(path)?.fixed_pieces[..] {
+            [fixed] if fixed.row == grid_size / 2 && fixed.col == grid_size / 2 && options.centre.is_none() => Some((fixed.id, fixed.orientation)),
+            _ => return Err(format!("Brute force needs the config to fix a single piece at row {} col {} and no --centre", grid_size / 2, grid_size / 2)),
+        },
+        None => None,
+    };
+    let (id, orientation) = config_centre.or(options.centre).unwrap_or((tiles.len() / 2, Orientation::Deg0));
+    if id >= tiles.len() {
+        return Err(format!("There's no piece {} in a set of {}", id, tiles.len()));
+    }
+    Ok((grid_size, OrientedTile::new(id, tiles, orientation)))
 }
 
+fn output_solution(solution: &Option<solver::Board>, options: &cli::CliOptions) -> Result<(), String> {
+    match solution {
+        Some(grid) => output_board(grid, options), // We did it!
+        None => {
+            println!("Failed to find a solution :(");
+            Ok(())
+        }
+    }
+}
 
-fn get_adjacent_sides(grid: &Vec<Vec<Option<OrientedTile>>>, x: usize, y: usize) -> (i8, i8, i8, i8) {
-    let mut adjacent_sides = (-1, -1, -1, -1);
+// Prints the board and saves it too if --output was given
+fn output_board(grid: &solver::Board, options: &cli::CliOptions) -> Result<(), String> {
+    let style = svg_style(options)?;
+    print_board(grid, options.format, &style);
+    if let Some(path) = &options.output {
+        save_board_as(grid, path, &style)?;
+        println!("Saved board to {}", path.display());
+    }
+    Ok(())
+}
+
+// As a picture or a board file, going by the extension
+fn save_board_as(grid: &solver::Board, path: &Path, style: &SvgStyle) -> Result<(), String> {
+    if is_svg_path(path) {
+        save_svg(&board_to_svg(grid, style), path)
+    } else if let Some(format) = raster_format(path) {
+        save_image(&render_board(grid, &style.palette), path, format)
+    } else {
+        save_board(grid, path)
+    }
+}
+
+// Once the rim is fitted or the interior arranged there's a board to show. Before that, the mega-tiles get saved as a
+// picture if --output was given, there's no file format to load them back from.
+fn output_mega_tiles(result: &mega::MegaTileResult, puzzle: &Puzzle, options: &cli::CliOptions) -> Result<(), String> {
+    if let Some(board) = result.board(puzzle) {
+        return output_board(&board, options);
+    }
+    let mega_tiles = &result.mega_tiles;
+    if let Some(path) = &options.output {
+        let style = svg_style(options)?;
+        if is_svg_path(path) {
+            save_svg(&mega_tiles_to_svg(mega_tiles, &style), path)?;
+        } else if let Some(format) = raster_format(path) {
+            save_image(&render_mega_tiles(mega_tiles, &style.palette), path, format)?;
+        } else {
+            return Err(format!("Mega-tiles can only be saved as a picture (.svg, .png or .ppm), not {}", path.display()));
+        }
+        println!("Saved {} mega-tiles to {}", mega_tiles.len(), path.display());
+    }
+    Ok(())
+}
+
+// Cursor movement would make a mess of anything that isn't a terminal, so those just get the usual log lines
+fn dashboard_for(options: &cli::CliOptions, title: &str, target_depth: usize) -> Dashboard {
+    if !options.dashboard {
+        return Dashboard::disabled();
+    }
+    if !std::io::stdout().is_terminal() {
+        println!("Output isn't a terminal, carrying on without the dashboard");
+        return Dashboard::disabled();
+    }
+    Dashboard::new(title, target_depth)
+}
+
+fn svg_style(options: &cli::CliOptions) -> Result<SvgStyle, String> {
+    let palette = match &options.palette {
+        Some(path) => Palette::load(path)?,
+        None => Palette::default(),
+    };
+    Ok(SvgStyle { palette, show_ids: options.show_ids })
+}
+
+fn resume_search(path: &Path, options: &cli::CliOptions) -> Result<(), String> {
+    let checkpoint = Checkpoint::load(path)?;
+    // Without --tiles, find whichever built in set the checkpoint was written for
+    let tile_set = match &options.tiles {
+        Some(_) => load_tiles(options)?,
+        None => [&TILE_SET[..], &MINI_TILE_SET[..]].into_iter().find(|tiles| checkpoint.matches_tiles(tiles)).unwrap_or(&[]).to_vec(),
+    };
+    if !checkpoint.matches_tiles(&tile_set) {
+        return Err(format!("{} was written for a different tile set, pass the one it started with using --tiles", path.display()));
+    }
+
+    println!("Resuming {} from seed {}", path.display(), checkpoint.seed);
+    match checkpoint.kind.clone() {
+        SearchKind::MegaTiles { puzzle, fixed_pieces } => {
+            let mut dashboard = dashboard_for(options, &format!("Resuming mega-tiles, seed {}", checkpoint.seed), puzzle.mega_tile_count());
+            let result = mega::create_mega_tiles_with_checkpoints(&tile_set, &puzzle, &fixed_pieces, &mut Checkpointer::resume(path, DEFAULT_CHECKPOINT_INTERVAL, checkpoint), &mut dashboard)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            output_mega_tiles(&result, &puzzle, options)?;
+        }
+        SearchKind::BruteForce { grid_size, centre } => {
+            let centre_tile = OrientedTile::new(centre.0, &tile_set, centre.1);
+            let mut checkpointer = Checkpointer::resume(path, DEFAULT_CHECKPOINT_INTERVAL, checkpoint);
+            let (solution, _) = brute_force(grid_size, &tile_set, centre_tile, &mut checkpointer);
+            checkpointer.result().map_err(|e| format!("{}: {}", path.display(), e))?;
+            output_solution(&solution, options)?;
+        }
+    }
+    Ok(())
+}
+
+fn get_adjacent_sides(grid: &[Vec<Option<OrientedTile>>], x: usize, y: usize) -> (i8, i8, i8, i8) {
+    let mut adjacent_sides = (ANY_SIDE, ANY_SIDE, ANY_SIDE, ANY_SIDE);
     if y > 0 && grid[y - 1][x].is_some() { adjacent_sides.0 = grid[y - 1][x].unwrap().bottom(); }
     if x < grid[0].len() - 1 && grid[y][x + 1].is_some() { adjacent_sides.1 = grid[y][x + 1].unwrap().left(); }
     if y < grid.len() - 1 && grid[y + 1][x].is_some() { adjacent_sides.2 = grid[y + 1][x].unwrap().top(); }
@@ -41,58 +367,181 @@ fn get_adjacent_sides(grid: &Vec<Vec<Option<OrientedTile>>>, x: usize, y: usize)
     adjacent_sides
 }
 
-fn brute_force(grid_size: usize, tile_set: &[Tile], centre_tile: OrientedTile) {
+// Also returns how many pieces it placed, counting the ones before a checkpoint it resumed from
+fn brute_force(grid_size: usize, tile_set: &[Tile], centre_tile: OrientedTile, checkpointer: &mut Checkpointer) -> (Option<solver::Board>, u64) {
     let mut grid = vec![vec![None; grid_size]; grid_size];
     let tile_lookup = build_indices(tile_set, 0);
 
     grid[grid_size / 2][grid_size / 2] = Some(centre_tile);
 
-    let mut available_mask = Vec::new();
-    for i in 0..tile_set.len() { available_mask.push(i == centre_tile.id()); }
+    let mut available = PieceSet::first(tile_set.len());
+    available.remove(centre_tile.id());
 
 
     let search_order = generate_spiral_search_order(grid_size);
+    let search = BruteForceSearch { tile_lookup: &tile_lookup, search_order: &search_order };
 
-    if brute_force_recursive(&mut grid, &mut available_mask, tile_set, &tile_lookup, tile_set, &search_order, 1) {
-        print_grid(&grid); // We did it!
-    } else {
-        println!("Failed to find a solution :(");
-    }
+    let mut candidate_path = Vec::new();
+    let mut nodes = checkpointer.nodes_so_far();
+    let found = brute_force_recursive(&mut grid, &mut available, &search, 1, checkpointer, &mut candidate_path, &mut nodes);
+    println!("Brute force placed {} pieces", nodes);
+    (if found { Some(grid) } else { None }, nodes)
 }
 
-fn brute_force_recursive(grid: &mut Vec<Vec<Option<OrientedTile>>>,
-                         available_mask: &mut [bool], tileset: &[Tile],
-                         tile_lookup: &HashMap<(i8, i8, i8, i8), Vec<OrientedTile>>,
-                         tile_set: &[Tile],
-                         search_order: &[(usize, usize)],
-                         search_index: usize) -> bool
+// What stays the same all the way down a brute force search
+struct BruteForceSearch<'a> {
+    tile_lookup: &'a TileLookup,
+    search_order: &'a [(usize, usize)],
+}
+
+fn brute_force_recursive(grid: &mut [Vec<Option<OrientedTile>>],
+                         available: &mut PieceSet,
+                         search: &BruteForceSearch,
+                         search_index: usize,
+                         checkpointer: &mut Checkpointer,
+                         candidate_path: &mut Vec<usize>,
+                         nodes: &mut u64) -> bool
 {
     // Found a complete solution! return early
-    if search_index == search_order.len() { return true; }
+    if search_index == search.search_order.len() { return true; }
+    if checkpointer.stopped() { return false; }
+
+    // The centre piece is fixed, so checkpoint depth 0 is the second square in the search order
+    let depth = search_index - 1;
+    checkpointer.finish_resume(depth);
+
+    let (x, y) = search.search_order[search_index];
+    let (top, right, bottom, left) = get_adjacent_sides(grid, y, x);
+    let possible_tiles = search.tile_lookup.get(top, right, bottom, left);
+
+    // Skip straight to the candidate the checkpoint was on
+    let first_candidate = match checkpointer.resume_frame(depth) {
+        Some(frame) if possible_tiles.get(frame.candidate_index).is_some_and(|t| frame.pieces == [(t.id(), t.orientation())]) => frame.candidate_index,
+        Some(_) => {
+            checkpointer.fail_resume(depth);
+            return false;
+        }
+        None => 0,
+    };
 
-    let (x, y) = search_order[search_index];
-    let possible_tiles_opt = tile_lookup.get(&get_adjacent_sides(grid, y, x));
+    // If nothing free fits we backtrack straight away
+    if !search.tile_lookup.pieces(top, right, bottom, left).intersects(available) { return false; }
+    for (candidate_index, oriented_tile) in possible_tiles.iter().enumerate().skip(first_candidate) {
+        if !available.contains(oriented_tile.id()) { continue; }
+        grid[x][y] = Some(*oriented_tile);
+        available.remove(oriented_tile.id());
 
-    // We can't keep going... Backtrack time
-    if possible_tiles_opt.is_none() { return false; }
+        candidate_path.truncate(depth);
+        candidate_path.push(candidate_index);
+        if !checkpointer.is_resuming() { *nodes += 1; } // Pieces replayed from a checkpoint were already counted
+        if checkpointer.save_due_after(*nodes) {
+            checkpointer.save_frames(brute_force_frames(grid, search.search_order, candidate_path), *nodes, 0);
+        }
 
-    for oriented_tile in possible_tiles_opt.unwrap() {
-        grid[x][y] = Some(*oriented_tile);
-        available_mask[oriented_tile.id() as usize] = false;
-        if brute_force_recursive(grid, available_mask, tileset, tile_lookup, tile_set, search_order, search_index + 1) {
+        if brute_force_recursive(grid, available, search, search_index + 1, checkpointer, candidate_path, nodes) {
             return true;
         }
-        available_mask[oriented_tile.id() as usize] = true;
+        available.insert(oriented_tile.id());
         grid[x][y] = None;
     }
     false // None of the branches worked
 }
 
-fn add_mega_tile_to_available_mask(mega_tile: &MegaTile, available_mask: &mut [bool; 256]) {
-    available_mask[mega_tile.tiles[0].id()] = false;
-    available_mask[mega_tile.tiles[1].id()] = false;
-    available_mask[mega_tile.tiles[2].id()] = false;
-    available_mask[mega_tile.tiles[3].id()] = false;
+// The squares after the centre, each with the candidate index that put its piece there
+fn brute_force_frames(grid: &[Vec<Option<OrientedTile>>], search_order: &[(usize, usize)], candidate_path: &[usize]) -> Vec<CheckpointFrame> {
+    candidate_path.iter().zip(search_order[1..].iter()).map(|(candidate_index, (x, y))| {
+        let oriented_tile = grid[*x][*y].unwrap();
+        CheckpointFrame { candidate_index: *candidate_index, rng_position: 0, pieces: vec![(oriented_tile.id(), oriented_tile.orientation())] }
+    }).collect()
+}
+
+// Exhaustive version of brute_force that spreads the tree over several threads.
+// Every way of filling the first split_depth squares after the centre becomes a work unit, and the threads take units
+// off the list until it's empty. Each unit is searched to the bottom so the whole tree gets covered exactly once.
+fn brute_force_split(grid_size: usize, tile_set: &[Tile], centre_tile: OrientedTile, split_depth: usize, threads: usize) -> u64 {
+    let mut grid = vec![vec![None; grid_size]; grid_size];
+    let tile_lookup = build_indices(tile_set, 0);
+    grid[grid_size / 2][grid_size / 2] = Some(centre_tile);
+
+    let mut available = PieceSet::first(tile_set.len());
+    available.remove(centre_tile.id());
+
+    let search_order = generate_spiral_search_order(grid_size);
+    let split_index = (1 + split_depth).min(search_order.len());
+    let mut units = Vec::new();
+    let search = BruteForceSearch { tile_lookup: &tile_lookup, search_order: &search_order };
+    enumerate_brute_force_prefixes(&mut grid, &mut available, &search, 1, split_index, &mut Vec::new(), &mut units);
+    println!("Split the search into {} work units at depth {} on {} threads", units.len(), split_index - 1, threads);
+
+    let cancel = AtomicBool::new(false);
+    let unit_stats = run_work_units(threads, &units, &cancel, |prefix, _| {
+        let mut grid = grid.clone();
+        let mut available = available;
+        for (oriented_tile, (x, y)) in prefix.iter().zip(search_order[1..].iter()) {
+            grid[*x][*y] = Some(*oriented_tile);
+            available.remove(oriented_tile.id());
+        }
+        let mut stats = WorkUnitStats::default();
+        count_brute_force_solutions(&mut grid, &mut available, &search, 1 + prefix.len(), &mut stats);
+        stats
+    });
+
+    let unit_stats: Vec<WorkUnitStats> = unit_stats.into_iter().flatten().collect();
+    print_work_unit_report(&unit_stats);
+    unit_stats.iter().map(|stats| stats.solutions).sum()
+}
+
+// Collects every partial placement of the squares from search_index up to split_index
+fn enumerate_brute_force_prefixes(grid: &mut [Vec<Option<OrientedTile>>],
+                                  available: &mut PieceSet,
+                                  search: &BruteForceSearch,
+                                  search_index: usize,
+                                  split_index: usize,
+                                  prefix: &mut Vec<OrientedTile>,
+                                  units: &mut Vec<Vec<OrientedTile>>)
+{
+    if search_index == split_index {
+        units.push(prefix.clone());
+        return;
+    }
+
+    let (x, y) = search.search_order[search_index];
+    let (top, right, bottom, left) = get_adjacent_sides(grid, y, x);
+    for oriented_tile in search.tile_lookup.get(top, right, bottom, left) {
+        if !available.contains(oriented_tile.id()) { continue; }
+        grid[x][y] = Some(*oriented_tile);
+        available.remove(oriented_tile.id());
+        prefix.push(*oriented_tile);
+        enumerate_brute_force_prefixes(grid, available, search, search_index + 1, split_index, prefix, units);
+        prefix.pop();
+        available.insert(oriented_tile.id());
+        grid[x][y] = None;
+    }
+}
+
+// Same walk as brute_force_recursive, but it keeps going after a solution so every branch gets counted
+fn count_brute_force_solutions(grid: &mut [Vec<Option<OrientedTile>>],
+                               available: &mut PieceSet,
+                               search: &BruteForceSearch,
+                               search_index: usize,
+                               stats: &mut WorkUnitStats)
+{
+    if search_index == search.search_order.len() {
+        stats.solutions += 1;
+        return;
+    }
+
+    let (x, y) = search.search_order[search_index];
+    let (top, right, bottom, left) = get_adjacent_sides(grid, y, x);
+    for oriented_tile in search.tile_lookup.get(top, right, bottom, left) {
+        if !available.contains(oriented_tile.id()) { continue; }
+        grid[x][y] = Some(*oriented_tile);
+        available.remove(oriented_tile.id());
+        stats.nodes += 1;
+        count_brute_force_solutions(grid, available, search, search_index + 1, stats);
+        available.insert(oriented_tile.id());
+        grid[x][y] = None;
+    }
 }
 
 // fn add_to_megatile_side_counts(mega_tile: &MegaTile, megatile_edges_count: &mut HashMap<(i8, i8), i32>) {
@@ -228,287 +677,3 @@ fn add_mega_tile_to_available_mask(mega_tile: &MegaTile, available_mask: &mut [b
 //     //
 //     // mega_tiles
 // }
-
-fn try_build_mega_tile(top_left_tile: &OrientedTile,
-                       bigram_count: &HashMap<(i8, i8), i32>,
-                       index_by_edge: &HashMap<i8, Vec<usize>>,
-                       index_by_bigram: &HashMap<(i8, i8), Vec<usize>>,
-                       available_mask: &[bool; 256],
-                       mega_tile_edges_without_counterpart: &Vec<(i8, i8)>) -> Option<MegaTile>
-{
-    None
-    //
-    // // // For debugging see whats in those index by things
-    // // for (k, v) in index_by_bigram.iter() {
-    // //     if v.len() > 1 {
-    // //         println!("({},{}) {:?}", k.0, k.1, v);
-    // //     }
-    // // }
-    // //
-    // // for (k, v) in index_by_edge.iter() {
-    // //     if v.len() > 1 {
-    // //         println!("{}: {:?}", k, v);
-    // //     }
-    // // }
-    //
-    // // Available mask shouldn't include the top left tile
-    // let mut available_mask = available_mask.clone();
-    // available_mask[top_left_tile.id()] = false;
-    //
-    // // The mega tile will need a combined top edge that matches something in the mega_tile_edges_without_counterpart but backwards
-    // // Filter the edges that need friends down to ones whose second element matches the top edge of the top left tile
-    // let mega_tile_top_edges = mega_tile_edges_without_counterpart.iter()
-    //     .filter(|(a, b)| *b == top_left_tile.top())
-    //     .collect::<Vec<_>>();
-    //
-    // // oriented tiles that could be the top right tile
-    // let mut top_right_candidates = Vec::new();
-    //
-    // for top_edge_to_match in mega_tile_top_edges {
-    //     // top_edge_to_match.1 should be the same as top_left_tile.top()
-    //     // top_edge_to_match.0 is the one we want on the top of the next tile
-    //     let bigram_needed_for_top_right_tile: (i8, i8) = (top_left_tile.right(), top_edge_to_match.0);
-    //     if !index_by_bigram.contains_key(&bigram_needed_for_top_right_tile) {
-    //         continue;
-    //     }
-    //     for top_right_tile_index in index_by_bigram.get(&bigram_needed_for_top_right_tile).unwrap() {
-    //         if !available_mask[*top_right_tile_index] {
-    //             continue;
-    //         }
-    //         let mask: Tile = Tile { top: top_edge_to_match.0, right: -1, bottom: -1, left: top_left_tile.right(), id: usize::MAX };
-    //         panic!("FIX THIS");
-    //         // let tiles_that_match_mask = find_orientations_for_tile_id_from_mask(*top_right_tile_index, &mask);
-    //         // for top_right_tile in tiles_that_match_mask {
-    //         //     top_right_candidates.push(top_right_tile);
-    //         // }
-    //     }
-    // }
-    //
-    // // println!("Found {} Tier 1 top right candidates", top_right_candidates.len());
-    //
-    // // Fall back and don't worry about the top edge if we haven't found something
-    // if top_right_candidates.len() == 0 {
-    //     if !index_by_edge.contains_key(&top_left_tile.right()) {
-    //         return None;
-    //     }
-    //     for top_right_tile_index in index_by_edge.get(&top_left_tile.right()).unwrap() {
-    //         if !available_mask[*top_right_tile_index] {
-    //             continue;
-    //         }
-    //         let mask: Tile = Tile { top: -1, right: -1, bottom: -1, left: top_left_tile.right(), id: usize::MAX };
-    //         panic!("FIX THIS");
-    //         // let tiles_that_match_mask = find_orientations_for_tile_id_from_mask(*top_right_tile_index, &mask);
-    //         // for top_right_tile in tiles_that_match_mask {
-    //         //     top_right_candidates.push(top_right_tile);
-    //         // }
-    //     }
-    //     // println!("Found {} Tier 2 top right candidates", top_right_candidates.len());
-    // }
-    //
-    // // The mega tile will need a combined left edge that matches something in the mega_tile_edges_without_counterpart but backwards
-    // // Filter the edges that need friends down to ones whose first element matches the left edge of the top left tile
-    // let mega_tile_left_edges = mega_tile_edges_without_counterpart.iter()
-    //     .filter(|(a, b)| *a == top_left_tile.left())
-    //     .collect::<Vec<_>>();
-    //
-    // // oriented tiles that could be the bottom left tile
-    // let mut bottom_left_candidates = Vec::new();
-    //
-    // for left_edge_to_match in mega_tile_left_edges {
-    //     // left_edge_to_match.0 should be the same as top_left_tile.left()
-    //     // left_edge_to_match.1 is the one we want on the left of the next tile
-    //     let bigram_needed_for_bottom_left_tile: (i8, i8) = (left_edge_to_match.1, top_left_tile.bottom());
-    //     if !index_by_bigram.contains_key(&bigram_needed_for_bottom_left_tile) {
-    //         continue;
-    //     }
-    //     for bottom_left_tile_index in index_by_bigram.get(&bigram_needed_for_bottom_left_tile).unwrap() {
-    //         if !available_mask[*bottom_left_tile_index] {
-    //             continue;
-    //         }
-    //         let mask: Tile = Tile { top: top_left_tile.bottom(), right: -1, bottom: -1, left: left_edge_to_match.1, id: usize::MAX };
-    //         panic!("FIX THIS");
-    //         // let tiles_that_match_mask = find_orientations_for_tile_id_from_mask(*bottom_left_tile_index, &mask);
-    //         // for bottom_left_tile in tiles_that_match_mask {
-    //         //     bottom_left_candidates.push(bottom_left_tile);
-    //         // }
-    //     }
-    // }
-    //
-    // // println!("Found {} Tier 1 bottom left candidates", bottom_left_candidates.len());
-    //
-    // // Fall back and don't worry about the left edge if we haven't found something
-    //
-    // if bottom_left_candidates.len() == 0 {
-    //     if !index_by_edge.contains_key(&top_left_tile.bottom()) {
-    //         return None;
-    //     }
-    //     for bottom_left_tile_index in index_by_edge.get(&top_left_tile.bottom()).unwrap() {
-    //         if !available_mask[*bottom_left_tile_index] {
-    //             continue;
-    //         }
-    //         let mask: Tile = Tile { top: top_left_tile.bottom(), right: -1, bottom: -1, left: -1, id: usize::MAX };
-    //         panic!("FIX THIS");
-    //         // let tiles_that_match_mask = find_orientations_for_tile_id_from_mask(*bottom_left_tile_index, &mask);
-    //         // for bottom_left_tile in tiles_that_match_mask {
-    //         //     bottom_left_candidates.push(bottom_left_tile);
-    //         // }
-    //     }
-    //     // println!("Found {} Tier 2 bottom left candidates", bottom_left_candidates.len());
-    // }
-    //
-    // // Now we have a list of top right and bottom left tiles that could work
-    // // We need to find a pair that works
-    // // Tier 1 - We find a fourth tile that matches with both a top right and bottom left tile
-    // for top_right_tile in top_right_candidates {
-    //     for bottom_left_tile in &bottom_left_candidates {
-    //         // skip over cases where the tiles are the same
-    //         if top_right_tile.id() == bottom_left_tile.id() {
-    //             continue;
-    //         }
-    //         // Find a tile to go in the bottom right
-    //         let bi_gram_needed_for_bottom_right_tile: (i8, i8) = (bottom_left_tile.right(), top_right_tile.bottom());
-    //         if !index_by_bigram.contains_key(&bi_gram_needed_for_bottom_right_tile) {
-    //             continue;
-    //         }
-    //         for bottom_right_tile_index in index_by_bigram.get(&bi_gram_needed_for_bottom_right_tile).unwrap() {
-    //             if !available_mask[*bottom_right_tile_index] || *bottom_right_tile_index == top_right_tile.id() || *bottom_right_tile_index == bottom_left_tile.id(){
-    //                 continue;
-    //             }
-    //             let mask: Tile = Tile { top: top_right_tile.bottom(), right: -1, bottom: -1, left: bottom_left_tile.right(), id: usize::MAX };
-    //             panic!("FIX THIS");
-    //             // let tiles_that_match_mask = find_orientations_for_tile_id_from_mask(*bottom_right_tile_index, &mask);
-    //             // for bottom_right_tile in tiles_that_match_mask {
-    //             //     let mega_tile = MegaTile { tile1: *top_left_tile, tile2: top_right_tile, tile3: *bottom_left_tile, tile4: bottom_right_tile };
-    //             //     visualise_megatile(&mega_tile);
-    //             //     return Some(mega_tile);
-    //             // }
-    //         }
-    //     }
-    // }
-    // // For now, just return None
-    // None
-}
-
-
-fn find_top_left_oriented_tiles_that_matches_two_mega_tile_edges(mega_tile_edges_without_counterpart: &Vec<(i8, i8)>,
-                                                                 index_by_bigram: &HashMap<(i8, i8), Vec<usize>>,
-                                                                 available_mask: &[bool; 256]) -> Vec<OrientedTile>
-{
-    // The goal here is to find a tile that can go in the top left of a mega tile
-    // Since this tile is in the top left, the top side will have to match the first part of an edge
-    // and the left side will have to match the second part of an edge
-    // We want to find a tile that matches some combination of the edges that are currently unmatched
-
-    // bi-gram combinations that we should search for in the index
-    let mut bigrams_to_search = Vec::new();
-
-    // Cross multiply the two parts of the mega_tile_edges_without_counterpart, exclude i = j
-    for i in 0..mega_tile_edges_without_counterpart.len() {
-        for j in 0..mega_tile_edges_without_counterpart.len() {
-            if i != j {
-                bigrams_to_search.push((mega_tile_edges_without_counterpart[i].0, mega_tile_edges_without_counterpart[j].1));
-            }
-        }
-    }
-
-    // Now search the index for the tiles that match the bigram combinations
-    let mut tiles_that_could_match_two_mega_tile_edges = Vec::new();
-    for bigram in bigrams_to_search {
-        if let Some(matching_tile_ids) = index_by_bigram.get(&bigram) {
-            for tile_id in matching_tile_ids {
-                if available_mask[*tile_id] {
-                    // Try out all of the four possible orientations
-                    for orientation in 0..4 {
-                        let oriented_tile = OrientedTile::new(*tile_id, &TILE_SET, orientation);
-                        if oriented_tile.left() == bigram.0 && oriented_tile.top() == bigram.1 {
-                            tiles_that_could_match_two_mega_tile_edges.push(oriented_tile);
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    tiles_that_could_match_two_mega_tile_edges
-}
-
-fn find_top_left_oriented_tiles_that_matches_one_mega_tile_edge(mega_tile_edges_without_counterpart: &Vec<(i8, i8)>,
-                                                                index_by_edge: &HashMap<i8, Vec<usize>>,
-                                                                available_mask: &[bool; 256]) -> Vec<OrientedTile>
-{
-    // The goal here is to find a tile that can go in the top left of a mega tile
-    // Since this tile is in the top left, it's left side will match the first part of an edge
-    // and it's top side will match the second part of a bigram
-
-    // Check firest for matches to the first part of the edge
-    let mut tiles_that_could_match_one_mega_tile_edge = Vec::new();
-    for edge in mega_tile_edges_without_counterpart {
-        if let Some(matching_tile_ids) = index_by_edge.get(&edge.0) {
-            for tile_id in matching_tile_ids {
-                if available_mask[*tile_id] {
-                    // Try out all of the four possible orientations
-                    for orientation in 0..4 {
-                        let oriented_tile = OrientedTile::new(*tile_id, &TILE_SET, orientation);
-                        if oriented_tile.left() == edge.0 {
-                            tiles_that_could_match_one_mega_tile_edge.push(oriented_tile);
-                        } else if oriented_tile.top() == edge.1 {
-                            tiles_that_could_match_one_mega_tile_edge.push(oriented_tile);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    tiles_that_could_match_one_mega_tile_edge
-}
-
-
-fn filter_to_unmatched_megatile_edges(megatile_edges_count: &HashMap<(i8, i8), i32>) -> Vec<(i8, i8)> {
-    // This function finds megatile edges that don't have a counterpart.
-    // A counterpart for the edge (F,G) is the edge (G,F)
-
-    // Print the megatile edges and their counts
-    for (edge, count) in megatile_edges_count {
-        // Print the edge as a char + 65
-        // println!("Edge: {}, {}, Count: {}", (edge.0 + 65) as u8 as char, (edge.1 + 65) as u8 as char, count);
-    }
-
-    let mut edges_without_counterpart = Vec::new();
-    for (edge, count) in megatile_edges_count {
-        // Check for identical twins e.g (T,T)
-        if edge.0 == edge.1 && count % 2 == 1 {
-            edges_without_counterpart.push(*edge);
-            continue;
-        }
-
-        // Check for the edge's counterpart
-        let counterpart = (edge.1, edge.0);
-        // We will flag the current edge as unmatched if there are fewer of its counterpart than the current edge
-        if let Some(counterpart_count) = megatile_edges_count.get(&counterpart) {
-            if count > counterpart_count {
-                edges_without_counterpart.push(*edge);
-            }
-        } else {
-            edges_without_counterpart.push(*edge);
-        }
-    }
-    // Build a string to print the edges without a counterpart
-    let mut edges_without_counterpart_string = String::new();
-    for edge in &edges_without_counterpart {
-        edges_without_counterpart_string.push_str(&format!("({}, {}), ", (edge.0 + 65) as u8 as char, (edge.1 + 65) as u8 as char));
-    }
-    println!("Edges without a counterpart: {}", edges_without_counterpart_string);
-    edges_without_counterpart
-}
-
-fn find_required_outside_matches(grid: &[[OrientedTile; 16]; 16]) -> [i32; 22]{
-    let mut required_outside_matches = [0; 22];
-    for i in 1..15 {
-        required_outside_matches[grid[0][i].tile_as_rotated().bottom as usize - 1] += 1;
-        required_outside_matches[grid[15][i].tile_as_rotated().top as usize - 1] += 1;
-        required_outside_matches[grid[i][0].tile_as_rotated().right as usize - 1] += 1;
-        required_outside_matches[grid[i][15].tile_as_rotated().left as usize - 1] += 1;
-    }
-    required_outside_matches
-}
-
@@ -1,47 +1,220 @@
-use std::collections::HashMap;
-use rand::Rng;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use crate::block::*;
+use crate::checkpoint::*;
 use crate::data::*;
+use crate::dashboard::*;
 use crate::display::*;
 use crate::helper::*;
+use crate::megadb::*;
+use crate::megagrid::*;
+use crate::rim::*;
+use crate::solver::{Board, SearchStats};
+
+// Step 1's set, where everything goes if Step 2 could fit the rim round it, and Step 3's arrangement of the interior
+pub struct MegaTileResult {
+    pub mega_tiles: Vec<MegaTile>,
+    pub rim_fit: Option<RimFit>,
+    pub arrangement: Option<MegaGrid>,
+}
+
+impl MegaTileResult {
+    // As much of a board as the steps got to, None if they didn't get as far as placing anything
+    pub fn board(&self, puzzle: &Puzzle) -> Option<Board> {
+        match (&self.arrangement, &self.rim_fit) {
+            (Some(arrangement), Some(rim_fit)) => Some(rim_fit.around(arrangement, puzzle)),
+            (Some(arrangement), None) => Some(expand_mega_grid(arrangement, puzzle)),
+            (None, Some(rim_fit)) => Some(rim_fit.to_board(puzzle)),
+            (None, None) => None,
+        }
+    }
+}
 
-pub fn create_mega_tiles() -> Vec<MegaTile> {
-    // Creates a set of 49 mega-tiles that could potentially be used in a solution
+pub fn create_mega_tiles(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], mega_db: Option<&MegaTileDb>, seed: u64, dashboard: &mut Dashboard) -> MegaTileResult {
+    // Creates a set of mega-tiles (49 for the real puzzle) that could potentially be used in a solution
+    print_random_data(tiles, puzzle);
 
     // Step 1 - Spawn a bunch of valid mega-tiles, trying to get good repetition in edge types
-    let mega_tiles = generate_initial_mega_tiles();
+    let mut rng = SearchRng::seed_from_u64(seed);
+    let mut run = StepOneRun { rng: &mut rng, cancel: &AtomicBool::new(false), stats: &mut SearchStats::default(), checkpointer: &mut Checkpointer::disabled(), dashboard };
+    let mega_tiles = generate_initial_mega_tiles(tiles, puzzle, fixed_pieces, mega_db, &mut run);
+    finish_mega_tiles(mega_tiles, tiles, puzzle, fixed_pieces, seed, dashboard)
+}
+
+// Same as create_mega_tiles but saves its progress as it goes, or picks up from a checkpoint if the checkpointer was resumed.
+// The seed comes from the checkpoint so a resumed run makes the same random choices as the original.
+// Candidates are always built rather than looked up, a checkpoint only replays if they come out the same way again.
+// Fails if it doesn't, rather than carrying on from somewhere else.
+pub fn create_mega_tiles_with_checkpoints(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], checkpointer: &mut Checkpointer, dashboard: &mut Dashboard) -> Result<MegaTileResult, String> {
+    print_random_data(tiles, puzzle);
+
+    let seed = checkpointer.checkpoint().ok_or("checkpoints are switched off")?.seed;
+    let mut rng = SearchRng::seed_from_u64(seed);
+    let mut stats = SearchStats { nodes: checkpointer.nodes_so_far(), deepest: 0 };
+    let mut run = StepOneRun { rng: &mut rng, cancel: &AtomicBool::new(false), stats: &mut stats, checkpointer, dashboard };
+    let mega_tiles = generate_initial_mega_tiles(tiles, puzzle, fixed_pieces, None, &mut run);
+    checkpointer.result()?;
+    Ok(finish_mega_tiles(mega_tiles, tiles, puzzle, fixed_pieces, seed, dashboard))
+}
+
+// Everything after Step 1. Split out so the parallel driver can hand over whichever thread's set came back first.
+// seed drives Steps 2 and 3's random choices, so the same seed fits the rim and arranges the interior the same way.
+pub fn finish_mega_tiles(mega_tiles: Vec<MegaTile>, tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], seed: u64, dashboard: &mut Dashboard) -> MegaTileResult {
+    if mega_tiles.is_empty() {
+        println!("Step 1 didn't come up with a set of mega-tiles");
+        return MegaTileResult { mega_tiles, rim_fit: None, arrangement: None };
+    }
 
     for mega_tile in mega_tiles.iter(){
         visualise_mega_tile(mega_tile);
         for tile in mega_tile.tiles.iter() {
-            println!("{}x{}: {} {} {} {}", tile.id(), tile.orientation, tile.top(), tile.right(), tile.bottom(), tile.left());
+            println!("{}x{}: {} {} {} {}", tile.id(), tile.orientation().quarter_turns(), tile.top(), tile.right(), tile.bottom(), tile.left());
         }
     }
 
     print_mega_tile_stats(&mega_tiles);
 
     // Verify mega tiles
-    verify_mega_tiles(&mega_tiles);
+    verify_mega_tiles(&mega_tiles, puzzle);
 
 
     // Step 2 - Massage the mega-tiles to make them fit the constraints of the rim pieces
+    let rim_fit = fit_rim(&mega_tiles, tiles, puzzle, fixed_pieces, seed);
 
-
-    Vec::new()
+    // Step 3 - Arrange the mega-tiles over the interior, inside the rim if there is one. Step 2 may have rebuilt some.
+    let set = rim_fit.as_ref().map_or(&mega_tiles, |rim_fit| &rim_fit.layout);
+    let rim = rim_fit.as_ref().map(|rim_fit| rim_fit.around(&[], puzzle));
+    let arrangement = solve_mega_grid(set, puzzle, fixed_pieces, rim.as_ref(), &mut SearchRng::seed_from_u64(seed), dashboard);
+    MegaTileResult { mega_tiles, rim_fit, arrangement }
 }
 
-fn verify_mega_tiles(mega_tiles: &Vec<MegaTile>){
-    // Verify that we have used all of the tiles
-    let mut used_tiles = [false; 256];
-    for mega_tile in mega_tiles.iter() {
-        for tile in mega_tile.tiles.iter() {
-            used_tiles[tile.id() as usize] = true;
+const BLOCK_CANDIDATE_LIMIT: usize = 2_000;   // Blocks enumerated at each step before they're scored
+const BLOCKS_TRIED_PER_STEP: usize = 12;      // Best scoring ones tried at each step before backing up
+const BLOCK_WORK_LIMIT: u64 = 2_000_000_000; // Pieces tried by enumerate_blocks over the whole search before giving up
+
+// Step 1 again for blocks of any shape. The set covers the interior the way plan_blocks lays it out, one block for each
+// placement in turn, scored and tracked the same way as mega-tiles. Fixed pieces go in the block covering their square.
+// Steps 2 and 3 only know about 2x2 mega-tiles, so this is as far as it goes.
+pub fn create_blocks(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], shape: BlockShape, seed: u64, dashboard: &mut Dashboard) -> Option<(Vec<BlockPlacement>, Vec<Block>)> {
+    print_random_data(tiles, puzzle);
+    let Some(plan) = plan_blocks(puzzle, shape) else {
+        println!("A {}x{} interior can't be covered by {}x{} blocks", puzzle.width - 2, puzzle.height - 2, shape.cols, shape.rows);
+        return None;
+    };
+    println!("Covering the interior with {} blocks", plan.len());
+
+    let mut presets = vec![Vec::new(); plan.len()];
+    let fixed: PieceSet = fixed_pieces.iter().map(|fixed| fixed.id).collect();
+    for fixed in fixed_pieces.iter() {
+        if let Some(i) = plan.iter().position(|placement| placement.contains(fixed.row, fixed.col)) {
+            presets[i].push((plan[i].slot(fixed.row, fixed.col), OrientedTile::new(fixed.id, tiles, fixed.orientation)));
         }
     }
+    let mut set = PartialSet::new(puzzle);
+    for fixed in fixed_pieces.iter() {
+        set.available.remove(fixed.id);
+    }
+
+    let mut builder = BlockBuilder {
+        puzzle,
+        plan,
+        presets,
+        fixed,
+        tile_lookup: build_indices(tiles, puzzle.border_piece_count()),
+        needed_sides_for_edges: count_tile_types(tiles, puzzle, true, true, false, true),
+        total_edge_count: count_tile_types(tiles, puzzle, true, true, true, true),
+        work_left: BLOCK_WORK_LIMIT,
+    };
+    let mut stats = SearchStats::default();
+    let mut rng = SearchRng::seed_from_u64(seed);
+    let mut run = StepOneRun { rng: &mut rng, cancel: &AtomicBool::new(false), stats: &mut stats, checkpointer: &mut Checkpointer::disabled(), dashboard };
+    let found = build_set_recursive(&mut builder, &mut set, &mut run);
+    dashboard.draw(&stats, &builder.picture(&set));
+    if !found {
+        let gave_up = if builder.work_left == 0 { ", gave up after trying too many pieces" } else { "" };
+        println!("Couldn't build a set of blocks. Placed {}, deepest {}/{}{}", stats.nodes, stats.deepest, builder.plan.len(), gave_up);
+        return None;
+    }
+
+    print_mega_tile_stats(&set.blocks);
+    verify_mega_tiles(&set.blocks, puzzle);
+    if let Some(i) = set.blocks.iter().position(|block| !block.holds_together()) {
+        println!("Block {} doesn't match inside", i);
+        return None;
+    }
+    Some((builder.plan, set.blocks))
+}
+
+struct BlockBuilder<'a> {
+    puzzle: &'a Puzzle,
+    plan: Vec<BlockPlacement>,
+    presets: Vec<Vec<(usize, OrientedTile)>>, // Fixed pieces for each placement, by slot
+    fixed: PieceSet,
+    tile_lookup: TileLookup,
+    needed_sides_for_edges: Vec<i32>,
+    total_edge_count: Vec<i32>,
+    work_left: u64, // Shared out over every enumerate_blocks call
+}
+
+impl SetBuilder for BlockBuilder<'_> {
+    type Block = Block;
+
+    fn set_size(&self) -> usize {
+        self.plan.len()
+    }
+
+    fn fixed(&self) -> &PieceSet {
+        &self.fixed
+    }
+
+    fn picture(&self, set: &PartialSet<Block>) -> Vec<String> {
+        board_picture(&expand_blocks(&self.plan, &set.blocks, self.puzzle))
+    }
+
+    fn is_healthy(&self, set: &PartialSet<Block>, dashboard: &mut Dashboard) -> bool {
+        self.work_left > 0 && leaves_enough_for_rim(&set.used_edge_count, &self.total_edge_count, &self.needed_sides_for_edges, self.puzzle, dashboard)
+    }
+
+    fn candidates(&mut self, set: &mut PartialSet<Block>, rng: &mut SearchRng) -> Vec<Block> {
+        // A fixed piece in the top left is the only seed. Otherwise anything, unturned for the first block.
+        let block_count = set.blocks.len();
+        let preset = &self.presets[block_count];
+        let seeds: Vec<OrientedTile> = match preset.iter().find(|(slot, _)| *slot == 0) {
+            Some((_, tile)) => vec![*tile],
+            None => {
+                let mut seeds: Vec<OrientedTile> = self.tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, ANY_SIDE).iter()
+                    .filter(|tile| set.available.contains(tile.id()) && (block_count > 0 || tile.orientation() == Orientation::Deg0))
+                    .copied()
+                    .collect();
+                seeds.shuffle(rng);
+                seeds
+            }
+        };
+        let candidates = enumerate_blocks(self.plan[block_count].shape, &seeds, preset, &set.available, &self.tile_lookup, BLOCK_CANDIDATE_LIMIT, &mut self.work_left);
+
+        // No pinned blocks saved for the end here, so no hint sides to hold back
+        let hint_sides = vec![0; self.puzzle.colour_count];
+        let edge_type_usage_score = get_edge_type_usage_score(&hint_sides, &self.needed_sides_for_edges, &set.used_edge_count, &self.total_edge_count);
+        let mut scored: Vec<(i32, Block)> = candidates.into_iter()
+            .map(|block| (score_mega_tile(&block, set, &edge_type_usage_score, rng), block))
+            .filter(|(score, _)| *score >= 0)
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.truncate(BLOCKS_TRIED_PER_STEP);
+        scored.into_iter().map(|(_, block)| block).collect()
+    }
+}
+
+fn verify_mega_tiles<B: EdgeBlock>(mega_tiles: &[B], puzzle: &Puzzle){
+    // Verify that we have used all of the tiles
+    let used_tiles: PieceSet = mega_tiles.iter().flat_map(|mega_tile| mega_tile.pieces().iter().map(|tile| tile.id())).collect();
     let mut used_tile_count = 0;
-    let max_tile_count = 49 * 4;
+    let max_tile_count = puzzle.interior_count();
     let mut unused_tile_list = Vec::new();
-    for i in 60..256 { // Ignore the corner and edge pieces
-        if used_tiles[i] {
+    for i in puzzle.border_piece_count()..puzzle.piece_count() { // Ignore the corner and edge pieces
+        if used_tiles.contains(i) {
             used_tile_count += 1;
         }else{
             println!("Tile {} was not used", i);
@@ -55,22 +228,20 @@ fn verify_mega_tiles(mega_tiles: &Vec<MegaTile>){
 
 }
 
-fn print_mega_tile_stats(mega_tiles: &Vec<MegaTile>) {
+fn print_mega_tile_stats<B: EdgeBlock>(mega_tiles: &[B]) {
     // We are most interested in the edge data
-    let mut edge_counts: HashMap<(i8, i8), usize> = HashMap::new();
+    let mut edge_counts: BTreeMap<EdgeSignature, usize> = BTreeMap::new();
     for mega_tile in mega_tiles.iter() {
-        for edge in [mega_tile.top(), mega_tile.right(), mega_tile.bottom(), mega_tile.left()].iter() {
+        for edge in mega_tile.edges().iter() {
             let count = edge_counts.entry(*edge).or_insert(0);
             *count += 1;
         }
     }
 
-    // Print the edge counts
+    // Print the edges as letters for easier reading
     println!("Edge counts:");
     for (edge, count) in edge_counts.iter() {
-        // Print the edges as letters for easier reading
-        // Need to add 65 to the char code to get the right letter
-        println!("({}, {})x{:2}", (edge.0 + 65) as u8 as char, (edge.1 + 65) as u8 as char, count);
+        println!("{}x{:2}", edge.letters(), count);
     }
 
     // Print the edge counts alongside their conjugates
@@ -80,17 +251,12 @@ fn print_mega_tile_stats(mega_tiles: &Vec<MegaTile>) {
         if conjugates_already_printed.contains(edge) {
             continue;
         }
-        let conjugate = (edge.1, edge.0);
+        let conjugate = edge.conjugate();
         // Check for symmetric tiles
-        if edge.0 == edge.1{
-            let mut line = format!("({}, {}):{:2}", (edge.0 + 65) as u8 as char, (edge.1 + 65) as u8 as char, count);
-            // Red if green otherwise
-            if count % 2 == 1 {
-                line = format!("\x1b[31m{}\x1b[0m", line);
-            } else {
-                line = format!("\x1b[32m{}\x1b[0m", line);
-            }
-            println!("{}", line);
+        if edge.is_symmetric() {
+            let line = format!("{}:{:2}", edge.letters(), count);
+            // Red if odd, green otherwise
+            println!("{}", flag(&line, count % 2 == 0));
 
             continue;
         }
@@ -98,87 +264,159 @@ fn print_mega_tile_stats(mega_tiles: &Vec<MegaTile>) {
 
 
         // We will print them like (A, B)x2 (B, A)x1 - The line will be green if the counts match, otherwise red
-        let mut line = format!("({}, {})x{:2} ({}, {})x{:2}", (edge.0 + 65) as u8 as char, (edge.1 + 65) as u8 as char, count, (edge.1 + 65) as u8 as char, (edge.0 + 65) as u8 as char, conjugate_count);
-        if count == conjugate_count {
-            line = format!("\x1b[32m{}\x1b[0m", line);
-        } else {
-            line = format!("\x1b[31m{}\x1b[0m", line);
-        }
-        println!("{}", line);
+        let line = format!("{}x{:2} {}x{:2}", edge.letters(), count, conjugate.letters(), conjugate_count);
+        println!("{}", flag(&line, count == conjugate_count));
 
 
         conjugates_already_printed.push(conjugate);
     }
 }
 
-fn generate_needed_edge_sides() -> [i32; 22]{
-    // Create some constraints for the mega_tiles based on the edge pieces
-    let required_outside_matches = count_tile_types(&TILE_SET, true, true, false, false);
-    let required_inside_matches = count_tile_types(&TILE_SET, false, false, true, false);
+// The fixed pieces that sit in the interior, grouped by the 2x2 block of the board they fall in. Each one comes with its
+// slot in the mega-tile covering that block, 0 to 3 in the same order as MegaTile::tiles.
+// The first block is built first and the rest are saved for the end, the way the clue pieces always were.
+struct PinnedBlocks {
+    blocks: Vec<Vec<(OrientedTile, usize)>>,
+    fixed: PieceSet, // Rim pieces included
+}
 
-    // Matches that shouldn't touch any edge pieces
-    let mut required_sides_for_edges = [0; 22];
-    for i in 5..22 {
-        required_sides_for_edges[i] =  required_inside_matches[i] - required_outside_matches[i];
+impl PinnedBlocks {
+    fn new(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece]) -> PinnedBlocks {
+        let mut block_positions: Vec<(usize, usize)> = Vec::new();
+        let mut blocks: Vec<Vec<(OrientedTile, usize)>> = Vec::new();
+        let fixed_ids: PieceSet = fixed_pieces.iter().map(|fixed| fixed.id).collect();
+        for fixed in fixed_pieces.iter() {
+            if fixed.row == 0 || fixed.col == 0 || fixed.row == puzzle.height - 1 || fixed.col == puzzle.width - 1 {
+                continue; // On the rim, so not part of any mega-tile
+            }
+            let position = ((fixed.row - 1) / 2, (fixed.col - 1) / 2);
+            let slot = (fixed.row - 1) % 2 * 2 + (fixed.col - 1) % 2;
+            let pinned_tile = (OrientedTile::new(fixed.id, tiles, fixed.orientation), slot);
+            match block_positions.iter().position(|p| *p == position) {
+                Some(i) => blocks[i].push(pinned_tile),
+                None => {
+                    block_positions.push(position);
+                    blocks.push(vec![pinned_tile]);
+                }
+            }
+        }
+        PinnedBlocks { blocks, fixed: fixed_ids }
     }
-    println!("Required sides for edges: {:?}", required_sides_for_edges);
-    panic!("Stop here");
-    required_sides_for_edges
 
+    // The block that has to be built as mega-tile number mega_tile_count, if any
+    fn block_for(&self, mega_tile_count: usize, last_mega_tile: usize) -> Option<&[(OrientedTile, usize)]> {
+        if self.blocks.is_empty() {
+            return None;
+        }
+        if mega_tile_count == 0 {
+            return Some(&self.blocks[0]);
+        }
+        let first_late_block = last_mega_tile + 1 - self.blocks.len();
+        if mega_tile_count >= first_late_block {
+            Some(&self.blocks[1 + mega_tile_count - first_late_block])
+        } else {
+            None
+        }
+    }
+
+    // The fixed pieces that don't get placed until the end
+    fn late_pieces(&self) -> Vec<OrientedTile> {
+        self.blocks.iter().skip(1).flatten().map(|(tile, _)| *tile).collect()
+    }
 }
 
-fn generate_initial_mega_tiles() -> Vec<MegaTile> {
-    // Recursively try to make mega-tiles until we have a good set of them
+fn set_pinned_available(pinned_block: Option<&[(OrientedTile, usize)]>, available: &mut PieceSet, free: bool) {
+    for (tile, _) in pinned_block.unwrap_or_default().iter() {
+        if free { available.insert(tile.id()) } else { available.remove(tile.id()) }
+    }
+}
 
-    print_random_data();
+// Recursively try to make mega-tiles until we have a good set of them.
+// Returns an empty list if the search runs dry or cancel gets raised.
+// Fixed pieces in the interior end up in the mega-tile covering their square, in the corner of it they sit in.
+// With a mega-tile database the candidates at each step are looked up in it instead of built from seed pieces.
+pub fn generate_initial_mega_tiles(tiles: &[Tile],
+                                   puzzle: &Puzzle,
+                                   fixed_pieces: &[FixedPiece],
+                                   mega_db: Option<&MegaTileDb>,
+                                   run: &mut StepOneRun) -> Vec<MegaTile> {
+    // Mega-tiles are 2x2 so the interior has to split evenly
+    if (puzzle.width - 2) % 2 == 1 || (puzzle.height - 2) % 2 == 1 {
+        println!("A {}x{} interior can't be covered by 2x2 mega-tiles", puzzle.width - 2, puzzle.height - 2);
+        return Vec::new();
+    }
 
-    let tiles = &TILE_SET;
-    let total_edge_count = count_tile_types(tiles, true, true, true, true);
-    let mut available_mask = [true; 256];
-    let mut mega_tiles: Vec<MegaTile> = Vec::new();
+    let pinned = PinnedBlocks::new(tiles, puzzle, fixed_pieces);
+    let mut search = MegaTileSearch {
+        tiles,
+        puzzle,
+        tile_lookup: build_indices(tiles, puzzle.border_piece_count()), // Skip the edge and corner pieces.
+        wanted_sides_for_hints: generate_wanted_hint_sides(&pinned.late_pieces(), puzzle),
+        needed_sides_for_edges: count_tile_types(tiles, puzzle, true, true, false, true),
+        total_edge_count: count_tile_types(tiles, puzzle, true, true, true, true),
+        pinned,
+        mega_db,
+    };
+
+    // Before starting, remove the fixed tiles from the available set
+    let mut set = PartialSet::new(puzzle);
+    for fixed in fixed_pieces.iter() {
+        set.available.remove(fixed.id);
+    }
+    let found = build_set_recursive(&mut search, &mut set, run);
+    run.dashboard.draw(run.stats, &mega_tile_picture(&set.blocks)); // Leave the final state on screen
+    if found { set.blocks } else { Vec::new() }
+}
+
+// What a Step 1 run carries round with it besides the puzzle
+pub struct StepOneRun<'a> {
+    pub rng: &'a mut SearchRng,
+    pub cancel: &'a AtomicBool, // Raised by another thread when it doesn't need us any more
+    pub stats: &'a mut SearchStats,
+    pub checkpointer: &'a mut Checkpointer,
+    pub dashboard: &'a mut Dashboard,
+}
+
+// What stays the same all the way through Step 1
+struct MegaTileSearch<'a> {
+    tiles: &'a [Tile],
+    puzzle: &'a Puzzle,
+    tile_lookup: TileLookup,
+    wanted_sides_for_hints: Vec<i32>,
+    needed_sides_for_edges: Vec<i32>,
+    total_edge_count: Vec<i32>,
+    pinned: PinnedBlocks,
+    mega_db: Option<&'a MegaTileDb>,
+}
 
-    let wanted_sides_for_hints = generate_wanted_hint_sides();
-    let needed_sides_for_edges = count_tile_types(&TILE_SET, true, true, false, true);
-    let tile_lookup = build_indices(tiles, 60); // Skip 60 because they are edge and corner.
-    let mut edge_usage_count = [0; 22];
+// The set built so far, with the edge counts the scoring works from
+struct PartialSet<B> {
+    blocks: Vec<B>,
+    available: PieceSet,
 
-    // Track the count of each edge variant so we can focus on pairing and repetition
-    let mut mega_tile_edge_count: HashMap<(i8, i8), usize> = HashMap::new();
+    // Track the count of each edge variant so we can focus on pairing and repetition.
+    // Kept sorted so the edge lists built from it always come out in the same order, which checkpoint replay relies on.
+    edge_count: BTreeMap<EdgeSignature, usize>,
 
     // Tier 1 - Unpaired edges
-    let mut unpaired_edges: Vec<(i8, i8)> = Vec::new();
+    unpaired_edges: Vec<EdgeSignature>,
 
     // Tier 2 - Paired edges - Second priority
-    let mut paired_edges: Vec<(i8, i8)> = Vec::new();
+    paired_edges: Vec<EdgeSignature>,
 
-    // Tier 3 - Rim optimization - Try to include pieces that appear infrequently in the rim
-    let mut rim_optimization: Vec<(i8, i8)> = Vec::new();
+    // Tier 3 - Rim optimization - Try to include pieces that appear infrequently in the rim. Not tracked yet.
 
     // Tier 4 - If it works it works. Just get the internals going.
 
-    // Before starting, remove the hint tiles from the available set
-    for tile in tiles.iter() {
-        if tile.id == 138 {
-            available_mask[tile.id as usize] = false;
-        }
-        if IS_USING_HINTS && (tile.id == 207 || tile.id == 254 || tile.id == 180 || tile.id == 248){
-            available_mask[tile.id as usize] = false;
-        }
-    }
-    build_mega_tiles_recursive(&mut available_mask, &mut mega_tiles, &mut mega_tile_edge_count, &mut unpaired_edges, &mut paired_edges, &mut rim_optimization, &tile_lookup, &wanted_sides_for_hints, &needed_sides_for_edges, &mut edge_usage_count, &total_edge_count);
-    mega_tiles
+    used_edge_count: Vec<i32>, // Sides of each colour on the pieces used so far
 }
 
-fn check_healthy_tileset(mega_tiles: &Vec<MegaTile>,
-                         mega_tile_edge_count: &HashMap<(i8, i8), usize>,
-                         unpaired_edges: &Vec<(i8, i8)>,
-                         paired_edges: &Vec<(i8, i8)>,
-                         rim_optimization: &Vec<(i8, i8)>,
-                         mega_tile_count: usize,
-                         used_edge_count:&[i32; 22],
-                         total_edge_count: &[i32; 22],
-                         needed_sides_for_edges: &[i32; 22],
+fn check_healthy_tileset(set: &PartialSet<MegaTile>,
+                         search: &MegaTileSearch,
+                         dashboard: &mut Dashboard,
 ) -> bool {
+    let mega_tile_count = set.blocks.len();
+    let unpaired_edges = &set.unpaired_edges;
 
     // Not super good
     // match mega_tile_count {
@@ -191,7 +429,7 @@ fn check_healthy_tileset(mega_tiles: &Vec<MegaTile>,
     //     40..=49 => if unpaired_edges.len() > 28 { return false; },
     //     _ => if unpaired_edges.len() > 28 { return false; },
     // }
-    let total_unique_edge_types = mega_tile_edge_count.len();
+    let total_unique_edge_types = set.edge_count.len();
 
     // Stricter count
     match mega_tile_count {
@@ -237,238 +475,354 @@ fn check_healthy_tileset(mega_tiles: &Vec<MegaTile>,
         _ => if total_unique_edge_types > 30 { return false; },
     }
 
-    // Make sure we have enough edges to fill the rim
-    for i in 5..22 {
+    if !leaves_enough_for_rim(&set.used_edge_count, &search.total_edge_count, &search.needed_sides_for_edges, search.puzzle, dashboard) {
+        return false;
+    }
+    dashboard.log(format!("mega_tile_count: {:2}, Unique edge count: {:2}, Unpaired edge count: {:2}", mega_tile_count, total_unique_edge_types, unpaired_edges.len()));
+    true
+}
+
+// Make sure we have enough edges to fill the rim
+fn leaves_enough_for_rim(used_edge_count: &[i32], total_edge_count: &[i32], needed_sides_for_edges: &[i32], puzzle: &Puzzle, dashboard: &mut Dashboard) -> bool {
+    for i in puzzle.border_colour_count..puzzle.colour_count {
         if used_edge_count[i] + needed_sides_for_edges[i] > total_edge_count[i] {
-            println!("Failed edge count check: {} + {} > {}", used_edge_count[i], needed_sides_for_edges[i], total_edge_count[i]);
+            dashboard.log(format!("Failed edge count check: {} + {} > {}", used_edge_count[i], needed_sides_for_edges[i], total_edge_count[i]));
             return false;
         }
     }
-    println!("mega_tile_count: {:2}, Unique edge count: {:2}, Unpaired edge count: {:2}", mega_tile_count, total_unique_edge_types, unpaired_edges.len());
     true
 }
 
+impl SetBuilder for MegaTileSearch<'_> {
+    type Block = MegaTile;
 
-fn build_mega_tiles_recursive(available_mask: &mut [bool; 256],
-                              mega_tiles: &mut Vec<MegaTile>,
-                              mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                              unpaired_edges: &mut Vec<(i8, i8)>,
-                              paired_edges: &mut Vec<(i8, i8)>,
-                              rim_optimization: &mut Vec<(i8, i8)>,
-                              tile_lookup: &HashMap<(i8, i8, i8, i8), Vec<OrientedTile>>,
-                              wanted_sides_for_hints: &[i32; 22],
-                              needed_sides_for_edges: &[i32; 22],
-                              used_edge_count: &mut [i32; 22],
-                              total_edge_count: &[i32; 22]) -> bool
-{
-    let mega_tile_count = mega_tiles.len();
+    fn set_size(&self) -> usize {
+        self.puzzle.mega_tile_count()
+    }
+
+    fn fixed(&self) -> &PieceSet {
+        &self.pinned.fixed
+    }
+
+    fn picture(&self, set: &PartialSet<MegaTile>) -> Vec<String> {
+        mega_tile_picture(&set.blocks)
+    }
+
+    fn is_healthy(&self, set: &PartialSet<MegaTile>, dashboard: &mut Dashboard) -> bool {
+        check_healthy_tileset(set, self, dashboard)
+    }
+
+    fn candidates(&mut self, set: &mut PartialSet<MegaTile>, rng: &mut SearchRng) -> Vec<MegaTile> {
+        let mega_tile_count = set.blocks.len();
+        let edge_type_usage_score = get_edge_type_usage_score(&self.wanted_sides_for_hints, &self.needed_sides_for_edges, &set.used_edge_count, &self.total_edge_count);
+        let pinned_block = self.pinned.block_for(mega_tile_count, self.puzzle.mega_tile_count());
+        let mut sorted_by_score_mega_tiles = match self.mega_db {
+            // The database doesn't know where the fixed pieces have to go, so their blocks are still built from seeds
+            Some(mega_db) if pinned_block.is_none() => {
+                get_db_megatiles_sorted_by_score(mega_db, self.tiles, set, &edge_type_usage_score, rng)
+            }
+            _ => {
+                // Get a list of all the valid seeds for this mega-tile
+                let sorted_by_score_seeds = get_valid_mega_tile_seeds(set, self, pinned_block);
+
+                // The fixed pieces are kept out of the available set, so let this block's ones back in while its mega-tiles are built
+                set_pinned_available(pinned_block, &mut set.available, true);
+                let mega_tiles = get_all_possible_megatiles_sorted_by_score(&sorted_by_score_seeds, set, &self.tile_lookup, &edge_type_usage_score, rng);
+                set_pinned_available(pinned_block, &mut set.available, false);
+                mega_tiles
+            }
+        };
+        if let Some(block) = pinned_block {
+            sorted_by_score_mega_tiles.retain(|mega_tile| block.iter().all(|(tile, slot)| mega_tile.tiles[*slot].is_placed_as(tile.id(), tile.orientation())));
+        }
+        sorted_by_score_mega_tiles
+    }
+}
+
+// What Step 1 needs to know about the kind of block it's building a set of. build_set_recursive does the rest.
+trait SetBuilder {
+    type Block: EdgeBlock + Clone;
+
+    // Blocks in a full set
+    fn set_size(&self) -> usize;
+
+    // Pieces that only go in one particular block, so stay out of the available set when it's taken off
+    fn fixed(&self) -> &PieceSet;
+
+    fn picture(&self, set: &PartialSet<Self::Block>) -> Vec<String>;
+
+    // Whether the set so far is still worth building on
+    fn is_healthy(&self, set: &PartialSet<Self::Block>, dashboard: &mut Dashboard) -> bool;
+
+    // The blocks to try next, best first. The same set and rng have to give the same list for a checkpoint to replay.
+    fn candidates(&mut self, set: &mut PartialSet<Self::Block>, rng: &mut SearchRng) -> Vec<Self::Block>;
+}
+
+fn build_set_recursive<S: SetBuilder>(builder: &mut S, set: &mut PartialSet<S::Block>, run: &mut StepOneRun) -> bool {
+    let block_count = set.blocks.len();
+    run.stats.deepest = run.stats.deepest.max(block_count);
+    if run.dashboard.due() {
+        run.dashboard.draw(run.stats, &builder.picture(set));
+    }
+
+    // Another thread got there first, or a resume went wrong
+    if run.cancel.load(Ordering::Relaxed) || run.checkpointer.stopped() {
+        return false;
+    }
 
     // Check if we are within acceptable boundaries on the number of unpaired_edges
-    if !check_healthy_tileset(mega_tiles, mega_tile_edge_count, unpaired_edges, paired_edges, rim_optimization, mega_tile_count, used_edge_count, total_edge_count, needed_sides_for_edges){
+    if !builder.is_healthy(set, run.dashboard) {
         return false;
     }
 
-    // wasdwasd
-    if mega_tile_count == 49 { // Stopping one early because holy heck its hard to compute
+    if block_count == builder.set_size() {
         return true;
     }
 
-    // Get a list of all the valid seeds for this mega-tile
-    let edge_type_usage_score = get_edge_type_usage_score(wanted_sides_for_hints, needed_sides_for_edges, used_edge_count, total_edge_count);
-    let sorted_by_score_seeds = get_valid_mega_tile_seeds(available_mask, mega_tile_edge_count, unpaired_edges, paired_edges, rim_optimization, mega_tile_count, tile_lookup);
-    let sorted_by_score_mega_tiles = get_all_possible_megatiles_sorted_by_score(&sorted_by_score_seeds, available_mask, mega_tile_edge_count, unpaired_edges, paired_edges, rim_optimization, mega_tile_count, tile_lookup, &edge_type_usage_score);
+    // Save our progress every so often. When resuming, rewind the rng to where it was when this level's list was drawn up.
+    run.checkpointer.arrive(block_count, run.stats.nodes, run.rng);
+    let resume_frame = run.checkpointer.resume_frame(block_count).cloned();
+    if let Some(frame) = &resume_frame {
+        run.rng.set_word_pos(frame.rng_position);
+    }
+    let rng_position = run.rng.get_word_pos();
 
-    // println!("{} Mega tiles so far, {} seeds this step, {} options this step", mega_tile_count, sorted_by_score_seeds.len(), sorted_by_score_mega_tiles.len());
+    let candidates = builder.candidates(set, run.rng);
 
-    // Recurse
-    for mega_tile in sorted_by_score_mega_tiles.iter() {
+    // Skip straight to the candidate the checkpoint was on
+    let first_candidate = match &resume_frame {
+        Some(frame) if candidates.get(frame.candidate_index).is_some_and(|block| block_pieces(block) == frame.pieces) => frame.candidate_index,
+        Some(_) => {
+            run.checkpointer.fail_resume(block_count);
+            return false;
+        }
+        None => 0,
+    };
 
-        // Add the mega tile to the list
-        mega_tiles.push(mega_tile.clone());
+    // Recurse
+    for (candidate_index, block) in candidates.iter().enumerate().skip(first_candidate) {
+        run.checkpointer.set_frame(block_count, CheckpointFrame { candidate_index, rng_position, pieces: block_pieces(block) });
 
-        // Add the mega tile
-        add_mega_tile_to_trackers(mega_tile, available_mask, mega_tile_edge_count, unpaired_edges, mega_tile_count, used_edge_count);
-        recalculate_paired_and_unpaired_counts(mega_tile_edge_count, unpaired_edges, paired_edges);
+        // Add the block to the set
+        set.push(block.clone());
+        if !run.checkpointer.is_resuming() {
+            run.stats.nodes += 1; // Pieces replayed from a checkpoint were already counted
+        }
 
         // Recurse
-        if build_mega_tiles_recursive(available_mask, mega_tiles, mega_tile_edge_count, unpaired_edges, paired_edges, rim_optimization, tile_lookup, wanted_sides_for_hints, needed_sides_for_edges, used_edge_count, total_edge_count) {
+        if build_set_recursive(builder, set, run) {
             return true;
         }
+        run.dashboard.backtrack(block_count);
 
-        // Remove the mega tile from the list
-        remove_mega_tiles_from_trackers(mega_tile, available_mask, mega_tile_edge_count, unpaired_edges, mega_tile_count, used_edge_count);
-        recalculate_paired_and_unpaired_counts(mega_tile_edge_count, unpaired_edges, paired_edges);
-
-        // Remove the mega tile from the list
-        mega_tiles.pop();
-
-        // Add the tiles back to the available set
-        for tile in mega_tile.tiles.iter() {
-            available_mask[tile.id() as usize] = true;
-        }
+        // Take it back off again
+        set.pop(builder.fixed());
     }
 
     return false;
 }
 
-fn get_edge_type_usage_score(wanted_sides_for_hints: &[i32; 22],
-                             needed_sides_for_edges: &[i32; 22],
-                             used_edge_count: &[i32; 22],
-                             total_edge_count: &[i32; 22]) -> [i32; 22] {
+fn block_pieces<B: EdgeBlock>(block: &B) -> Vec<(usize, Orientation)> {
+    block.pieces().iter().map(|tile| (tile.id(), tile.orientation())).collect()
+}
+
+fn get_edge_type_usage_score(wanted_sides_for_hints: &[i32],
+                             needed_sides_for_edges: &[i32],
+                             used_edge_count: &[i32],
+                             total_edge_count: &[i32]) -> Vec<i32> {
     // Figure out which edges types we want to favour in the scoring system
-    let mut edge_type_usage_score = [0; 22];
+    let mut edge_type_usage_score = vec![0; total_edge_count.len()];
 
 
     // return total - used - wanted - needed
-    for i in 0..22 {
+    for i in 0..total_edge_count.len() {
         edge_type_usage_score[i] = total_edge_count[i] - used_edge_count[i] - wanted_sides_for_hints[i] - needed_sides_for_edges[i];
     }
     edge_type_usage_score
 }
 
-fn add_mega_tile_to_trackers(mega_tile: &MegaTile,
-                             available_mask: &mut [bool; 256],
-                             mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                             rim_optimization: &mut Vec<(i8, i8)>,
-                             mega_tile_count: usize,
-                             edge_usage_count: &mut [i32; 22]) {
-    // Update available_mask
-    for tile in mega_tile.tiles.iter() {
-        available_mask[tile.id() as usize] = false;
-        // edge usage count
-        for edge in [tile.top(), tile.right(), tile.bottom(), tile.left()].iter() {
-            edge_usage_count[*edge as usize] += 1;
+impl<B: EdgeBlock> PartialSet<B> {
+    fn new(puzzle: &Puzzle) -> PartialSet<B> {
+        PartialSet {
+            blocks: Vec::new(),
+            available: PieceSet::first(puzzle.piece_count()),
+            edge_count: BTreeMap::new(),
+            unpaired_edges: Vec::new(),
+            paired_edges: Vec::new(),
+            used_edge_count: vec![0; puzzle.colour_count],
         }
     }
 
-    // Update mega_tile_edge_count
-    for edge in [mega_tile.top(), mega_tile.right(), mega_tile.bottom(), mega_tile.left()].iter() {
-        let count = mega_tile_edge_count.entry(*edge).or_insert(0);
-        *count += 1;
-    }
-}
-
-fn remove_mega_tiles_from_trackers(mega_tile: &MegaTile,
-                                   available_mask: &mut [bool; 256],
-                                   mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                                   rim_optimization: &mut Vec<(i8, i8)>,
-                                   mega_tile_count: usize,
-                                   edge_usage_count: &mut [i32; 22]) {
-    // Update available_mask
-    for tile in mega_tile.tiles.iter() {
-        // Don't add the hint tiles back to the available set
-        if tile.id() == 138 || IS_USING_HINTS && (tile.id() == 207 || tile.id() == 254 || tile.id() == 180 || tile.id() == 248) {
-            continue;
+    // Add a block to the set and count its pieces and edges in
+    fn push(&mut self, mega_tile: B) {
+        // Update available
+        for tile in mega_tile.pieces().iter() {
+            self.available.remove(tile.id());
+            // edge usage count
+            for edge in [tile.top(), tile.right(), tile.bottom(), tile.left()].iter() {
+                self.used_edge_count[*edge as usize] += 1;
+            }
         }
-        available_mask[tile.id() as usize] = true;
-        // edge usage count
-        for edge in [tile.top(), tile.right(), tile.bottom(), tile.left()].iter() {
-            edge_usage_count[*edge as usize] -= 1;
+
+        // Update mega_tile_edge_count
+        for edge in mega_tile.edges().iter() {
+            let count = self.edge_count.entry(*edge).or_insert(0);
+            *count += 1;
         }
+        self.blocks.push(mega_tile);
+        self.recalculate_paired_and_unpaired_counts();
     }
 
-    // Update mega_tile_edge_count
-    for edge in [mega_tile.top(), mega_tile.right(), mega_tile.bottom(), mega_tile.left()].iter() {
-        // Remove the entry from the hashmap if 1, otherwise decrement
-        let count = mega_tile_edge_count.get_mut(edge).unwrap();
-        if *count == 1 {
-            mega_tile_edge_count.remove(edge);
-        } else {
-            *count -= 1;
+    // Take the last block back off and count it back out
+    fn pop(&mut self, fixed: &PieceSet) -> B {
+        let mega_tile = self.blocks.pop().expect("nothing to take off");
+        // Update available
+        for tile in mega_tile.pieces().iter() {
+            // Don't add the fixed tiles back to the available set, they're only let in for their own block.
+            // Their sides were counted on the way in though, so they come off the edge usage count like everyone else's.
+            if !fixed.contains(tile.id()) {
+                self.available.insert(tile.id());
+            }
+            // edge usage count
+            for edge in [tile.top(), tile.right(), tile.bottom(), tile.left()].iter() {
+                self.used_edge_count[*edge as usize] -= 1;
+            }
         }
-    }
-}
 
-fn recalculate_paired_and_unpaired_counts(mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                                          unpaired_edges: &mut Vec<(i8, i8)>,
-                                          paired_edges: &mut Vec<(i8, i8)>)
-{
-    unpaired_edges.clear();
-    paired_edges.clear();
-    for edge in mega_tile_edge_count.keys() {
-        let count = mega_tile_edge_count.get(edge).unwrap_or(&0);
-        if edge.0 == edge.1 {
-            if count % 2 == 0 {
-                paired_edges.push(*edge);
-                continue;
+        // Update mega_tile_edge_count
+        for edge in mega_tile.edges().iter() {
+            // Remove the entry from the map if 1, otherwise decrement
+            let count = self.edge_count.get_mut(edge).unwrap();
+            if *count == 1 {
+                self.edge_count.remove(edge);
             } else {
-                unpaired_edges.push(*edge);
-                continue;
+                *count -= 1;
             }
         }
-        let conjugate = (edge.1, edge.0);
-        // Get the conjugate count or 0 if it doesn't exist
-        let conjugate_count = mega_tile_edge_count.get(&conjugate).unwrap_or(&0);
-        if count > conjugate_count {
-            unpaired_edges.push(*edge);
-        }
-        if count == conjugate_count {
-            paired_edges.push(*edge);
-            paired_edges.push(conjugate);
+        self.recalculate_paired_and_unpaired_counts();
+        mega_tile
+    }
+
+    fn recalculate_paired_and_unpaired_counts(&mut self) {
+        self.unpaired_edges.clear();
+        self.paired_edges.clear();
+        for (edge, count) in self.edge_count.iter() {
+            if edge.is_symmetric() {
+                if count % 2 == 0 {
+                    self.paired_edges.push(*edge);
+                } else {
+                    self.unpaired_edges.push(*edge);
+                }
+                continue;
+            }
+            let conjugate = edge.conjugate();
+            // Get the conjugate count or 0 if it doesn't exist
+            let conjugate_count = self.edge_count.get(&conjugate).unwrap_or(&0);
+            if count > conjugate_count {
+                self.unpaired_edges.push(*edge);
+            }
+            if count == conjugate_count {
+                self.paired_edges.push(*edge);
+                self.paired_edges.push(conjugate);
+            }
         }
     }
 }
 
 fn check_oriented_tile_fits_in_with_unpaired_edges_as_seed(oriented_tile: &OrientedTile,
-                                                           unpaired_edges: &mut Vec<(i8, i8)>) -> bool{
+                                                           unpaired_edges: &[EdgeSignature]) -> bool{
     // Assuming this oriented tile is in the top-left position, check if it fits with the unpaired edges
     // println!("Checking if the oriented tile fits in with sides: {}, {}, {}, {}", oriented_tile.top(), oriented_tile.right(), oriented_tile.bottom(), oriented_tile.left());
     let mut fits_left = false;
     let mut fits_top = false;
     for edge in unpaired_edges.iter() {
         // println!("Checking edge: {}, {}", edge.0, edge.1);
-        if edge.0 != oriented_tile.left() {
+        if edge.first() != oriented_tile.left() {
             fits_left = true;
             continue; // We don't want to match both the left and top to the same edge
         }
-        if edge.1 != oriented_tile.top() { fits_top = true; }
+        if edge.last() != oriented_tile.top() { fits_top = true; }
     }
     return fits_top && fits_left;
 }
 
 
 
-fn get_all_possible_megatiles_sorted_by_score(seeds: &Vec<OrientedTile>,
-                                              available_mask: &mut [bool; 256],
-                                              mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                                              unpaired_edges: &mut Vec<(i8, i8)>,
-                                              paired_edges: &mut Vec<(i8, i8)>,
-                                              rim_optimization: &mut Vec<(i8, i8)>,
-                                              mega_tile_count: usize,
-                                              tile_lookup: &HashMap<(i8, i8, i8, i8), Vec<OrientedTile>>,
-                                              edge_type_usage_score: &[i32; 22]) -> Vec<MegaTile> {
+fn get_all_possible_megatiles_sorted_by_score(seeds: &[OrientedTile],
+                                              set: &PartialSet<MegaTile>,
+                                              tile_lookup: &TileLookup,
+                                              edge_type_usage_score: &[i32],
+                                              rng: &mut SearchRng) -> Vec<MegaTile> {
     // Builds all possible mega-tiles that start from the list of seeds
     // We need to score them as we generate them
     let mut mega_tiles_with_score: Vec<(i32, MegaTile)> = Vec::new();
+    let available = &set.available;
 
     for top_left_oriented_tile in seeds.iter() {
-        if let Some(top_right_tiles) = tile_lookup.get(&(-1, -1, -1, top_left_oriented_tile.right())) {
-            for top_right_tile in top_right_tiles.iter() {
-                if available_mask[top_right_tile.id() as usize] && top_right_tile.id() != top_left_oriented_tile.id(){
-                    if let Some(bottom_right_tiles) = tile_lookup.get(&(top_right_tile.bottom(), -1, -1, -1)) {
-                        for bottom_right_tile in bottom_right_tiles.iter() {
-                            if available_mask[bottom_right_tile.id() as usize] && bottom_right_tile.id() != top_left_oriented_tile.id() && bottom_right_tile.id() != top_right_tile.id(){
-                                if let Some(bottom_left_tiles) = tile_lookup.get(&(top_left_oriented_tile.bottom(), bottom_right_tile.left(), -1, -1)) {
-                                    for bottom_left_tile in bottom_left_tiles.iter() {
-                                        if available_mask[bottom_left_tile.id() as usize] && bottom_left_tile.id() != top_left_oriented_tile.id() && bottom_left_tile.id() != top_right_tile.id() && bottom_left_tile.id() != bottom_right_tile.id(){
-                                            // We have a valid mega-tile
-                                            let mega_tile = MegaTile{
-                                                tiles: [top_left_oriented_tile.clone(), top_right_tile.clone(), bottom_left_tile.clone(), bottom_right_tile.clone()]
-                                            };
-                                            let score = score_mega_tile(&mega_tile, mega_tile_edge_count, unpaired_edges, paired_edges, rim_optimization, mega_tile_count, edge_type_usage_score);
-                                            mega_tiles_with_score.push((score, mega_tile));
-                                        }
-                                    }
-                                }
+        for top_right_tile in tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, top_left_oriented_tile.right()) {
+            if available.contains(top_right_tile.id()) && top_right_tile.id() != top_left_oriented_tile.id(){
+                for bottom_right_tile in tile_lookup.get(top_right_tile.bottom(), ANY_SIDE, ANY_SIDE, ANY_SIDE) {
+                    if available.contains(bottom_right_tile.id()) && bottom_right_tile.id() != top_left_oriented_tile.id() && bottom_right_tile.id() != top_right_tile.id(){
+                        // Nothing free closes the block off
+                        if !tile_lookup.pieces(top_left_oriented_tile.bottom(), bottom_right_tile.left(), ANY_SIDE, ANY_SIDE).intersects(available) {
+                            continue;
+                        }
+                        for bottom_left_tile in tile_lookup.get(top_left_oriented_tile.bottom(), bottom_right_tile.left(), ANY_SIDE, ANY_SIDE) {
+                            if available.contains(bottom_left_tile.id()) && bottom_left_tile.id() != top_left_oriented_tile.id() && bottom_left_tile.id() != top_right_tile.id() && bottom_left_tile.id() != bottom_right_tile.id(){
+                                // We have a valid mega-tile
+                                let mega_tile = MegaTile{
+                                    tiles: [top_left_oriented_tile.clone(), top_right_tile.clone(), bottom_left_tile.clone(), bottom_right_tile.clone()]
+                                };
+                                let score = score_mega_tile(&mega_tile, set, edge_type_usage_score, rng);
+                                mega_tiles_with_score.push((score, mega_tile));
                             }
                         }
                     }
                 }
             }
+        }
+    }
+
+    best_first(mega_tiles_with_score)
+}
 
+const FIRST_DB_CANDIDATES: usize = 2_000; // Records sampled for the first mega-tile
+
+// The same thing with the candidates looked up instead of built: anything that pairs off one of the unpaired edges,
+// turned so that side is on top. A mega-tile that pairs off more than one is only scored once.
+// The first one has nothing to pair with and gets a random score anyway, so it's picked from a random sample.
+fn get_db_megatiles_sorted_by_score(mega_db: &MegaTileDb,
+                                    tiles: &[Tile],
+                                    set: &PartialSet<MegaTile>,
+                                    edge_type_usage_score: &[i32],
+                                    rng: &mut SearchRng) -> Vec<MegaTile> {
+    let available = &set.available;
+    let mut candidates: Vec<&MegaTileRecord> = Vec::new();
+    if set.blocks.is_empty() {
+        // Nothing to pair with yet, so anything, once each
+        let everything: Vec<&MegaTileRecord> = mega_db.records().iter().filter(|record| record.is_canonical() && record.is_available(available)).collect();
+        candidates.extend(everything.choose_multiple(rng, FIRST_DB_CANDIDATES));
+    } else {
+        let mut seen = HashSet::new();
+        for edge in set.unpaired_edges.iter() {
+            for record in mega_db.with_top(edge.conjugate()).iter() {
+                if record.is_available(available) && seen.insert(record.identity()) {
+                    candidates.push(record);
+                }
+            }
         }
     }
 
+    let mut mega_tiles_with_score: Vec<(i32, MegaTile)> = Vec::with_capacity(candidates.len());
+    for record in candidates {
+        let mega_tile = record.mega_tile(tiles);
+        let score = score_mega_tile(&mega_tile, set, edge_type_usage_score, rng);
+        mega_tiles_with_score.push((score, mega_tile));
+    }
+    best_first(mega_tiles_with_score)
+}
+
+fn best_first(mut mega_tiles_with_score: Vec<(i32, MegaTile)>) -> Vec<MegaTile> {
     // Sort by score -> Highest score at the start, then return
     mega_tiles_with_score.sort_by(|a, b| b.0.cmp(&a.0));
     let mut mega_tiles: Vec<MegaTile> = Vec::new();
@@ -481,52 +835,46 @@ fn get_all_possible_megatiles_sorted_by_score(seeds: &Vec<OrientedTile>,
     return mega_tiles;
 }
 
-fn score_mega_tile(mega_tile: &MegaTile,
-                   mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                   unpaired_edges: &mut Vec<(i8, i8)>,
-                   paired_edges: &mut Vec<(i8, i8)>,
-                   rim_optimization: &mut Vec<(i8, i8)>,
-                   mega_tile_count: usize,
-                   edge_type_usage_score: &[i32; 22]) -> i32
+fn score_mega_tile<B: EdgeBlock>(mega_tile: &B,
+                   set: &PartialSet<B>,
+                   edge_type_usage_score: &[i32],
+                   rng: &mut SearchRng) -> i32
 {
     // We use the scoring mechanism to control which tiles are prioritized based on the tile count
     // For the first 5 tiles, we don't really care so long as they match one of the sides.
     let mut score = 0;
 
-    let top = mega_tile.top();
-    let right = mega_tile.right();
-    let bottom = mega_tile.bottom();
-    let left = mega_tile.left();
+    let [top, right, bottom, left] = mega_tile.edges();
 
     // Little bonus for pieces that are symmetrical
     for side in [top, right, bottom, left].iter() {
-        if side.0 == side.1 { score += 100; }
+        if side.is_symmetric() { score += 100; }
     }
 
-    if mega_tile_count < 3 {
+    if set.blocks.len() < 3 {
         // We don't care about the score for the first 5 tiles
         // Literally just generate some random number lol
-        return rand::thread_rng().gen_range(1..1000);
+        return rng.gen_range(1..1000);
     }
 
     // Add score for edges that match with unpaired edges
-    for edge in unpaired_edges.iter() {
+    for edge in set.unpaired_edges.iter() {
         let mut has_matched_this_edge = false;
-        if edge.0 == top.1 && edge.1 == top.0 {
+        if *edge == top.conjugate() {
             score += 1000;
             has_matched_this_edge = true;
         }
-        if edge.0 == left.1 && edge.1 == left.0 {
+        if *edge == left.conjugate() {
             if !has_matched_this_edge { score += 1000; }
             else { score -= 10000; }
             has_matched_this_edge = true;
         }
-        if edge.0 == right.1 && edge.1 == right.0 {
+        if *edge == right.conjugate() {
             if !has_matched_this_edge { score += 1000; }
             else { score -= 10000; } // Matching the edge on two sides doesn't help
             has_matched_this_edge = true;
         }
-        if edge.0 == bottom.1 && edge.1 == bottom.0 {
+        if *edge == bottom.conjugate() {
             if !has_matched_this_edge { score += 1000; }
             else { score -= 10000; }
             has_matched_this_edge = true;
@@ -534,23 +882,23 @@ fn score_mega_tile(mega_tile: &MegaTile,
     }
 
     // Add score for edges that match with paired edges
-    for edge in paired_edges.iter() {
+    for edge in set.paired_edges.iter() {
         let mut has_matched_this_edge = false;
-        if edge.0 == top.1 && edge.1 == top.0 {
+        if *edge == top.conjugate() {
             score += 300;
             has_matched_this_edge = true;
         }
-        if edge.0 == left.1 && edge.1 == left.0 {
+        if *edge == left.conjugate() {
             if !has_matched_this_edge { score += 300; }
             else { score -= 10000; }
             has_matched_this_edge = true;
         }
-        if edge.0 == bottom.1 && edge.1 == bottom.0 {
+        if *edge == bottom.conjugate() {
             if !has_matched_this_edge { score += 300; }
             else { score -= 10000; }
             has_matched_this_edge = true;
         }
-        if edge.0 == right.1 && edge.1 == right.0 {
+        if *edge == right.conjugate() {
             if !has_matched_this_edge { score += 300; }
             else { score -= 10000; } // Matching the edge on two sides doesn't help
             has_matched_this_edge = true;
@@ -558,10 +906,10 @@ fn score_mega_tile(mega_tile: &MegaTile,
 
     }
     // clone the usage score
-    let mut remaining_available_usages = edge_type_usage_score.clone();
+    let mut remaining_available_usages = edge_type_usage_score.to_vec();
 
     // Want to make small adjustments to the score depending on what edges are used in this mega tile
-    for tile in mega_tile.tiles.iter() {
+    for tile in mega_tile.pieces().iter() {
         for edge in [tile.top(), tile.right(), tile.bottom(), tile.left()].iter() {
             score += remaining_available_usages[*edge as usize];
             remaining_available_usages[*edge as usize] -= 1;
@@ -577,56 +925,56 @@ fn score_mega_tile(mega_tile: &MegaTile,
 
 // Seed pieces are all the pieces that can be used to start a mega-tile.
 // Return a vector of all possible seeds in order of best to worst
-fn get_valid_mega_tile_seeds(available_mask: &mut [bool; 256],
-                             mega_tile_edge_count: &mut HashMap<(i8, i8), usize>,
-                             unpaired_edges: &mut Vec<(i8, i8)>,
-                             paired_edges: &mut Vec<(i8, i8)>,
-                             rim_optimization: &mut Vec<(i8, i8)>,
-                             mega_tile_count: usize,
-                             tile_lookup: &HashMap<(i8, i8, i8, i8), Vec<OrientedTile>>) -> Vec<(OrientedTile)> {
-    // For the first 5 mega-tiles, we want to use the starter piece and then the 4 hint pieces
-    if IS_USING_HINTS {
-        match mega_tile_count {
-            0 => return vec![(OrientedTile::new(138, &TILE_SET, 1))],
-            // The final 4 pieces should be hint pieces
-            45=> return if check_oriented_tile_fits_in_with_unpaired_edges_as_seed(&OrientedTile::new(207, &TILE_SET, 3), unpaired_edges)
-            { vec![(OrientedTile::new(207, &TILE_SET, 3))] } else { Vec::new() },
-            46 => return if check_oriented_tile_fits_in_with_unpaired_edges_as_seed(&OrientedTile::new(254, &TILE_SET, 2), unpaired_edges)
-            { vec![(OrientedTile::new(254, &TILE_SET, 2))] } else { Vec::new() },
-            47 => return if check_oriented_tile_fits_in_with_unpaired_edges_as_seed(&OrientedTile::new(180, &TILE_SET, 0), unpaired_edges)
-            { vec![(OrientedTile::new(180, &TILE_SET, 0))] } else { Vec::new() },
-            48 => return if check_oriented_tile_fits_in_with_unpaired_edges_as_seed(&OrientedTile::new(248, &TILE_SET, 2), unpaired_edges)
-            { vec![(OrientedTile::new(248, &TILE_SET, 2))] } else { Vec::new() },
-            _ => {}
-        }
-    } else {
-        match mega_tile_count {
-            0 => return vec![(OrientedTile::new(138, &TILE_SET, 1))],
-            _ => {}
-        }
+fn get_valid_mega_tile_seeds(set: &PartialSet<MegaTile>,
+                             search: &MegaTileSearch,
+                             pinned_block: Option<&[(OrientedTile, usize)]>) -> Vec<OrientedTile> {
+    let (available, unpaired_edges, mega_tile_count) = (&set.available, &set.unpaired_edges, set.blocks.len());
+    let (tile_lookup, tiles, puzzle) = (&search.tile_lookup, search.tiles, search.puzzle);
+    // Blocks with a fixed piece in them get built first and last
+    if let Some(block) = pinned_block {
+        return match block.iter().find(|(_, slot)| *slot == 0) {
+            // The fixed piece is the seed. Later on it also has to fit in with the edges that still need pairing.
+            Some((tile, _)) if mega_tile_count == 0 || check_oriented_tile_fits_in_with_unpaired_edges_as_seed(tile, unpaired_edges) => vec![*tile],
+            Some(_) => Vec::new(),
+            // Otherwise the seed is anything that fits against it. Mega-tiles without it in the right place get thrown out later.
+            None => {
+                let (tile, slot) = block[0];
+                let seeds = match slot {
+                    1 => tile_lookup.get(ANY_SIDE, tile.left(), ANY_SIDE, ANY_SIDE),
+                    2 => tile_lookup.get(ANY_SIDE, ANY_SIDE, tile.top(), ANY_SIDE),
+                    _ => tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, ANY_SIDE),
+                };
+                seeds.iter().filter(|seed| available.contains(seed.id())).copied().collect()
+            }
+        };
+    }
+
+    // Nothing fixed to start from, so kick things off with every interior piece. The first one can't be rotated meaningfully.
+    if mega_tile_count == 0 {
+        return (puzzle.border_piece_count()..puzzle.piece_count())
+            .filter(|i| available.contains(*i))
+            .map(|i| OrientedTile::new(i, tiles, Orientation::Deg0))
+            .collect();
     }
 
-    
     // Otherwise, the seed piece must match with two different unpaired edges on its top and left
-    let mut seed_pieces: Vec<(OrientedTile)> = Vec::new();
+    let mut seed_pieces: Vec<OrientedTile> = Vec::new();
     let mut last_inner: i8 = 0;
     let mut last_outer: i8 = 0;
 
     // Create a cross of unpaired_edges.0 and unpaired_edges.1 excluding the actual pairs themselves
     for (i, edge) in unpaired_edges.iter().enumerate() {
         // skip this loop if we have already checked this edge
-        if edge.1 == last_outer { continue; }
-        last_outer = edge.1;
+        if edge.last() == last_outer { continue; }
+        last_outer = edge.last();
         for (j, other_edge) in unpaired_edges.iter().enumerate() {
             // skip this loop if we have already checked this edge
-            if other_edge.0 == last_inner { continue; }
-            last_inner = other_edge.0;
+            if other_edge.first() == last_inner { continue; }
+            last_inner = other_edge.first();
             if i == j { continue; }
-            if let Some(oriented_tiles) = tile_lookup.get(&(edge.1, -1, -1, other_edge.0)) {
-                for oriented_tile in oriented_tiles.iter() {
-                    if available_mask[oriented_tile.id() as usize] {
-                        seed_pieces.push(*oriented_tile);
-                    }
+            for oriented_tile in tile_lookup.get(edge.last(), ANY_SIDE, ANY_SIDE, other_edge.first()) {
+                if available.contains(oriented_tile.id()) {
+                    seed_pieces.push(*oriented_tile);
                 }
             }
         }
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use memmap2::Mmap;
+use crate::block::EdgeSignature;
+use crate::checkpoint::tile_set_checksum;
+use crate::data::*;
+
+// Every 2x2 block the interior pieces can make, worked out once and saved, so Step 1 can look mega-tiles up by edge
+// instead of building them again at every level. The file is mapped into memory rather than read, it's tens of MB.
+// Each mega-tile is stored in all four turns, sorted by its outer signature (top, right, bottom and left bigrams) and
+// then by its set of pieces. Everything showing a bigram along the top sits in one run that can be binary searched.
+// Two mega-tiles with the same signature made of the same pieces do the same job whichever way round the middle is,
+// so only one of them is kept.
+//
+// Layout, little endian:
+//   header   "E2MEGADB", version u32, tile count u32, tile set checksum u64, record count u64
+//   records  16 bytes each, the eight colours of the four bigrams then each piece as id and quarter turns
+
+const MAGIC: &[u8; 8] = b"E2MEGADB";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 32;
+const RECORD_LEN: usize = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MegaTileRecord {
+    edges: [u8; 8],
+    pieces: [[u8; 2]; 4], // Top left, top right, bottom left, bottom right, like MegaTile
+}
+
+impl MegaTileRecord {
+    fn new(mega_tile: &MegaTile) -> MegaTileRecord {
+        let mut edges = [0; 8];
+        for (i, bigram) in [mega_tile.top(), mega_tile.right(), mega_tile.bottom(), mega_tile.left()].iter().enumerate() {
+            edges[i * 2] = bigram.0 as u8;
+            edges[i * 2 + 1] = bigram.1 as u8;
+        }
+        MegaTileRecord { edges, pieces: mega_tile.tiles.map(|tile| [tile.id() as u8, tile.orientation().quarter_turns()]) }
+    }
+
+    // Top, right, bottom, left
+    pub fn edges(&self) -> [EdgeSignature; 4] {
+        [0, 1, 2, 3].map(|side| self.edge(side))
+    }
+
+    fn edge(&self, side: usize) -> EdgeSignature {
+        EdgeSignature::from((self.edges[side * 2] as i8, self.edges[side * 2 + 1] as i8))
+    }
+
+    pub fn mega_tile(&self, tiles: &[Tile]) -> MegaTile {
+        MegaTile { tiles: self.pieces.map(|[id, turns]| OrientedTile::new(id as usize, tiles, Orientation::from_quarter_turns(turns).expect("turns are stored as 0 to 3"))) }
+    }
+
+    pub fn piece_ids(&self) -> [usize; 4] {
+        self.pieces.map(|[id, _]| id as usize)
+    }
+
+    pub fn is_available(&self, available: &PieceSet) -> bool {
+        self.pieces.iter().all(|[id, _]| available.contains(*id as usize))
+    }
+
+    // The same mega-tile turns up four times, once per turn. This picks out one of them, the one whose edges come first.
+    pub fn is_canonical(&self) -> bool {
+        (1..4).all(|turns| self.edges <= self.edges_turned(turns))
+    }
+
+    // Edges as they'd read after some clockwise quarter turns, the left bigram coming round to the top
+    fn edges_turned(&self, turns: usize) -> [u8; 8] {
+        let mut edges = self.edges;
+        edges.rotate_right(turns * 2);
+        edges
+    }
+
+    fn piece_set(&self) -> [usize; 4] {
+        let mut ids = self.piece_ids();
+        ids.sort_unstable();
+        ids
+    }
+
+    // Same signature and pieces, whichever turn and however the middle is arranged
+    pub fn identity(&self) -> ([u8; 8], [usize; 4]) {
+        let edges = (0..4).map(|turns| self.edges_turned(turns)).min().expect("four turns");
+        (edges, self.piece_set())
+    }
+}
+
+pub struct MegaTileDb {
+    map: Mmap,
+    record_count: usize,
+}
+
+impl MegaTileDb {
+    pub fn open(path: &Path, tiles: &[Tile]) -> Result<MegaTileDb, String> {
+        let file = File::open(path).map_err(|e| format!("could not open mega-tile database {}: {}", path.display(), e))?;
+        // Safe as long as nobody rewrites the file while it's mapped, and megadb writes a new one rather than editing
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| format!("could not map {}: {}", path.display(), e))?;
+        let bad = |problem: &str| format!("{} isn't a usable mega-tile database: {}", path.display(), problem);
+
+        if map.len() < HEADER_LEN || &map[..8] != MAGIC {
+            return Err(bad("wrong file type"));
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes(map[at..at + 4].try_into().expect("four bytes"));
+        let read_u64 = |at: usize| u64::from_le_bytes(map[at..at + 8].try_into().expect("eight bytes"));
+        if read_u32(8) != VERSION {
+            return Err(bad(&format!("version {}, expected {}", read_u32(8), VERSION)));
+        }
+        if read_u32(12) as usize != tiles.len() || read_u64(16) != tile_set_checksum(tiles) {
+            return Err(bad("it was built for a different tile set"));
+        }
+        let record_count = read_u64(24) as usize;
+        if map.len() != HEADER_LEN + record_count * RECORD_LEN {
+            return Err(bad("it's been cut short"));
+        }
+        Ok(MegaTileDb { map, record_count })
+    }
+
+    pub fn records(&self) -> &[MegaTileRecord] {
+        let bytes = &self.map[HEADER_LEN..];
+        // MegaTileRecord is nothing but bytes, so any 16 of them are a valid record and it doesn't need aligning
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const MegaTileRecord, self.record_count) }
+    }
+
+    // Every mega-tile showing this bigram along its top, turned that way up
+    pub fn with_top(&self, top: EdgeSignature) -> &[MegaTileRecord] {
+        let key = [top.first() as u8, top.last() as u8];
+        let records = self.records();
+        let start = records.partition_point(|record| record.edges[..2] < key[..]);
+        let end = records.partition_point(|record| record.edges[..2] <= key[..]);
+        &records[start..end]
+    }
+}
+
+// Every mega-tile the interior pieces make, in every turn. Takes a few seconds for the real puzzle.
+pub fn enumerate_mega_tiles(tiles: &[Tile], puzzle: &Puzzle) -> Vec<MegaTileRecord> {
+    let tile_lookup = build_indices(tiles, puzzle.border_piece_count());
+    let mut records = Vec::new();
+    for id in puzzle.border_piece_count()..puzzle.piece_count() {
+        for orientation in Orientation::ALL {
+            let top_left = OrientedTile::new(id, tiles, orientation);
+            for top_right in tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, top_left.right()) {
+                if top_right.id() == id {
+                    continue;
+                }
+                for bottom_right in tile_lookup.get(top_right.bottom(), ANY_SIDE, ANY_SIDE, ANY_SIDE) {
+                    if bottom_right.id() == id || bottom_right.id() == top_right.id() {
+                        continue;
+                    }
+                    for bottom_left in tile_lookup.get(top_left.bottom(), bottom_right.left(), ANY_SIDE, ANY_SIDE) {
+                        if [id, top_right.id(), bottom_right.id()].contains(&bottom_left.id()) {
+                            continue;
+                        }
+                        records.push(MegaTileRecord::new(&MegaTile { tiles: [top_left, *top_right, *bottom_left, *bottom_right] }));
+                    }
+                }
+            }
+        }
+    }
+
+    // Signature then piece set, keeping one of each. Only one way of arranging the middle survives, so the database can't be
+    // asked for a block that has to hold a fixed piece in a given square and turn. Those blocks are built from seeds instead,
+    // and it's the pinned_block check in MegaTileSearch::candidates that keeps them away from here.
+    records.sort_by_cached_key(|record| (record.edges, record.piece_set()));
+    records.dedup_by(|a, b| a.edges == b.edges && a.piece_set() == b.piece_set());
+    records
+}
+
+// Written to a temporary file and moved into place, so a database that's mapped somewhere else never changes under it
+pub fn save_mega_tile_db(path: &Path, records: &[MegaTileRecord], tiles: &[Tile]) -> Result<(), String> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + records.len() * RECORD_LEN);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&tile_set_checksum(tiles).to_le_bytes());
+    bytes.extend_from_slice(&(records.len() as u64).to_le_bytes());
+    for record in records.iter() {
+        bytes.extend_from_slice(&record.edges);
+        bytes.extend(record.pieces.iter().flatten());
+    }
+
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, bytes).and_then(|_| fs::rename(&temp_path, path))
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+// How the mega-tiles spread over signatures and bigrams. Rare bigrams are the ones Step 1 will struggle to pair up.
+pub fn print_mega_tile_db_stats(db: &MegaTileDb) {
+    let records = db.records();
+    let canonical = records.iter().filter(|record| record.is_canonical()).count();
+
+    // Records are sorted by signature, so each one is a run. Only one turn of each is counted.
+    let mut per_signature: Vec<(usize, [EdgeSignature; 4])> = records.chunk_by(|a, b| a.edges == b.edges)
+        .filter(|run| run[0].is_canonical())
+        .map(|run| (run.len(), run[0].edges()))
+        .collect();
+    let mut per_bigram: BTreeMap<EdgeSignature, usize> = BTreeMap::new();
+    for run in records.chunk_by(|a, b| a.edges[..2] == b.edges[..2]) {
+        per_bigram.insert(run[0].edge(0), run.len());
+    }
+
+    println!("{} mega-tiles, {} counting every turn", canonical, records.len());
+    println!("{} different signatures, {} different bigrams", per_signature.len(), per_bigram.len());
+
+    // How many signatures have 1, 2-3, 4-7, ... mega-tiles
+    let mut histogram: BTreeMap<u32, usize> = BTreeMap::new();
+    for (count, _) in per_signature.iter() {
+        *histogram.entry(count.ilog2()).or_insert(0) += 1;
+    }
+    println!("Mega-tiles per signature:");
+    for (power, signatures) in histogram.iter() {
+        println!("  {:>6} - {:<6} {} signatures", 1usize << power, (1usize << (power + 1)) - 1, signatures);
+    }
+
+    per_signature.sort_by_key(|(count, _)| std::cmp::Reverse(*count));
+    println!("Most common signatures (top, right, bottom, left):");
+    for (count, edges) in per_signature.iter().take(10) {
+        let letters: Vec<String> = edges.iter().map(|edge| edge.letters()).collect();
+        println!("  {:>6}  {}", count, letters.join(" "));
+    }
+
+    let mut bigrams: Vec<(&EdgeSignature, &usize)> = per_bigram.iter().collect();
+    bigrams.sort_by_key(|(_, count)| **count);
+    println!("Rarest bigrams along the top:");
+    for (bigram, count) in bigrams.iter().take(10) {
+        println!("  {:>6}  {}", count, bigram.letters());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::testing::solved_puzzle;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("eternity-{}-{}.megadb", name, std::process::id()))
+    }
+
+    // A small saved database, handing back where it is and its tile set
+    fn saved_db(name: &str) -> (PathBuf, Vec<Tile>, Vec<MegaTileRecord>) {
+        let (tiles, puzzle, _) = solved_puzzle(6, 6, 8, 3, 2);
+        let records = enumerate_mega_tiles(&tiles, &puzzle);
+        let path = temp_path(name);
+        save_mega_tile_db(&path, &records, &tiles).unwrap();
+        (path, tiles, records)
+    }
+
+    // Damages a saved database and hands back what open says about it
+    fn open_after(name: &str, damage: impl FnOnce(&mut Vec<u8>)) -> String {
+        let (path, tiles, _) = saved_db(name);
+        let mut bytes = fs::read(&path).unwrap();
+        damage(&mut bytes);
+        fs::write(&path, bytes).unwrap();
+        let result = MegaTileDb::open(&path, &tiles);
+        fs::remove_file(&path).unwrap();
+        result.err().expect("a damaged database shouldn't open")
+    }
+
+    #[test]
+    fn saved_records_come_back_in_top_bigram_runs() {
+        let (path, tiles, records) = saved_db("round-trip");
+        let db = MegaTileDb::open(&path, &tiles).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!records.is_empty());
+        assert_eq!(db.records(), &records[..]);
+
+        let tops: std::collections::BTreeSet<EdgeSignature> = records.iter().map(|record| record.edge(0)).collect();
+        for top in tops.iter() {
+            let expected: Vec<MegaTileRecord> = records.iter().filter(|record| record.edge(0) == *top).copied().collect();
+            assert_eq!(db.with_top(*top), &expected[..], "top {}", top.letters());
+        }
+        let unused = EdgeSignature::from((0, 0)); // A border colour, which no interior piece has
+        assert!(!tops.contains(&unused));
+        assert!(db.with_top(unused).is_empty());
+    }
+
+    #[test]
+    fn only_one_arrangement_is_kept_per_signature_and_piece_set() {
+        let (tiles, puzzle, _) = solved_puzzle(6, 6, 8, 3, 2);
+        let records = enumerate_mega_tiles(&tiles, &puzzle);
+        let mut keys: Vec<([u8; 8], [usize; 4])> = records.iter().map(|record| (record.edges, record.piece_set())).collect();
+        keys.dedup();
+        assert_eq!(keys.len(), records.len());
+        assert!(records.iter().all(|record| record.mega_tile(&tiles).tiles.iter().all(|tile| tile.id() >= puzzle.border_piece_count())));
+    }
+
+    #[test]
+    fn open_turns_down_a_wrong_magic() {
+        assert!(open_after("magic", |bytes| bytes[0] = b'X').contains("wrong file type"));
+        assert!(open_after("short-header", |bytes| bytes.truncate(HEADER_LEN - 1)).contains("wrong file type"));
+    }
+
+    #[test]
+    fn open_turns_down_another_version() {
+        let message = open_after("version", |bytes| bytes[8..12].copy_from_slice(&2u32.to_le_bytes()));
+        assert!(message.contains("version 2, expected 1"), "{}", message);
+    }
+
+    #[test]
+    fn open_turns_down_another_tile_set() {
+        let (path, _, _) = saved_db("tile-set");
+        let (other_tiles, _, _) = solved_puzzle(6, 6, 8, 3, 3);
+        let result = MegaTileDb::open(&path, &other_tiles);
+        fs::remove_file(&path).unwrap();
+        assert!(result.err().unwrap().contains("different tile set"));
+    }
+
+    #[test]
+    fn open_turns_down_a_truncated_file() {
+        assert!(open_after("truncated", |bytes| { bytes.pop(); }).contains("cut short"));
+        let one_more = |bytes: &mut Vec<u8>| {
+            let record_count = u64::from_le_bytes(bytes[24..32].try_into().unwrap());
+            bytes[24..32].copy_from_slice(&(record_count + 1).to_le_bytes());
+        };
+        assert!(open_after("record-count", one_more).contains("cut short"));
+    }
+}
@@ -0,0 +1,228 @@
+use rand::seq::SliceRandom;
+use crate::dashboard::*;
+use crate::data::*;
+use crate::solver::{Board, SearchStats};
+
+// Step 3 of the mega-tile builder: arrange the whole set over the interior, 7x7 blocks of 2x2 for the real puzzle.
+// It's the piece solver one level up. Blocks are filled row by row from the top left, and a mega-tile fits when each
+// bigram it shows a neighbour is that neighbour's bigram read the other way, its conjugate. Bigrams run clockwise, so
+// a block's top (left then right) has to be the bottom of the block above read right to left.
+// A mega-tile holding a fixed piece is pinned to the block that piece has to be in, turned to leave the piece the
+// right way up. If Step 2 fitted the rim, ring blocks also have to show the rim pieces the colours they need.
+
+const MEGA_GRID_NODE_LIMIT: u64 = 20_000_000; // Mega-tiles placed before giving up
+
+// The layout row by row over the interior's blocks, each mega-tile turned the way it sits
+pub type MegaGrid = Vec<MegaTile>;
+
+struct GridSearch<'a> {
+    mega_tiles: &'a [MegaTile],
+    blocks_wide: usize,
+    pinned: Vec<Option<(usize, u8)>>, // Mega-tile and turns for blocks holding a fixed piece
+    rim: Option<&'a Board>,
+    order: Vec<usize>, // Mega-tiles in the order they're tried
+    used: Vec<bool>,
+    placed: MegaGrid,
+    stats: SearchStats,
+}
+
+// None if there's no arrangement or the node limit ran out first
+pub fn solve_mega_grid(mega_tiles: &[MegaTile],
+                       puzzle: &Puzzle,
+                       fixed_pieces: &[FixedPiece],
+                       rim: Option<&Board>,
+                       rng: &mut SearchRng,
+                       dashboard: &mut Dashboard) -> Option<MegaGrid>
+{
+    if mega_tiles.len() != puzzle.mega_tile_count() {
+        println!("Need {} mega-tiles to fill the interior, got {}", puzzle.mega_tile_count(), mega_tiles.len());
+        return None;
+    }
+    let blocks_wide = (puzzle.width - 2) / 2;
+    let pinned = pin_fixed_pieces(mega_tiles, puzzle, fixed_pieces)?;
+    let mut order: Vec<usize> = (0..mega_tiles.len()).collect();
+    order.shuffle(rng);
+
+    let mut search = GridSearch {
+        mega_tiles,
+        blocks_wide,
+        used: vec![false; mega_tiles.len()],
+        pinned,
+        rim,
+        order,
+        placed: Vec::with_capacity(mega_tiles.len()),
+        stats: SearchStats::default(),
+    };
+    for (index, _) in search.pinned.iter().flatten() {
+        search.used[*index] = true;
+    }
+
+    let solved = solve_mega_grid_recursive(&mut search, dashboard);
+    if solved {
+        println!("Arranged all {} mega-tiles after placing {}", mega_tiles.len(), search.stats.nodes);
+        Some(search.placed)
+    } else {
+        println!("Couldn't arrange the mega-tiles. Placed {}, deepest {}/{}", search.stats.nodes, search.stats.deepest, mega_tiles.len());
+        None
+    }
+}
+
+// The arrangement as a full size board with the rim left empty
+pub fn expand_mega_grid(grid: &[MegaTile], puzzle: &Puzzle) -> Board {
+    let mut board = vec![vec![None; puzzle.width]; puzzle.height];
+    let blocks_wide = (puzzle.width - 2) / 2;
+    for (block, mega_tile) in grid.iter().enumerate() {
+        let (row, col) = (1 + block / blocks_wide * 2, 1 + block % blocks_wide * 2);
+        for (slot, tile) in mega_tile.tiles.iter().enumerate() {
+            board[row + slot / 2][col + slot % 2] = Some(*tile);
+        }
+    }
+    board
+}
+
+// Fixed pieces on the rim are Step 2's business. Anything inside decides where its mega-tile goes and how it's turned.
+fn pin_fixed_pieces(mega_tiles: &[MegaTile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece]) -> Option<Vec<Option<(usize, u8)>>> {
+    let blocks_wide = (puzzle.width - 2) / 2;
+    let mut pinned = vec![None; mega_tiles.len()];
+    for fixed in fixed_pieces.iter() {
+        let on_rim = fixed.row == 0 || fixed.col == 0 || fixed.row == puzzle.height - 1 || fixed.col == puzzle.width - 1;
+        if on_rim {
+            continue;
+        }
+        let block = (fixed.row - 1) / 2 * blocks_wide + (fixed.col - 1) / 2;
+        let slot = (fixed.row - 1) % 2 * 2 + (fixed.col - 1) % 2;
+        let pin = mega_tiles.iter().enumerate().find_map(|(index, mega_tile)| {
+            (0..4).find(|turns| {
+                mega_tile.turned(*turns).tiles[slot].is_placed_as(fixed.id, fixed.orientation)
+            }).map(|turns| (index, turns))
+        });
+        let Some(pin) = pin else {
+            println!("No mega-tile can put piece {} at {},{} the right way up", fixed.id, fixed.row, fixed.col);
+            return None;
+        };
+        if pinned[block].is_some_and(|other| other != pin) {
+            println!("Piece {} at {},{} needs a different mega-tile to another fixed piece in the same block", fixed.id, fixed.row, fixed.col);
+            return None;
+        }
+        pinned[block] = Some(pin);
+    }
+    Some(pinned)
+}
+
+fn conjugate(bigram: (i8, i8)) -> (i8, i8) {
+    (bigram.1, bigram.0)
+}
+
+fn fits(search: &GridSearch, block: usize, mega_tile: &MegaTile) -> bool {
+    let (block_row, block_col) = (block / search.blocks_wide, block % search.blocks_wide);
+    if block_row > 0 && mega_tile.top() != conjugate(search.placed[block - search.blocks_wide].bottom()) {
+        return false;
+    }
+    if block_col > 0 && mega_tile.left() != conjugate(search.placed[block - 1].right()) {
+        return false;
+    }
+    let Some(rim) = search.rim else { return true };
+
+    // Every piece against any rim piece beside it
+    let (row, col) = (1 + block_row * 2, 1 + block_col * 2);
+    mega_tile.tiles.iter().enumerate().all(|(slot, tile)| {
+        let (row, col) = (row + slot / 2, col + slot % 2);
+        rim[row - 1][col].is_none_or(|above| above.bottom() == tile.top())
+            && rim[row][col + 1].is_none_or(|right| right.left() == tile.right())
+            && rim[row + 1][col].is_none_or(|below| below.top() == tile.bottom())
+            && rim[row][col - 1].is_none_or(|left| left.right() == tile.left())
+    })
+}
+
+fn solve_mega_grid_recursive(search: &mut GridSearch, dashboard: &mut Dashboard) -> bool {
+    let block = search.placed.len();
+    if block > search.stats.deepest {
+        search.stats.deepest = block;
+    }
+    if dashboard.due() {
+        dashboard.draw(&search.stats, &mega_tile_picture(&search.placed));
+    }
+    if block == search.mega_tiles.len() {
+        return true;
+    }
+
+    let candidates: Vec<(usize, u8)> = match search.pinned[block] {
+        Some(pin) => vec![pin],
+        None => search.order.iter()
+            .filter(|index| !search.used[**index])
+            .flat_map(|index| (0..4).map(move |turns| (*index, turns)))
+            .collect(),
+    };
+
+    for (index, turns) in candidates {
+        let mega_tile = search.mega_tiles[index].turned(turns);
+        if !fits(search, block, &mega_tile) {
+            continue;
+        }
+
+        search.stats.nodes += 1;
+        search.used[index] = true;
+        search.placed.push(mega_tile);
+        if solve_mega_grid_recursive(search, dashboard) {
+            return true;
+        }
+        search.placed.pop();
+        if search.pinned[block].is_none() {
+            search.used[index] = false;
+        }
+        dashboard.backtrack(block);
+        if search.stats.nodes >= MEGA_GRID_NODE_LIMIT {
+            return false;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use super::*;
+    use crate::testing::*;
+    use crate::validate::{verify_board, BoardIssue};
+
+    #[test]
+    fn arranges_scrambled_mega_tiles_inside_the_rim() {
+        let (tiles, puzzle, solution) = solved_puzzle(10, 10, 12, 3, 11);
+        let placed = solution[4][5].unwrap();
+        let fixed = [FixedPiece { id: placed.id(), row: 4, col: 5, orientation: placed.orientation() }];
+        let mega_tiles = scrambled(&solution_mega_tiles(&solution, &puzzle), 11);
+
+        let mut rim = solution.clone();
+        for row in rim.iter_mut().take(puzzle.height - 1).skip(1) {
+            row[1..puzzle.width - 1].fill(None);
+        }
+        let grid = solve_mega_grid(&mega_tiles, &puzzle, &fixed, Some(&rim), &mut SearchRng::seed_from_u64(11), &mut Dashboard::disabled())
+            .expect("the solution's arrangement fits");
+
+        // With the rim round it that's the whole board
+        let mut board = expand_mega_grid(&grid, &puzzle);
+        for (row, rim_row) in rim.iter().enumerate() {
+            for (col, tile) in rim_row.iter().enumerate() {
+                board[row][col] = board[row][col].or(*tile);
+            }
+        }
+        let report = verify_board(&board, &tiles, Some(&puzzle), &fixed);
+        assert!(report.is_valid(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn arranges_scrambled_mega_tiles_without_a_rim() {
+        let (tiles, puzzle, solution) = solved_puzzle(10, 8, 12, 3, 12);
+        let placed = solution[5][2].unwrap();
+        let fixed = [FixedPiece { id: placed.id(), row: 5, col: 2, orientation: placed.orientation() }];
+        let mega_tiles = scrambled(&solution_mega_tiles(&solution, &puzzle), 12);
+        let grid = solve_mega_grid(&mega_tiles, &puzzle, &fixed, None, &mut SearchRng::seed_from_u64(12), &mut Dashboard::disabled())
+            .expect("the solution's arrangement fits");
+
+        // Only the rim is missing
+        let report = verify_board(&expand_mega_grid(&grid, &puzzle), &tiles, Some(&puzzle), &fixed);
+        assert_eq!(report.mismatch_count(), 0);
+        assert!(!report.issues.iter().any(|issue| matches!(issue, BoardIssue::FixedPieceMoved { .. } | BoardIssue::DuplicatePiece { .. })));
+        assert_eq!(report.placed, puzzle.interior_count());
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::SeedableRng;
+use crate::checkpoint::*;
+use crate::dashboard::Dashboard;
+use crate::data::*;
+use crate::display::*;
+use crate::helper::*;
+use crate::mega::*;
+use crate::megadb::MegaTileDb;
+use crate::solver::*;
+
+// Runs the same search on several threads at once. Every thread gets its own seed so they wander off in different
+// directions, and the first one to find something raises the shared cancel flag so the rest can stop.
+// This replaces launching several copies of the binary with run_alot.ps1.
+
+#[derive(Clone, Debug)]
+pub struct ParallelReport {
+    pub base_seed: u64,
+    pub per_thread: Vec<SearchStats>,
+    pub winner: Option<usize>, // The thread whose result was kept
+    pub elapsed: Duration,
+}
+
+impl ParallelReport {
+    pub fn total_nodes(&self) -> u64 {
+        self.per_thread.iter().map(|stats| stats.nodes).sum()
+    }
+
+    pub fn deepest(&self) -> usize {
+        self.per_thread.iter().map(|stats| stats.deepest).max().unwrap_or(0)
+    }
+}
+
+pub fn default_thread_count() -> usize {
+    thread::available_parallelism().map_or(1, |count| count.get())
+}
+
+// Seeds are handed out in order so a run can be repeated thread for thread from its base seed
+pub fn thread_seed(base_seed: u64, thread_index: usize) -> u64 {
+    base_seed.wrapping_add(thread_index as u64)
+}
+
+// Calls search once per thread with that thread's index and rng. A Some result wins and cancels everybody else.
+// cancel can also be raised from outside to stop the whole lot.
+pub fn run_parallel<T, F>(threads: usize, base_seed: u64, cancel: &AtomicBool, search: F) -> (Option<T>, ParallelReport)
+    where T: Send,
+          F: Fn(usize, &mut SearchRng, &AtomicBool, &mut SearchStats) -> Option<T> + Sync
+{
+    let start = Instant::now();
+    let threads = threads.max(1);
+    let winner = AtomicUsize::new(usize::MAX);
+
+    let results: Vec<(Option<T>, SearchStats)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|thread_index| {
+            let search = &search;
+            let winner = &winner;
+            scope.spawn(move || {
+                let mut rng = SearchRng::seed_from_u64(thread_seed(base_seed, thread_index));
+                let mut stats = SearchStats::default();
+                let result = search(thread_index, &mut rng, cancel, &mut stats);
+
+                // Only the first thread to finish gets to keep its result
+                let won = result.is_some() && winner.compare_exchange(usize::MAX, thread_index, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+                if won {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                (if won { result } else { None }, stats)
+            })
+        }).collect();
+        handles.into_iter().map(|handle| handle.join().expect("search thread panicked")).collect()
+    });
+
+    let mut found = None;
+    let mut per_thread = Vec::with_capacity(threads);
+    for (result, stats) in results {
+        if result.is_some() {
+            found = result;
+        }
+        per_thread.push(stats);
+    }
+    let winner = winner.into_inner();
+    let report = ParallelReport {
+        base_seed,
+        per_thread,
+        winner: if winner == usize::MAX { None } else { Some(winner) },
+        elapsed: start.elapsed(),
+    };
+    (found, report)
+}
+
+// What came out of one unit of an exhaustive search
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WorkUnitStats {
+    pub nodes: u64,
+    pub solutions: u64,
+}
+
+// Hands units out to the threads one at a time until they've all been done, so a thread that gets a small unit just
+// goes back for another. Results come back in unit order. Units nobody got to before cancel was raised are None.
+pub fn run_work_units<U, R, F>(threads: usize, units: &[U], cancel: &AtomicBool, work: F) -> Vec<Option<R>>
+    where U: Sync,
+          R: Send,
+          F: Fn(&U, &AtomicBool) -> R + Sync
+{
+    let next_unit = AtomicUsize::new(0);
+
+    let finished: Vec<Vec<(usize, R)>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads.max(1)).map(|_| {
+            let work = &work;
+            let next_unit = &next_unit;
+            scope.spawn(move || {
+                let mut finished = Vec::new();
+                while !cancel.load(Ordering::Relaxed) {
+                    let unit_index = next_unit.fetch_add(1, Ordering::Relaxed);
+                    if unit_index >= units.len() {
+                        break;
+                    }
+                    finished.push((unit_index, work(&units[unit_index], cancel)));
+                }
+                finished
+            })
+        }).collect();
+        handles.into_iter().map(|handle| handle.join().expect("search thread panicked")).collect()
+    });
+
+    let mut results: Vec<Option<R>> = (0..units.len()).map(|_| None).collect();
+    for (unit_index, result) in finished.into_iter().flatten() {
+        results[unit_index] = Some(result);
+    }
+    results
+}
+
+// The board is handed back rather than printed so the caller can pick the output format
+pub fn parallel_solve_board(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], threads: usize, base_seed: u64) -> Option<Board> {
+    println!("Searching on {} threads, base seed {}", threads, base_seed);
+    let cancel = AtomicBool::new(false);
+    let (solution, report) = run_parallel(threads, base_seed, &cancel, |_, rng, cancel, stats| {
+        solve_board_seeded(tiles, puzzle, fixed_pieces, Some(rng), Some(cancel), stats)
+    });
+
+    print_parallel_report(&report);
+    solution
+}
+
+pub fn parallel_create_mega_tiles(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], mega_db: Option<&MegaTileDb>, threads: usize, base_seed: u64) -> MegaTileResult {
+    print_random_data(tiles, puzzle);
+
+    println!("Searching on {} threads, base seed {}", threads, base_seed);
+    let cancel = AtomicBool::new(false);
+    let (mega_tiles, report) = run_parallel(threads, base_seed, &cancel, |_, rng, cancel, stats| {
+        let mut run = StepOneRun { rng, cancel, stats, checkpointer: &mut Checkpointer::disabled(), dashboard: &mut Dashboard::disabled() };
+        let mega_tiles = generate_initial_mega_tiles(tiles, puzzle, fixed_pieces, mega_db, &mut run);
+        if mega_tiles.is_empty() { None } else { Some(mega_tiles) }
+    });
+
+    print_parallel_report(&report);
+    // Step 2 carries on from the winning thread's seed, so --seed with --threads 1 repeats the whole run
+    let seed = report.winner.map_or(base_seed, |winner| thread_seed(base_seed, winner));
+    finish_mega_tiles(mega_tiles.unwrap_or_default(), tiles, puzzle, fixed_pieces, seed, &mut Dashboard::disabled())
+}
@@ -0,0 +1,132 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use crate::data::*;
+use crate::svg::{mega_tile_layout, mismatched_edges, Palette};
+use crate::validate::BoardSide;
+
+// Boards and mega-tiles as pixels, for thumbnails of long runs that can be stitched together afterwards.
+// Same layout as svg.rs: four triangles per piece meeting in the middle, and a red bar along every mismatched edge.
+// PPM is the simplest thing any image tool can read, PNG is the same pixels compressed.
+
+const SQUARE_SIZE: usize = 24;
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const OUTLINE: [u8; 3] = [51, 51, 51];
+const MISMATCH: [u8; 3] = [255, 0, 0];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RasterFormat {
+    Ppm,
+    Png,
+}
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>, // Row by row from the top left
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![BACKGROUND; width * height] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, colour: [u8; 3]) {
+        self.pixels[y * self.width + x] = colour;
+    }
+}
+
+// .ppm and .png files are pictures, anything else isn't
+pub fn raster_format(path: &Path) -> Option<RasterFormat> {
+    let extension = path.extension()?.to_ascii_lowercase();
+    match extension.to_str()? {
+        "ppm" => Some(RasterFormat::Ppm),
+        "png" => Some(RasterFormat::Png),
+        _ => None,
+    }
+}
+
+pub fn render_board(grid: &[Vec<Option<OrientedTile>>], palette: &Palette) -> Image {
+    let mut image = Image::new(grid.first().map_or(0, |row| row.len()) * SQUARE_SIZE, grid.len() * SQUARE_SIZE);
+    draw_grid(&mut image, grid, (0, 0), palette);
+    image
+}
+
+// Laid out like mega_tiles_to_svg, in rows as close to square as the count allows
+pub fn render_mega_tiles(mega_tiles: &[MegaTile], palette: &Palette) -> Image {
+    let layout = mega_tile_layout(mega_tiles, SQUARE_SIZE);
+    let mut image = Image::new(layout.width, layout.height);
+    for (grid, origin) in layout.grids.iter() {
+        draw_grid(&mut image, grid, *origin, palette);
+    }
+    image
+}
+
+pub fn save_image(image: &Image, path: &Path, format: RasterFormat) -> Result<(), String> {
+    let result = match format {
+        RasterFormat::Ppm => fs::write(path, image_to_ppm(image)).map_err(|e| e.to_string()),
+        RasterFormat::Png => write_png(image, path),
+    };
+    result.map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+pub fn image_to_ppm(image: &Image) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    bytes.extend(image.pixels.iter().flatten());
+    bytes
+}
+
+fn write_png(image: &Image, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    let data: Vec<u8> = image.pixels.iter().flatten().copied().collect();
+    writer.write_image_data(&data).map_err(|e| e.to_string())
+}
+
+fn draw_grid(image: &mut Image, grid: &[Vec<Option<OrientedTile>>], origin: (usize, usize), palette: &Palette) {
+    let (x0, y0) = origin;
+    let size = SQUARE_SIZE;
+    for (row, row_tiles) in grid.iter().enumerate() {
+        for (col, tile) in row_tiles.iter().enumerate() {
+            let (x, y) = (x0 + col * size, y0 + row * size);
+            for dy in 0..size {
+                for dx in 0..size {
+                    let on_outline = dx == 0 || dy == 0 || dx == size - 1 || dy == size - 1;
+                    let colour = match tile {
+                        // The diagonals split the square into its four triangles
+                        Some(_) if on_outline || dx == dy || dx + dy == size - 1 => OUTLINE,
+                        Some(oriented_tile) => {
+                            let side = match (dy < dx, dx + dy < size - 1) {
+                                (true, true) => oriented_tile.top(),
+                                (true, false) => oriented_tile.right(),
+                                (false, false) => oriented_tile.bottom(),
+                                (false, true) => oriented_tile.left(),
+                            };
+                            palette.rgb(side)
+                        }
+                        None if on_outline => [204, 204, 204],
+                        None => BACKGROUND,
+                    };
+                    image.set(x + dx, y + dy, colour);
+                }
+            }
+        }
+    }
+
+    // Mismatches go on top, straddling the edge so both squares show it
+    for (row, col, side) in mismatched_edges(grid) {
+        let (x, y) = (x0 + col * size, y0 + row * size);
+        let (across, down) = match side {
+            BoardSide::Right => (size - 2..size + 2, 0..size),
+            _ => (0..size, size - 2..size + 2),
+        };
+        for dy in down {
+            for dx in across.clone() {
+                image.set(x + dx, y + dy, MISMATCH);
+            }
+        }
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::HashSet;
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use crate::data::*;
+use crate::megagrid::expand_mega_grid;
+use crate::solver::Board;
+
+// Step 2 of the mega-tile builder. Step 1 hands over a loose set of mega-tiles, this lays them out over the interior so
+// that the border pieces can go round the outside.
+//
+// Every rim square faces one interior square, so once the mega-tiles are placed the colour each edge piece needs on its
+// inward side is known. Three checks say whether that's worth trying to fill:
+//   - the edge pieces' inward colours have to be exactly the colours the outer ring of mega-tiles shows them
+//   - each mega-tile side on the ring shows a bigram, two colours next to each other, and there has to be a pair of edge
+//     pieces with those inward colours that also match each other
+//   - going round the ring, some piece with the right inward colour has to be able to follow the one before it
+// Mega-tiles on the ring are turned, swapped with others and rebuilt by trading single pieces with another mega-tile
+// until all three pass, then the border pieces are searched for one at a time round the ring, clockwise from the top
+// left corner. That search settles each ring block as it reaches it, so it can still swap or turn one the checks let by.
+// Blocks holding a fixed piece never move. The interior layout is left as it falls, lining that up is the next step.
+
+const MAX_MOVES: usize = 20_000;      // Moves tried per attempt before giving up on getting the checks to pass
+const MAX_ATTEMPTS: usize = 50;
+const PLAIN_ATTEMPTS: usize = 10;      // Attempts with only turns and swaps before pieces start being traded
+const RIM_NODE_LIMIT: u64 = 2_000_000; // Pieces and mega-tiles placed per attempt at chaining round the ring
+const SHAKE_MOVES: usize = 10;         // Random moves between attempts, so the next one starts somewhere new
+
+// Border pieces with the row and column they go in
+pub type RimPieces = Vec<(usize, usize, OrientedTile)>;
+
+#[derive(Clone)]
+pub struct RimFit {
+    pub layout: Vec<MegaTile>, // Row by row over the interior's 2x2 blocks, each turned the way it sits. Rebuilt ones included.
+    pub rim: RimPieces,
+}
+
+impl RimFit {
+    pub fn to_board(&self, puzzle: &Puzzle) -> Board {
+        self.around(&self.layout, puzzle)
+    }
+
+    // The rim round some other arrangement of the interior, or round nothing at all
+    pub fn around(&self, interior: &[MegaTile], puzzle: &Puzzle) -> Board {
+        let mut grid = expand_mega_grid(interior, puzzle);
+        for (row, col, tile) in self.rim.iter() {
+            grid[*row][*col] = Some(*tile);
+        }
+        grid
+    }
+}
+
+#[derive(Copy, Clone)]
+enum RimKind {
+    Corner(usize), // 0 top left, then clockwise
+    // Which side of the board, then the interior square it faces as a block, a slot in the block and the side facing out
+    Edge { side: usize, block: usize, slot: usize },
+}
+
+#[derive(Copy, Clone)]
+struct RimSquare {
+    row: usize,
+    col: usize,
+    kind: RimKind,
+}
+
+impl RimSquare {
+    // The side of the board facing out. Corners face out on two sides, this is the one further round the ring.
+    fn outward_side(&self) -> usize {
+        match self.kind {
+            RimKind::Corner(corner) => corner,
+            RimKind::Edge { side, .. } => side,
+        }
+    }
+
+    // Turned so grey faces out. Edge pieces start with grey on the left, corners with grey on the bottom and left.
+    fn orientation(&self) -> Orientation {
+        Orientation::from_quarter_turns((self.outward_side() as u8 + 1) % 4).expect("always 0 to 3")
+    }
+
+    // The sides touching the previous and next squares round the ring
+    fn back_side(&self) -> usize {
+        match self.kind {
+            RimKind::Corner(corner) => (corner + 2) % 4,
+            RimKind::Edge { side, .. } => (side + 3) % 4,
+        }
+    }
+
+    fn forward_side(&self) -> usize {
+        (self.outward_side() + 1) % 4
+    }
+}
+
+// The set as it stands after any rebuilding, which of them sits on each block and how many quarter turns it has been given
+#[derive(Clone)]
+struct Layout {
+    mega_tiles: Vec<MegaTile>,
+    placed: Vec<(usize, u8)>,
+    locked: Vec<bool>, // Blocks holding a fixed piece
+}
+
+// Where a search round the ring has got to
+struct RimSearch {
+    demand: Vec<Option<i8>>,              // Inward colour each ring square needs, once the block it faces is settled
+    chosen: Vec<Option<(usize, u8)>>,     // Mega-tile and turns settled on each ring block
+    used: Vec<bool>,                      // Mega-tiles settled somewhere
+    wanted: Vec<usize>,                   // Edge pieces needed so far by inward colour
+    available: PieceSet,                  // Border pieces not yet placed
+    placed: Vec<OrientedTile>,            // Pieces round the ring so far
+    nodes: u64,
+}
+
+struct RimFitter<'a> {
+    tiles: &'a [Tile],
+    puzzle: &'a Puzzle,
+    ring: Vec<RimSquare>,
+    ring_blocks: Vec<usize>,
+    facing: Vec<Vec<(usize, usize, usize)>>, // For each block, the ring squares in front of it with the slot and side they face
+    bigrams: Vec<(usize, usize)>, // Pairs of ring squares facing the same mega-tile side
+    possible_bigrams: HashSet<(i8, i8)>,
+    supply: Vec<usize>,           // Edge pieces by inward colour
+    // Colour bitmasks of what each square can touch going backwards and forwards round the ring
+    edge_steps: Vec<Vec<(u64, u64)>>, // By inward colour
+    corner_steps: Vec<(u64, u64)>,
+    fixed_on_rim: Vec<Option<FixedPiece>>,
+}
+
+// None if the rim can't be fitted, after printing why
+pub fn fit_rim(mega_tiles: &[MegaTile], tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece], seed: u64) -> Option<RimFit> {
+    let fitter = RimFitter::new(mega_tiles, tiles, puzzle, fixed_pieces)?;
+    let mut rng = SearchRng::seed_from_u64(seed);
+    let mut layout = fitter.initial_layout(mega_tiles, fixed_pieces)?;
+
+    let mut violations = fitter.violations(&layout);
+    println!("Rim check: {} problems with the set as it came out of Step 1", violations);
+    for attempt in 0..MAX_ATTEMPTS {
+        // Rebuilding changes the set itself, so give the mega-tiles as they were built a fair chance first
+        let rebuild = attempt >= PLAIN_ATTEMPTS;
+        fitter.improve(&mut layout, &mut violations, rebuild, &mut rng);
+        if violations > 0 {
+            println!("Rim attempt {}: still {} problems after {} moves", attempt, violations, MAX_MOVES);
+        } else if let Some((repaired, rim)) = fitter.assign_rim(&layout, &mut rng) {
+            println!("Rim fitted on attempt {}", attempt);
+            return Some(RimFit { layout: fitter.turned_layout(&repaired), rim });
+        } else {
+            println!("Rim attempt {}: the colours add up but the border pieces won't chain together", attempt);
+        }
+
+        // Move somewhere new for the next attempt
+        for _ in 0..SHAKE_MOVES {
+            fitter.random_move(&mut layout, rebuild, &mut rng);
+        }
+        violations = fitter.violations(&layout);
+    }
+    println!("Couldn't fit the rim in {} attempts", MAX_ATTEMPTS);
+    None
+}
+
+impl<'a> RimFitter<'a> {
+    fn new(mega_tiles: &[MegaTile], tiles: &'a [Tile], puzzle: &'a Puzzle, fixed_pieces: &[FixedPiece]) -> Option<RimFitter<'a>> {
+        let (width, height) = (puzzle.width, puzzle.height);
+        if mega_tiles.len() != puzzle.mega_tile_count() {
+            println!("Need {} mega-tiles to fit the rim, got {}", puzzle.mega_tile_count(), mega_tiles.len());
+            return None;
+        }
+        if tiles.len() != puzzle.piece_count() {
+            println!("A {}x{} board needs {} pieces to fit the rim from, the tile set has {}", width, height, puzzle.piece_count(), tiles.len());
+            return None;
+        }
+        if puzzle.colour_count > u64::BITS as usize {
+            println!("Can't fit the rim with more than {} colours", u64::BITS);
+            return None;
+        }
+        let blocks_wide = (width - 2) / 2;
+        let blocks_high = (height - 2) / 2;
+        let edge = |side: usize, interior_row: usize, interior_col: usize| RimKind::Edge {
+            side,
+            block: (interior_row - 1) / 2 * blocks_wide + (interior_col - 1) / 2,
+            slot: (interior_row - 1) % 2 * 2 + (interior_col - 1) % 2,
+        };
+
+        // Clockwise from the top left corner
+        let mut ring = vec![RimSquare { row: 0, col: 0, kind: RimKind::Corner(0) }];
+        ring.extend((1..width - 1).map(|col| RimSquare { row: 0, col, kind: edge(0, 1, col) }));
+        ring.push(RimSquare { row: 0, col: width - 1, kind: RimKind::Corner(1) });
+        ring.extend((1..height - 1).map(|row| RimSquare { row, col: width - 1, kind: edge(1, row, width - 2) }));
+        ring.push(RimSquare { row: height - 1, col: width - 1, kind: RimKind::Corner(2) });
+        ring.extend((1..width - 1).rev().map(|col| RimSquare { row: height - 1, col, kind: edge(2, height - 2, col) }));
+        ring.push(RimSquare { row: height - 1, col: 0, kind: RimKind::Corner(3) });
+        ring.extend((1..height - 1).rev().map(|row| RimSquare { row, col: 0, kind: edge(3, row, 1) }));
+
+        // Neighbouring edge squares in front of the same block side. Sides are an even length so they pair off from the corner.
+        let mut bigrams = Vec::new();
+        let mut run_start = 1;
+        for (i, square) in ring.iter().enumerate().skip(1) {
+            if let RimKind::Corner(_) = square.kind {
+                run_start = i + 1;
+            } else if (i - run_start) % 2 == 1 {
+                bigrams.push((i - 1, i));
+            }
+        }
+
+        let ring_blocks = (0..blocks_wide * blocks_high)
+            .filter(|block| {
+                let (row, col) = (block / blocks_wide, block % blocks_wide);
+                row == 0 || col == 0 || row == blocks_high - 1 || col == blocks_wide - 1
+            })
+            .collect();
+
+        // Edge pieces lie with grey on the left, so the inward side is the right and the ring runs bottom to top.
+        // Corners lie with grey on the bottom and left, so the ring comes in on the right and leaves by the top.
+        let edge_pieces: Vec<&Tile> = tiles[puzzle.corner_count()..puzzle.border_piece_count()].iter().collect();
+        let mut supply = vec![0; puzzle.colour_count];
+        let mut possible_bigrams = HashSet::new();
+        let mut edge_steps = vec![Vec::new(); puzzle.colour_count];
+        let corner_steps = tiles[..puzzle.corner_count()].iter().map(|corner| (colour_bit(corner.right), colour_bit(corner.top))).collect();
+        for first in edge_pieces.iter() {
+            supply[first.right as usize] += 1;
+            edge_steps[first.right as usize].push((colour_bit(first.bottom), colour_bit(first.top)));
+            for second in edge_pieces.iter() {
+                if first.id != second.id && first.top == second.bottom {
+                    possible_bigrams.insert((first.right, second.right));
+                }
+            }
+        }
+
+        let mut facing = vec![Vec::new(); blocks_wide * blocks_high];
+        for (i, square) in ring.iter().enumerate() {
+            if let RimKind::Edge { side, block, slot } = square.kind {
+                facing[block].push((i, slot, side));
+            }
+        }
+
+        let fixed_on_rim = ring.iter()
+            .map(|square| fixed_pieces.iter().find(|fixed| (fixed.row, fixed.col) == (square.row, square.col)).copied())
+            .collect();
+
+        Some(RimFitter { tiles, puzzle, ring, ring_blocks, facing, bigrams, possible_bigrams, supply, edge_steps, corner_steps, fixed_on_rim })
+    }
+
+    fn blocks_wide(&self) -> usize {
+        (self.puzzle.width - 2) / 2
+    }
+
+    // Blocks with a fixed piece get that mega-tile as it was built, everything else fills in from the front of the set
+    fn initial_layout(&self, mega_tiles: &[MegaTile], fixed_pieces: &[FixedPiece]) -> Option<Layout> {
+        let block_count = mega_tiles.len();
+        let mut placed: Vec<Option<(usize, u8)>> = vec![None; block_count];
+        let mut locked = vec![false; block_count];
+        let mut used = vec![false; block_count];
+        for fixed in fixed_pieces.iter() {
+            let on_rim = fixed.row == 0 || fixed.col == 0 || fixed.row == self.puzzle.height - 1 || fixed.col == self.puzzle.width - 1;
+            if on_rim {
+                continue;
+            }
+            let block = (fixed.row - 1) / 2 * self.blocks_wide() + (fixed.col - 1) / 2;
+            let Some(index) = mega_tiles.iter().position(|mega_tile| mega_tile.tiles.iter().any(|tile| tile.id() == fixed.id)) else {
+                println!("Piece {} is fixed but isn't in any of the mega-tiles", fixed.id);
+                return None;
+            };
+            placed[block] = Some((index, 0));
+            locked[block] = true;
+            used[index] = true;
+        }
+
+        let mut unused = (0..block_count).filter(|index| !used[*index]);
+        let mut layout = Layout {
+            mega_tiles: mega_tiles.to_vec(),
+            placed: placed.iter().map(|entry| entry.or_else(|| unused.next().map(|index| (index, 0))).expect("one mega-tile per block")).collect(),
+            locked,
+        };
+        for block in self.ring_blocks.clone() {
+            if !layout.locked[block] {
+                self.best_turn(&mut layout, block);
+            }
+        }
+        Some(layout)
+    }
+
+    fn mega_tile_at(&self, layout: &Layout, block: usize) -> MegaTile {
+        let (index, turns) = layout.placed[block];
+        layout.mega_tiles[index].turned(turns)
+    }
+
+    fn turned_layout(&self, layout: &Layout) -> Vec<MegaTile> {
+        (0..layout.placed.len()).map(|block| self.mega_tile_at(layout, block)).collect()
+    }
+
+    // The colour each ring square has to show inwards, None for the corners
+    fn demand(&self, layout: &Layout) -> Vec<Option<i8>> {
+        self.ring.iter().map(|square| match square.kind {
+            RimKind::Corner(_) => None,
+            RimKind::Edge { side, block, slot } => Some(self.mega_tile_at(layout, block).tiles[slot].sides()[side]),
+        }).collect()
+    }
+
+    // Inward colours the edge pieces can't cover, plus bigrams no pair of edge pieces can show, plus places round the ring
+    // where nothing can follow on
+    fn violations(&self, layout: &Layout) -> usize {
+        let demand = self.demand(layout);
+        let mut shortfall = vec![0_i32; self.puzzle.colour_count];
+        for colour in demand.iter().flatten() {
+            if *colour >= 0 {
+                shortfall[*colour as usize] += 1;
+            }
+        }
+        let count_problems: i32 = shortfall.iter().zip(self.supply.iter()).map(|(wanted, supply)| (wanted - *supply as i32).max(0)).sum();
+        let bigram_problems = self.bigrams.iter()
+            .filter(|(first, second)| match (demand[*first], demand[*second]) {
+                (Some(a), Some(b)) => !self.possible_bigrams.contains(&(a, b)),
+                _ => false,
+            })
+            .count();
+        count_problems as usize + bigram_problems + self.chain_breaks(&demand)
+    }
+
+    // Which colours could be showing forwards after each square, ignoring that a piece can only be used once.
+    // When nothing fits the chain is broken there and starts again from anything that fits on its own.
+    fn chain_breaks(&self, demand: &[Option<i8>]) -> usize {
+        let mut reachable = u64::MAX;
+        let mut breaks = 0;
+        for colour in demand.iter() {
+            let steps = match colour {
+                None => &self.corner_steps,
+                Some(colour) if *colour >= 0 => &self.edge_steps[*colour as usize],
+                Some(_) => continue, // Grey facing the rim is already short in the counts
+            };
+            let next = steps.iter().filter(|(back, _)| reachable & back != 0).fold(0, |next, (_, forward)| next | forward);
+            if next == 0 {
+                breaks += 1;
+                reachable = steps.iter().fold(0, |next, (_, forward)| next | forward);
+            } else {
+                reachable = next;
+            }
+        }
+        breaks
+    }
+
+    fn best_turn(&self, layout: &mut Layout, block: usize) {
+        let best = (0..4).min_by_key(|turns| {
+            let mut trial = layout.clone();
+            trial.placed[block].1 = *turns;
+            self.violations(&trial)
+        });
+        layout.placed[block].1 = best.unwrap_or(0);
+    }
+
+    // Turn a ring block, or swap it with any other block or (when rebuilding) trade a piece with it, then give both their
+    // best turn
+    fn random_move(&self, layout: &mut Layout, rebuild: bool, rng: &mut SearchRng) {
+        let movable: Vec<usize> = (0..layout.placed.len()).filter(|block| !layout.locked[*block]).collect();
+        let movable_ring: Vec<usize> = self.ring_blocks.iter().copied().filter(|block| !layout.locked[*block]).collect();
+        let (Some(&block), Some(&other)) = (movable_ring.choose(rng), movable.choose(rng)) else {
+            return;
+        };
+        let roll: f64 = rng.gen();
+        if roll < 0.4 || block == other {
+            layout.placed[block].1 = (layout.placed[block].1 + rng.gen_range(1..4)) % 4;
+            return;
+        }
+        if roll < 0.8 || !rebuild {
+            layout.placed.swap(block, other);
+        } else if !self.trade_pieces(layout, block, other, rng) {
+            return;
+        }
+        self.best_turn(layout, block);
+        if self.ring_blocks.contains(&other) {
+            self.best_turn(layout, other);
+        }
+    }
+
+    // Rebuild two mega-tiles by swapping a piece between them, turning each piece whichever way keeps both matched
+    // inside. False if the pieces picked don't fit that way round.
+    fn trade_pieces(&self, layout: &mut Layout, block: usize, other: usize, rng: &mut SearchRng) -> bool {
+        let (index, other_index) = (layout.placed[block].0, layout.placed[other].0);
+        let (slot, other_slot) = (rng.gen_range(0..4), rng.gen_range(0..4));
+        let (mut mega_tile, mut other_mega_tile) = (layout.mega_tiles[index], layout.mega_tiles[other_index]);
+        let (piece, other_piece) = (mega_tile.tiles[slot], other_mega_tile.tiles[other_slot]);
+        let Some(turns) = (0..4).find(|turns| {
+            mega_tile.tiles[slot] = other_piece.turned(*turns);
+            holds_together(&mega_tile)
+        }) else {
+            return false;
+        };
+        let Some(other_turns) = (0..4).find(|turns| {
+            other_mega_tile.tiles[other_slot] = piece.turned(*turns);
+            holds_together(&other_mega_tile)
+        }) else {
+            return false;
+        };
+        mega_tile.tiles[slot] = other_piece.turned(turns);
+        other_mega_tile.tiles[other_slot] = piece.turned(other_turns);
+        layout.mega_tiles[index] = mega_tile;
+        layout.mega_tiles[other_index] = other_mega_tile;
+        true
+    }
+
+    // Hill climb, keeping any move that doesn't make things worse so it can wander across flat ground
+    fn improve(&self, layout: &mut Layout, violations: &mut usize, rebuild: bool, rng: &mut SearchRng) {
+        for _ in 0..MAX_MOVES {
+            if *violations == 0 {
+                return;
+            }
+            let before = layout.clone();
+            self.random_move(layout, rebuild, rng);
+            let after = self.violations(layout);
+            if after <= *violations {
+                *violations = after;
+            } else {
+                *layout = before;
+            }
+        }
+    }
+
+    // Border pieces round the ring one square at a time, each matching the interior and the piece before it.
+    // Each ring block is settled just before the first square in front of it, trying the layout's mega-tile as it's
+    // turned first and then any unused one at any turn, so a layout that's nearly right gets repaired on the way round.
+    fn assign_rim(&self, layout: &Layout, rng: &mut SearchRng) -> Option<(Layout, RimPieces)> {
+        let mut corners: Vec<usize> = (0..self.puzzle.corner_count()).collect();
+        let mut edges: Vec<usize> = (self.puzzle.corner_count()..self.puzzle.border_piece_count()).collect();
+        let mut order: Vec<usize> = (0..layout.mega_tiles.len()).collect();
+        corners.shuffle(rng);
+        edges.shuffle(rng);
+        order.shuffle(rng);
+
+        let mut search = RimSearch {
+            demand: vec![None; self.ring.len()],
+            chosen: vec![None; layout.placed.len()],
+            used: vec![false; layout.mega_tiles.len()],
+            wanted: vec![0; self.puzzle.colour_count],
+            available: PieceSet::first(self.tiles.len()),
+            placed: Vec::with_capacity(self.ring.len()),
+            nodes: 0,
+        };
+        for fixed in self.fixed_on_rim.iter().flatten() {
+            search.available.remove(fixed.id);
+        }
+        // Locked blocks stay as they are, which the checks have already passed
+        for block in (0..layout.placed.len()).filter(|block| layout.locked[*block]) {
+            search.used[layout.placed[block].0] = true;
+            if self.ring_blocks.contains(&block) {
+                let (index, turns) = layout.placed[block];
+                if !self.choose_block(&mut search, layout, block, index, turns) {
+                    return None;
+                }
+            }
+        }
+
+        if !self.assign_rim_recursive(layout, &order, &corners, &edges, &mut search) {
+            return None;
+        }
+
+        // The blocks off the ring take what's left, keeping their own mega-tile where it wasn't taken for the ring
+        let mut repaired = layout.clone();
+        for block in 0..layout.placed.len() {
+            if let Some(choice) = search.chosen[block] {
+                repaired.placed[block] = choice;
+            }
+        }
+        for block in (0..layout.placed.len()).filter(|block| search.chosen[*block].is_none() && !layout.locked[*block]) {
+            let index = layout.placed[block].0;
+            if search.used[index] {
+                let spare = (0..search.used.len()).find(|index| !search.used[*index]).expect("one mega-tile per block");
+                repaired.placed[block] = (spare, 0);
+            }
+            search.used[repaired.placed[block].0] = true;
+        }
+        let rim = self.ring.iter().zip(search.placed).map(|(square, tile)| (square.row, square.col, tile)).collect();
+        Some((repaired, rim))
+    }
+
+    // Takes the colours the mega-tile shows the rim, unless there aren't enough edge pieces left to show them back
+    fn choose_block(&self, search: &mut RimSearch, layout: &Layout, block: usize, index: usize, turns: u8) -> bool {
+        let mega_tile = layout.mega_tiles[index].turned(turns);
+        let facing: Vec<(usize, i8)> = self.facing[block].iter().map(|(square, slot, side)| (*square, mega_tile.tiles[*slot].sides()[*side])).collect();
+        for (_, colour) in facing.iter() {
+            if *colour < 0 {
+                return false;
+            }
+        }
+        for (i, (_, colour)) in facing.iter().enumerate() {
+            search.wanted[*colour as usize] += 1;
+            if search.wanted[*colour as usize] > self.supply[*colour as usize] {
+                for (_, taken) in facing[..=i].iter() {
+                    search.wanted[*taken as usize] -= 1;
+                }
+                return false;
+            }
+        }
+        for (square, colour) in facing {
+            search.demand[square] = Some(colour);
+        }
+        search.chosen[block] = Some((index, turns));
+        search.used[index] = true;
+        true
+    }
+
+    fn unchoose_block(&self, search: &mut RimSearch, block: usize) {
+        let Some((index, _)) = search.chosen[block].take() else { return };
+        for (square, _, _) in self.facing[block].iter() {
+            if let Some(colour) = search.demand[*square].take() {
+                search.wanted[colour as usize] -= 1;
+            }
+        }
+        search.used[index] = false;
+    }
+
+    fn assign_rim_recursive(&self, layout: &Layout, order: &[usize], corners: &[usize], edges: &[usize], search: &mut RimSearch) -> bool {
+        let index = search.placed.len();
+        if index == self.ring.len() {
+            return true;
+        }
+        if search.nodes >= RIM_NODE_LIMIT {
+            return false;
+        }
+        let square = self.ring[index];
+
+        if let RimKind::Edge { block, .. } = square.kind {
+            if search.chosen[block].is_none() {
+                let preferred = layout.placed[block];
+                let others = order.iter().flat_map(|index| (0..4).map(move |turns| (*index, turns))).filter(|choice| *choice != preferred);
+                for (mega_index, turns) in std::iter::once(preferred).chain(others) {
+                    if search.used[mega_index] {
+                        continue;
+                    }
+                    search.nodes += 1;
+                    if !self.choose_block(search, layout, block, mega_index, turns) {
+                        continue;
+                    }
+                    if self.assign_rim_recursive(layout, order, corners, edges, search) {
+                        return true;
+                    }
+                    self.unchoose_block(search, block);
+                }
+                return false;
+            }
+        }
+
+        let fixed = self.fixed_on_rim[index];
+        let candidates: Vec<OrientedTile> = match fixed {
+            Some(fixed) => vec![OrientedTile::new(fixed.id, self.tiles, fixed.orientation)],
+            None => {
+                let pieces = if let RimKind::Corner(_) = square.kind { corners } else { edges };
+                pieces.iter().filter(|id| search.available.contains(**id)).map(|id| OrientedTile::new(*id, self.tiles, square.orientation())).collect()
+            }
+        };
+
+        for tile in candidates {
+            let sides = tile.sides();
+            let fits_inside = search.demand[index].is_none_or(|colour| sides[(square.outward_side() + 2) % 4] == colour);
+            let fits_behind = search.placed.last().is_none_or(|previous| previous.sides()[self.ring[index - 1].forward_side()] == sides[square.back_side()]);
+            // The last square closes the ring back onto the first
+            let fits_ahead = index + 1 < self.ring.len() || search.placed[0].sides()[self.ring[0].back_side()] == sides[square.forward_side()];
+            if !(fits_inside && fits_behind && fits_ahead) {
+                continue;
+            }
+
+            search.nodes += 1;
+            search.available.remove(tile.id());
+            search.placed.push(tile);
+            if self.assign_rim_recursive(layout, order, corners, edges, search) {
+                return true;
+            }
+            search.placed.pop();
+            if fixed.is_none() {
+                search.available.insert(tile.id());
+            }
+        }
+        false
+    }
+}
+
+fn colour_bit(colour: i8) -> u64 {
+    if colour < 0 { 0 } else { 1 << colour }
+}
+
+// The four pieces meet each other on all four inside edges
+fn holds_together(mega_tile: &MegaTile) -> bool {
+    let [top_left, top_right, bottom_left, bottom_right] = mega_tile.tiles;
+    top_left.right() == top_right.left() && bottom_left.right() == bottom_right.left()
+        && top_left.bottom() == bottom_left.top() && top_right.bottom() == bottom_right.top()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use crate::validate::{verify_board, BoardIssue, BoardSide};
+
+    // Whether the edge between (row, col) and its neighbour on side touches the rim
+    fn touches_rim(row: usize, col: usize, side: BoardSide, puzzle: &Puzzle) -> bool {
+        let (next_row, next_col) = if side == BoardSide::Right { (row, col + 1) } else { (row + 1, col) };
+        [(row, col), (next_row, next_col)].iter().any(|(row, col)| *row == 0 || *col == 0 || *row == puzzle.height - 1 || *col == puzzle.width - 1)
+    }
+
+    #[test]
+    fn fits_the_rim_round_scrambled_mega_tiles() {
+        for (size, seed) in [(6, 0), (6, 1), (6, 2), (8, 3), (8, 4), (10, 5)] {
+            let (tiles, puzzle, solution) = solved_puzzle(size, size, 10, 3, seed);
+            let mega_tiles = scrambled(&solution_mega_tiles(&solution, &puzzle), seed);
+            let rim_fit = fit_rim(&mega_tiles, &tiles, &puzzle, &[], seed).expect("the solution's rim fits");
+
+            // Every piece once and grey all round. The interior isn't arranged yet so only edges against the rim have to match.
+            let report = verify_board(&rim_fit.to_board(&puzzle), &tiles, Some(&puzzle), &[]);
+            let rim_problems: Vec<&BoardIssue> = report.issues.iter()
+                .filter(|issue| !matches!(issue, BoardIssue::Mismatch { row, col, side, .. } if !touches_rim(*row, *col, *side, &puzzle)))
+                .collect();
+            assert!(rim_problems.is_empty(), "seed {}: {:?}", seed, rim_problems);
+        }
+    }
+
+    #[test]
+    fn keeps_a_fixed_rim_piece_where_it_is() {
+        let (tiles, puzzle, solution) = solved_puzzle(6, 6, 8, 3, 7);
+        let corner = solution[0][5].unwrap();
+        let fixed = [FixedPiece { id: corner.id(), row: 0, col: 5, orientation: corner.orientation() }];
+        let mega_tiles = scrambled(&solution_mega_tiles(&solution, &puzzle), 7);
+        let rim_fit = fit_rim(&mega_tiles, &tiles, &puzzle, &fixed, 7).expect("the solution's rim fits");
+        let report = verify_board(&rim_fit.to_board(&puzzle), &tiles, Some(&puzzle), &fixed);
+        assert!(!report.issues.iter().any(|issue| matches!(issue, BoardIssue::FixedPieceMoved { .. })));
+    }
+
+    #[test]
+    fn needs_every_mega_tile_and_every_piece() {
+        let (tiles, puzzle, solution) = solved_puzzle(6, 6, 8, 3, 7);
+        let mega_tiles = solution_mega_tiles(&solution, &puzzle);
+        assert!(fit_rim(&mega_tiles[1..], &tiles, &puzzle, &[], 7).is_none());
+        assert!(fit_rim(&mega_tiles, &tiles[..tiles.len() - 1], &puzzle, &[], 7).is_none());
+    }
+}
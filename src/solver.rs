@@ -0,0 +1,404 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::dashboard::*;
+use crate::data::*;
+
+// Backtracking solver for a complete board, rim included.
+// Cells are filled row by row from the top left, so the top and left neighbours are always known.
+// Grey is treated as a real colour here, which keeps corners and edges facing the right way.
+//
+// The same search also runs in a best-score mode. Instead of backtracking as soon as nothing fits it is allowed
+// to place pieces that clash with their top/left neighbours, up to a mismatch budget per band of rows,
+// and it keeps hold of the board with the most matched edges it has seen.
+
+// A board in progress, None for squares nothing has been placed on yet
+pub type Board = Vec<Vec<Option<OrientedTile>>>;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchStats {
+    pub nodes: u64,   // Number of pieces placed
+    pub deepest: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MismatchRegion {
+    // Rows first_row..=last_row may contain up to max_mismatches broken edges between them
+    pub first_row: usize,
+    pub last_row: usize,
+    pub max_mismatches: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MismatchBudget {
+    // Rows that aren't covered by any region have to match exactly
+    pub regions: Vec<MismatchRegion>,
+}
+
+impl MismatchBudget {
+    pub fn none() -> MismatchBudget {
+        MismatchBudget { regions: Vec::new() }
+    }
+
+    // "first-last:count" pairs separated by commas, e.g. "0-11:0,12-15:13"
+    pub fn from_spec(spec: &str) -> Result<MismatchBudget, String> {
+        let mut regions = Vec::new();
+        for part in spec.split(',').map(|part| part.trim()).filter(|part| !part.is_empty()) {
+            let parsed = part.split_once(':').and_then(|(rows, count)| {
+                let (first_row, last_row) = match rows.split_once('-') {
+                    Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+                    None => {
+                        let row = rows.trim().parse().ok()?;
+                        (row, row)
+                    }
+                };
+                Some(MismatchRegion { first_row, last_row, max_mismatches: count.trim().parse().ok()? })
+            });
+            match parsed {
+                Some(region) if region.first_row <= region.last_row => regions.push(region),
+                _ => return Err(format!("bad mismatch region '{}', expected first-last:count", part)),
+            }
+        }
+        Ok(MismatchBudget { regions })
+    }
+
+    // Index of the region a row belongs to, if any
+    fn region_for_row(&self, row: usize) -> Option<usize> {
+        self.regions.iter().position(|region| region.first_row <= row && row <= region.last_row)
+    }
+}
+
+#[derive(Clone)]
+pub struct BestBoard {
+    pub grid: Board,
+    pub score: usize, // Matched edges, out of puzzle.max_matches()
+    pub placed: usize,
+}
+
+// Saves a board, to --output in practice
+pub type SaveBest<'a> = Box<dyn FnMut(&BestBoard) -> Result<(), String> + 'a>;
+
+// Hands the best board so far over to be saved while a long search is still going, so an overnight run leaves
+// something behind even if it's killed. Only boards that beat the last one saved, and no more than once per interval.
+pub struct BestBoardSaver<'a> {
+    interval: Duration,
+    last_saved: Instant,
+    unsaved: bool, // There's a better board than the last one saved
+    save: SaveBest<'a>,
+}
+
+impl<'a> BestBoardSaver<'a> {
+    pub fn new(interval: Duration, save: impl FnMut(&BestBoard) -> Result<(), String> + 'a) -> BestBoardSaver<'a> {
+        BestBoardSaver { interval, last_saved: Instant::now(), unsaved: false, save: Box::new(save) }
+    }
+
+    fn save_if_due(&mut self, best: Option<&BestBoard>) {
+        if !self.unsaved || self.last_saved.elapsed() < self.interval {
+            return;
+        }
+        if let Some(best) = best {
+            // A failed save shouldn't end the search, the next one might work and the board is still printed at the end
+            if let Err(e) = (self.save)(best) {
+                println!("Couldn't save the best board so far: {}", e);
+            }
+        }
+        self.unsaved = false;
+        self.last_saved = Instant::now();
+    }
+}
+
+struct SearchState<'a> {
+    stats: SearchStats,
+    budget: MismatchBudget,
+    region_mismatches: Vec<usize>,
+    score: usize,
+    max_score: usize,
+    best: Option<BestBoard>,
+    node_limit: Option<u64>,
+    stop_at_first_solution: bool,
+    solved: bool,
+    cancel: Option<&'a AtomicBool>, // Raised by another thread when it doesn't need us any more
+    last_report: Instant,
+    dashboard: Dashboard,
+    best_saver: Option<BestBoardSaver<'a>>,
+}
+
+// An exact search for a full board, quiet so it can run on several threads at once. parallel_solve_board does the printing.
+// With an rng the pieces are tried in a shuffled order, so differently seeded runs explore different parts of the tree.
+// Gives up as soon as cancel is raised.
+pub fn solve_board_seeded(tiles: &[Tile],
+                          puzzle: &Puzzle,
+                          fixed_pieces: &[FixedPiece],
+                          rng: Option<&mut SearchRng>,
+                          cancel: Option<&AtomicBool>,
+                          stats: &mut SearchStats) -> Option<Board>
+{
+    let (mut grid, search_order) = place_fixed_pieces(tiles, puzzle, fixed_pieces)?;
+    let mut state = new_search_state(&grid, puzzle, MismatchBudget::none(), None, true, cancel, Dashboard::disabled());
+    run_search(tiles, &mut grid, &search_order, rng, &mut state);
+
+    *stats = state.stats;
+    if state.solved { Some(grid) } else { None }
+}
+
+// Keeps placing pieces when nothing fits, as long as the row's region still has mismatches to spend.
+// Runs until node_limit pieces have been placed, the tree is exhausted, or a perfect board turns up.
+// Only the score is printed, the board itself is left to the caller.
+pub fn search_best_board(tiles: &[Tile],
+                         puzzle: &Puzzle,
+                         fixed_pieces: &[FixedPiece],
+                         budget: &MismatchBudget,
+                         node_limit: Option<u64>,
+                         dashboard: Dashboard,
+                         best_saver: Option<BestBoardSaver>) -> Option<BestBoard>
+{
+    let (mut grid, search_order) = place_fixed_pieces(tiles, puzzle, fixed_pieces)?;
+    let mut state = SearchState { best_saver, ..new_search_state(&grid, puzzle, budget.clone(), node_limit, false, None, dashboard) };
+    run_search(tiles, &mut grid, &search_order, None, &mut state);
+    if let Some(best) = &state.best {
+        state.dashboard.draw(&state.stats, &board_picture(&best.grid)); // Leave the best board on screen
+    }
+
+    if let Some(best) = &state.best {
+        println!("Best board: {}/{} matches, {} pieces placed, {} placements tried", best.score, state.max_score, best.placed, state.stats.nodes);
+    }
+    state.best
+}
+
+fn place_fixed_pieces(tiles: &[Tile], puzzle: &Puzzle, fixed_pieces: &[FixedPiece]) -> Option<(Board, Vec<(usize, usize)>)> {
+    let mut grid: Board = vec![vec![None; puzzle.width]; puzzle.height];
+
+    for fixed in fixed_pieces.iter() {
+        if fixed.id >= tiles.len() || fixed.row >= puzzle.height || fixed.col >= puzzle.width {
+            println!("Fixed piece {} at {},{} doesn't fit on a {}x{} board", fixed.id, fixed.row, fixed.col, puzzle.width, puzzle.height);
+            return None;
+        }
+        grid[fixed.row][fixed.col] = Some(OrientedTile::new(fixed.id, tiles, fixed.orientation));
+    }
+
+    // Every empty square in reading order
+    let mut search_order = Vec::with_capacity(puzzle.piece_count());
+    for (row, cells) in grid.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            if cell.is_none() {
+                search_order.push((row, col));
+            }
+        }
+    }
+    Some((grid, search_order))
+}
+
+fn new_search_state<'a>(grid: &[Vec<Option<OrientedTile>>],
+                        puzzle: &Puzzle,
+                        budget: MismatchBudget,
+                        node_limit: Option<u64>,
+                        stop_at_first_solution: bool,
+                        cancel: Option<&'a AtomicBool>,
+                        dashboard: Dashboard) -> SearchState<'a>
+{
+    // Fixed pieces that touch each other already score before anything is placed
+    let mut score = 0;
+    for (row, cells) in grid.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            if let Some(tile) = cell {
+                if col + 1 < puzzle.width && grid[row][col + 1].is_some_and(|right| right.left() == tile.right()) { score += 1; }
+                if row + 1 < puzzle.height && grid[row + 1][col].is_some_and(|below| below.top() == tile.bottom()) { score += 1; }
+            }
+        }
+    }
+
+    SearchState {
+        stats: SearchStats::default(),
+        region_mismatches: vec![0; budget.regions.len()],
+        budget,
+        score,
+        max_score: puzzle.max_matches(),
+        best: None,
+        node_limit,
+        stop_at_first_solution,
+        solved: false,
+        cancel,
+        last_report: Instant::now(),
+        dashboard,
+        best_saver: None,
+    }
+}
+
+fn run_search(tiles: &[Tile],
+              grid: &mut [Vec<Option<OrientedTile>>],
+              search_order: &[(usize, usize)],
+              rng: Option<&mut SearchRng>,
+              state: &mut SearchState) -> bool
+{
+    let mut available = PieceSet::first(tiles.len());
+    for tile in grid.iter().flatten().flatten() {
+        available.remove(tile.id());
+    }
+    let mut tile_lookup = build_indices(tiles, 0);
+    if let Some(rng) = rng {
+        tile_lookup.shuffle_candidates(rng);
+    }
+    solve_board_recursive(grid, &mut available, &tile_lookup, search_order, 0, state)
+}
+
+// Returns true when the search should stop: a solution was found, we ran out of nodes or another thread cancelled us
+fn solve_board_recursive(grid: &mut [Vec<Option<OrientedTile>>],
+                         available: &mut PieceSet,
+                         tile_lookup: &TileLookup,
+                         search_order: &[(usize, usize)],
+                         search_index: usize,
+                         state: &mut SearchState) -> bool
+{
+    if search_index > state.stats.deepest {
+        state.stats.deepest = search_index;
+    }
+    if state.dashboard.due() {
+        state.dashboard.draw(&state.stats, &board_picture(grid));
+    }
+    if let Some(best_saver) = &mut state.best_saver {
+        best_saver.save_if_due(state.best.as_ref());
+    }
+
+    // Found a complete board! Only stop if it's a real solution or the search asked for the first one
+    if search_index == search_order.len() {
+        state.solved = state.stop_at_first_solution || state.score == state.max_score;
+        return state.solved;
+    }
+
+    let (row, col) = search_order[search_index];
+    let top = if row == 0 { -1 } else { grid[row - 1][col].unwrap().bottom() };
+    let left = if col == 0 { -1 } else { grid[row][col - 1].unwrap().right() };
+
+    // How many more broken edges this row is allowed
+    let region = state.budget.region_for_row(row);
+    let mismatches_left = match region {
+        Some(r) => state.budget.regions[r].max_mismatches - state.region_mismatches[r],
+        None => 0,
+    };
+
+    // Exact fits first, then pieces that break one of the top/left edges, then both
+    for broken_sides in 0..=mismatches_left.min(2) {
+        let relaxed_tiles: Vec<OrientedTile>;
+        let possible_tiles: &[OrientedTile] = match broken_sides {
+            // Nothing that's still free fits exactly, so don't bother walking the list
+            0 if !tile_lookup.pieces(top, ANY_SIDE, ANY_SIDE, left).intersects(available) => continue,
+            0 => tile_lookup.get(top, ANY_SIDE, ANY_SIDE, left),
+            1 => {
+                let top_only = tile_lookup.get(top, ANY_SIDE, ANY_SIDE, ANY_SIDE).iter().filter(|t| t.left() != left);
+                let left_only = tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, left).iter().filter(|t| t.top() != top);
+                relaxed_tiles = top_only.chain(left_only).copied().collect();
+                &relaxed_tiles
+            }
+            _ => {
+                relaxed_tiles = tile_lookup.get(ANY_SIDE, ANY_SIDE, ANY_SIDE, ANY_SIDE).iter().filter(|t| t.top() != top && t.left() != left).copied().collect();
+                &relaxed_tiles
+            }
+        };
+
+        for oriented_tile in possible_tiles.iter() {
+            if !available.contains(oriented_tile.id()) || !fits_rim(grid, row, col, oriented_tile) {
+                continue;
+            }
+            let (matched, broken) = count_neighbour_matches(grid, row, col, oriented_tile);
+            if broken > mismatches_left {
+                continue;
+            }
+
+            grid[row][col] = Some(*oriented_tile);
+            available.remove(oriented_tile.id());
+            state.stats.nodes += 1;
+            state.score += matched;
+            if let Some(r) = region { state.region_mismatches[r] += broken; }
+            record_best(grid, search_index + 1, state);
+
+            let stop = solve_board_recursive(grid, available, tile_lookup, search_order, search_index + 1, state)
+                || state.node_limit.is_some_and(|limit| state.stats.nodes >= limit)
+                || state.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+
+            if let Some(r) = region { state.region_mismatches[r] -= broken; }
+            state.score -= matched;
+            if stop {
+                return true;
+            }
+            available.insert(oriented_tile.id());
+            grid[row][col] = None;
+            state.dashboard.backtrack(search_index);
+        }
+    }
+    false // None of the branches worked
+}
+
+fn record_best(grid: &[Vec<Option<OrientedTile>>], placed: usize, state: &mut SearchState) {
+    // Plain solving only cares about the finished board
+    if state.stop_at_first_solution {
+        return;
+    }
+    let is_better = state.best.as_ref().is_none_or(|best| state.score > best.score || (state.score == best.score && placed > best.placed));
+    if !is_better {
+        return;
+    }
+    state.best = Some(BestBoard { grid: grid.to_vec(), score: state.score, placed });
+    state.dashboard.set_best(state.score, state.max_score);
+    if let Some(best_saver) = &mut state.best_saver {
+        best_saver.unsaved = true;
+    }
+
+    // Don't flood the terminal, one line a second is plenty. The dashboard has its own panel for it.
+    if state.last_report.elapsed().as_secs() >= 1 && !state.dashboard.is_enabled() {
+        println!("New best: {}/{} matches with {} pieces placed after {} placements", state.score, state.max_score, placed, state.stats.nodes);
+        state.last_report = Instant::now();
+    }
+}
+
+// Grey has to face outwards on the rim and nowhere else
+fn fits_rim(grid: &[Vec<Option<OrientedTile>>], row: usize, col: usize, oriented_tile: &OrientedTile) -> bool {
+    let height = grid.len();
+    let width = grid[0].len();
+    (row == 0) == (oriented_tile.top() == -1)
+        && (col == width - 1) == (oriented_tile.right() == -1)
+        && (row == height - 1) == (oriented_tile.bottom() == -1)
+        && (col == 0) == (oriented_tile.left() == -1)
+}
+
+// Matched and broken edges against every neighbour that's already on the board, fixed pieces included
+fn count_neighbour_matches(grid: &[Vec<Option<OrientedTile>>], row: usize, col: usize, oriented_tile: &OrientedTile) -> (usize, usize) {
+    let height = grid.len();
+    let width = grid[0].len();
+    let mut matched = 0;
+    let mut broken = 0;
+    let mut check = |matches: bool| if matches { matched += 1 } else { broken += 1 };
+
+    if row > 0 {
+        if let Some(neighbour) = grid[row - 1][col] { check(neighbour.bottom() == oriented_tile.top()); }
+    }
+    if col > 0 {
+        if let Some(neighbour) = grid[row][col - 1] { check(neighbour.right() == oriented_tile.left()); }
+    }
+    if col < width - 1 {
+        if let Some(neighbour) = grid[row][col + 1] { check(neighbour.left() == oriented_tile.right()); }
+    }
+    if row < height - 1 {
+        if let Some(neighbour) = grid[row + 1][col] { check(neighbour.top() == oriented_tile.bottom()); }
+    }
+    (matched, broken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::solved_puzzle;
+
+    #[test]
+    fn best_boards_are_saved_as_they_improve() {
+        let (tiles, puzzle, _) = solved_puzzle(5, 5, 8, 3, 1);
+        let budget = MismatchBudget::from_spec("0-4:6").unwrap();
+        let mut saved = Vec::new();
+        let best_saver = BestBoardSaver::new(Duration::ZERO, |best: &BestBoard| {
+            saved.push((best.score, best.placed));
+            Ok(())
+        });
+        let best = search_best_board(&tiles, &puzzle, &[], &budget, Some(10_000), Dashboard::disabled(), Some(best_saver)).unwrap();
+        assert!(!saved.is_empty());
+        assert!(saved.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", saved);
+        assert!(*saved.last().unwrap() <= (best.score, best.placed));
+    }
+}
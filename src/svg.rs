@@ -0,0 +1,199 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::data::*;
+use crate::solver::Board;
+use crate::validate::BoardSide;
+
+// Boards and mega-tiles as SVG, for reports and for comparing runs side by side without a wide ANSI terminal.
+// Each piece is four triangles meeting in the middle, one per side, filled from a palette indexed by colour id.
+// Edges where two neighbours disagree get a red line along them.
+//
+// A palette file is JSON, one #rrggbb colour per colour id and optionally one for grey. raster.rs uses the same palette.
+//
+//   { "grey": "#808080", "colours": ["#e6194b", "#3cb44b", ...] }
+//
+// Ids past the end of the list wrap round to the start.
+
+const SQUARE_SIZE: usize = 40;
+const MISMATCH_COLOUR: &str = "#ff0000";
+
+// 22 colours for the real puzzle, picked to stay apart from each other and from grey
+const DEFAULT_COLOURS: [&str; 22] = [
+    "#e6194b", "#3cb44b", "#ffe119", "#4363d8", "#f58231", "#911eb4", "#46f0f0", "#f032e6", "#bcf60c", "#fabebe", "#008080",
+    "#e6beff", "#9a6324", "#fffac8", "#800000", "#aaffc3", "#808000", "#ffd8b1", "#000075", "#a9a9a9", "#ffffff", "#000000",
+];
+
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub grey: String,
+    pub colours: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile {
+    grey: Option<String>,
+    colours: Vec<String>,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette { grey: String::from("#808080"), colours: DEFAULT_COLOURS.iter().map(|colour| colour.to_string()).collect() }
+    }
+}
+
+impl Palette {
+    pub fn from_json(text: &str) -> Result<Palette, String> {
+        let file: PaletteFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        if file.colours.is_empty() {
+            return Err(String::from("the palette has no colours"));
+        }
+        if let Some(bad) = file.grey.iter().chain(file.colours.iter()).find(|colour| parse_hex_colour(colour).is_none()) {
+            return Err(format!("'{}' isn't a #rrggbb colour", bad));
+        }
+        Ok(Palette { grey: file.grey.unwrap_or_else(|| Palette::default().grey), colours: file.colours })
+    }
+
+    pub fn load(path: &str) -> Result<Palette, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read palette {}: {}", path, e))?;
+        Palette::from_json(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn fill(&self, colour: i8) -> &str {
+        if colour < 0 { &self.grey } else { &self.colours[colour as usize % self.colours.len()] }
+    }
+
+    pub fn rgb(&self, colour: i8) -> [u8; 3] {
+        parse_hex_colour(self.fill(colour)).expect("palette colours are checked when they're loaded")
+    }
+}
+
+fn parse_hex_colour(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii())?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SvgStyle {
+    pub palette: Palette,
+    pub show_ids: bool,
+}
+
+pub fn board_to_svg(grid: &[Vec<Option<OrientedTile>>], style: &SvgStyle) -> String {
+    let width = grid.first().map_or(0, |row| row.len()) * SQUARE_SIZE;
+    let height = grid.len() * SQUARE_SIZE;
+    let mut svg = svg_header(width, height);
+    draw_grid(&mut svg, grid, (0, 0), style);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn mega_tiles_to_svg(mega_tiles: &[MegaTile], style: &SvgStyle) -> String {
+    let layout = mega_tile_layout(mega_tiles, SQUARE_SIZE);
+    let mut svg = svg_header(layout.width, layout.height);
+    for (grid, origin) in layout.grids.iter() {
+        draw_grid(&mut svg, grid, *origin, style);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Mega-tiles in rows, as close to square as the count allows, with a gap of half a square round each one.
+// raster.rs lays them out the same way.
+pub struct MegaTileLayout {
+    pub width: usize,
+    pub height: usize,
+    pub grids: Vec<(Board, (usize, usize))>, // Each mega-tile as a 2x2 grid, with its top left corner
+}
+
+pub fn mega_tile_layout(mega_tiles: &[MegaTile], square_size: usize) -> MegaTileLayout {
+    let gap = square_size / 2;
+    let per_row = (1..=mega_tiles.len()).find(|per_row| per_row * per_row >= mega_tiles.len()).unwrap_or(1);
+    let rows = mega_tiles.len().div_ceil(per_row);
+    let step = 2 * square_size + gap;
+    let grids = mega_tiles.iter().enumerate().map(|(i, mega_tile)| {
+        let grid = vec![vec![Some(mega_tile.tiles[0]), Some(mega_tile.tiles[1])],
+                        vec![Some(mega_tile.tiles[2]), Some(mega_tile.tiles[3])]];
+        (grid, (gap + i % per_row * step, gap + i / per_row * step))
+    }).collect();
+    MegaTileLayout { width: per_row * step + gap, height: rows * step + gap, grids }
+}
+
+// Every edge where two placed neighbours disagree, as the square on its left or above and the side of that square it's
+// on, Right or Bottom. Both pictures mark them the same way.
+pub fn mismatched_edges(grid: &[Vec<Option<OrientedTile>>]) -> Vec<(usize, usize, BoardSide)> {
+    let mut edges = Vec::new();
+    for (row, row_tiles) in grid.iter().enumerate() {
+        for (col, tile) in row_tiles.iter().enumerate() {
+            let Some(oriented_tile) = tile else { continue };
+            if let Some(Some(right)) = row_tiles.get(col + 1) {
+                if oriented_tile.right() != right.left() {
+                    edges.push((row, col, BoardSide::Right));
+                }
+            }
+            if let Some(Some(below)) = grid.get(row + 1).map(|below_row| below_row[col]) {
+                if oriented_tile.bottom() != below.top() {
+                    edges.push((row, col, BoardSide::Bottom));
+                }
+            }
+        }
+    }
+    edges
+}
+
+pub fn is_svg_path(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+}
+
+pub fn save_svg(svg: &str, path: &Path) -> Result<(), String> {
+    fs::write(path, svg).map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+fn svg_header(width: usize, height: usize) -> String {
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", width, height)
+}
+
+// Writing to a String can't fail, hence the ignored results
+fn draw_grid(svg: &mut String, grid: &[Vec<Option<OrientedTile>>], origin: (usize, usize), style: &SvgStyle) {
+    let (x0, y0) = origin;
+    let size = SQUARE_SIZE;
+    let half = size / 2;
+    for (row, row_tiles) in grid.iter().enumerate() {
+        for (col, tile) in row_tiles.iter().enumerate() {
+            let (x, y) = (x0 + col * size, y0 + row * size);
+            let Some(oriented_tile) = tile else {
+                let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{size}\" height=\"{size}\" fill=\"none\" stroke=\"#cccccc\"/>", x, y);
+                continue;
+            };
+            // Top, right, bottom, left, each a triangle from two corners to the centre
+            let corners = [(x, y), (x + size, y), (x + size, y + size), (x, y + size)];
+            for (side, colour) in oriented_tile.sides().iter().enumerate() {
+                let (a, b) = (corners[side], corners[(side + 1) % 4]);
+                let _ = writeln!(svg, "<polygon points=\"{},{} {},{} {},{}\" fill=\"{}\" stroke=\"#333333\" stroke-width=\"0.5\"/>",
+                                 a.0, a.1, b.0, b.1, x + half, y + half, style.palette.fill(*colour));
+            }
+            if style.show_ids {
+                let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-family=\"sans-serif\" font-size=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\" \
+                                       fill=\"#000000\" stroke=\"#ffffff\" stroke-width=\"2\" paint-order=\"stroke\">{}</text>",
+                                 x + half, y + half, size / 3, oriented_tile.id());
+            }
+        }
+    }
+
+    // Mismatches go on top so the neighbouring squares don't cover them
+    for (row, col, side) in mismatched_edges(grid) {
+        let (x, y) = (x0 + col * size, y0 + row * size);
+        match side {
+            BoardSide::Right => mismatch_line(svg, (x + size, y), (x + size, y + size)),
+            _ => mismatch_line(svg, (x, y + size), (x + size, y + size)),
+        }
+    }
+}
+
+fn mismatch_line(svg: &mut String, from: (usize, usize), to: (usize, usize)) {
+    let _ = writeln!(svg, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"4\" stroke-linecap=\"round\"/>",
+                     from.0, from.1, to.0, to.1, MISMATCH_COLOUR);
+}
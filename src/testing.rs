@@ -0,0 +1,80 @@
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use crate::data::*;
+use crate::solver::Board;
+
+// Small puzzles for the tests, made the other way round from usual: colour every join of a board at random, then cut
+// it up into pieces. The tile set comes out the way the loader would hand it over, corners then edges then interior,
+// border pieces turned so grey is on the left and the interior ones given a random turn. The board is the solution.
+pub fn solved_puzzle(width: usize, height: usize, colour_count: usize, border_colour_count: usize, seed: u64) -> (Vec<Tile>, Puzzle, Board) {
+    let mut rng = SearchRng::seed_from_u64(seed);
+    let on_rim = |row: usize, col: usize| row == 0 || col == 0 || row == height - 1 || col == width - 1;
+    let mut colour = |rim: bool| if rim { rng.gen_range(0..border_colour_count) as i8 } else { rng.gen_range(border_colour_count..colour_count) as i8 };
+
+    // Joins between two rim squares run along the border, everything else is an interior colour
+    let mut across = vec![vec![-1; width + 1]; height]; // across[row][col] is the join on the left of (row, col)
+    let mut down = vec![vec![-1; width]; height + 1];   // down[row][col] is the join above (row, col)
+    for (row, joins) in across.iter_mut().enumerate() {
+        for (col, join) in joins.iter_mut().enumerate().take(width).skip(1) {
+            *join = colour(on_rim(row, col - 1) && on_rim(row, col));
+        }
+    }
+    for (row, joins) in down.iter_mut().enumerate().take(height).skip(1) {
+        for (col, join) in joins.iter_mut().enumerate() {
+            *join = colour(on_rim(row - 1, col) && on_rim(row, col));
+        }
+    }
+
+    // Squares in the order the pieces are numbered
+    let mut corners = Vec::new();
+    let mut edges = Vec::new();
+    let mut interior = Vec::new();
+    for row in 0..height {
+        for col in 0..width {
+            let rim_sides = [row == 0, col == width - 1, row == height - 1, col == 0].iter().filter(|side| **side).count();
+            match rim_sides {
+                2 => corners.push((row, col)),
+                1 => edges.push((row, col)),
+                _ => interior.push((row, col)),
+            }
+        }
+    }
+
+    let mut tiles = Vec::new();
+    let mut grid = vec![vec![None; width]; height];
+    for (row, col) in corners.into_iter().chain(edges).chain(interior) {
+        let placed = [down[row][col], across[row][col + 1], down[row + 1][col], across[row][col]];
+        // Turn it back to how it's listed: grey on the bottom and left for corners, on the left for edges
+        let turns = match placed.iter().filter(|side| **side == -1).count() {
+            0 => rng.gen_range(0..4),
+            grey_sides => (0..4).find(|turns| (3 - (grey_sides - 1)..4).all(|side| placed[(side + turns) % 4] == -1)).expect("grey faces out"),
+        };
+        let listed: [i8; 4] = [0, 1, 2, 3].map(|side| placed[(side + turns) % 4]);
+        let id = tiles.len();
+        tiles.push(Tile { top: listed[0], right: listed[1], bottom: listed[2], left: listed[3], id });
+        let orientation = Orientation::from_quarter_turns(turns as u8).expect("always 0 to 3");
+        grid[row][col] = Some(OrientedTile::new(id, &tiles, orientation));
+    }
+    let puzzle = Puzzle { width, height, colour_count, border_colour_count };
+    (tiles, puzzle, grid)
+}
+
+// The solution's interior as mega-tiles, row by row over the 2x2 blocks the way expand_mega_grid lays them out
+pub fn solution_mega_tiles(board: &Board, puzzle: &Puzzle) -> Vec<MegaTile> {
+    let tile = |row: usize, col: usize| board[row][col].expect("the solution is full");
+    let mut mega_tiles = Vec::new();
+    for row in (1..puzzle.height - 1).step_by(2) {
+        for col in (1..puzzle.width - 1).step_by(2) {
+            mega_tiles.push(MegaTile { tiles: [tile(row, col), tile(row, col + 1), tile(row + 1, col), tile(row + 1, col + 1)] });
+        }
+    }
+    mega_tiles
+}
+
+// The same mega-tiles in a random order, each given a random turn
+pub fn scrambled(mega_tiles: &[MegaTile], seed: u64) -> Vec<MegaTile> {
+    let mut rng = SearchRng::seed_from_u64(seed);
+    let mut mega_tiles: Vec<MegaTile> = mega_tiles.iter().map(|mega_tile| mega_tile.turned(rng.gen_range(0..4))).collect();
+    mega_tiles.shuffle(&mut rng);
+    mega_tiles
+}
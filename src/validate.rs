@@ -0,0 +1,436 @@
+use crate::data::*;
+use crate::helper::*;
+
+// Checks the things the solvers assume about a tile set instead of panicking halfway through a run.
+// Corners first, then edges, then interior pieces. Grey (-1) only ever on the bottom/left of border pieces.
+// Every colour has to pair up, border colours can't leak into the interior and the counts have to fit the board.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TileClass {
+    Corner,
+    Edge,
+    Interior,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TileSetIssue {
+    BadGreyPlacement { id: usize },
+    BadColour { id: usize, colour: i8 },
+    OutOfOrder { id: usize, class: TileClass, after: TileClass },
+    OddColourCount { colour: i8, count: i32 },
+    SharedBorderColour { colour: i8 },
+    WrongTileCount { expected: usize, found: usize },
+    WrongPieceCount { class: TileClass, expected: usize, found: usize },
+}
+
+#[derive(Clone, Debug)]
+pub struct TileSetReport {
+    pub corner_count: usize,
+    pub edge_count: usize,
+    pub interior_count: usize,
+    pub puzzle: Puzzle,
+    pub colour_counts: Vec<i32>,
+    pub border_colours: Vec<i8>,
+    pub interior_colours: Vec<i8>,
+    pub issues: Vec<TileSetIssue>,
+}
+
+impl TileSetReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+// Corners have grey bottom and left, edges only a grey left. Anything else with grey on it is broken.
+pub fn classify_tile(tile: &Tile) -> Option<TileClass> {
+    if tile.top == -1 || tile.right == -1 {
+        return None;
+    }
+    match (tile.bottom == -1, tile.left == -1) {
+        (true, true) => Some(TileClass::Corner),
+        (false, true) => Some(TileClass::Edge),
+        (false, false) => Some(TileClass::Interior),
+        (true, false) => None,
+    }
+}
+
+pub fn validate_tileset(tiles: &[Tile], puzzle: &Puzzle) -> TileSetReport {
+    let mut issues = Vec::new();
+    let mut well_formed_tiles = Vec::with_capacity(tiles.len());
+    let mut corner_count = 0;
+    let mut edge_count = 0;
+    let mut interior_count = 0;
+    let mut highest_class_so_far = TileClass::Corner;
+
+    for tile in tiles.iter() {
+        let class = match classify_tile(tile) {
+            Some(class) => class,
+            None => {
+                issues.push(TileSetIssue::BadGreyPlacement { id: tile.id });
+                continue;
+            }
+        };
+        if let Some(colour) = [tile.top, tile.right, tile.bottom, tile.left].iter().find(|c| **c < -1 || **c >= puzzle.colour_count as i8) {
+            issues.push(TileSetIssue::BadColour { id: tile.id, colour: *colour });
+            continue;
+        }
+
+        match class {
+            TileClass::Corner => corner_count += 1,
+            TileClass::Edge => edge_count += 1,
+            TileClass::Interior => interior_count += 1,
+        }
+        if class < highest_class_so_far {
+            issues.push(TileSetIssue::OutOfOrder { id: tile.id, class, after: highest_class_so_far });
+        } else {
+            highest_class_so_far = class;
+        }
+        well_formed_tiles.push(*tile);
+    }
+
+    // Every colour is shared by two touching sides, so each one has to show up an even number of times
+    let colour_counts = count_tile_types(&well_formed_tiles, puzzle, true, true, true, false);
+    for (colour, count) in colour_counts.iter().enumerate() {
+        if count % 2 == 1 {
+            issues.push(TileSetIssue::OddColourCount { colour: colour as i8, count: *count });
+        }
+    }
+
+    // Border colours run along the rim. The inward side of an edge piece is an interior colour.
+    let mut is_border_colour = vec![false; puzzle.colour_count];
+    let mut is_interior_colour = vec![false; puzzle.colour_count];
+    for tile in well_formed_tiles.iter() {
+        match classify_tile(tile) {
+            Some(TileClass::Corner) => {
+                is_border_colour[tile.top as usize] = true;
+                is_border_colour[tile.right as usize] = true;
+            }
+            Some(TileClass::Edge) => {
+                is_border_colour[tile.top as usize] = true;
+                is_border_colour[tile.bottom as usize] = true;
+                is_interior_colour[tile.right as usize] = true;
+            }
+            _ => {
+                for side in [tile.top, tile.right, tile.bottom, tile.left].iter() {
+                    is_interior_colour[*side as usize] = true;
+                }
+            }
+        }
+    }
+    let border_colours: Vec<i8> = (0..puzzle.colour_count).filter(|c| is_border_colour[*c]).map(|c| c as i8).collect();
+    let interior_colours: Vec<i8> = (0..puzzle.colour_count).filter(|c| is_interior_colour[*c]).map(|c| c as i8).collect();
+    for colour in border_colours.iter() {
+        if is_interior_colour[*colour as usize] {
+            issues.push(TileSetIssue::SharedBorderColour { colour: *colour });
+        }
+    }
+
+    // A WxH board has 4 corners, 2(W-2) + 2(H-2) edges and (W-2)(H-2) interior pieces
+    if tiles.len() != puzzle.piece_count() {
+        issues.push(TileSetIssue::WrongTileCount { expected: puzzle.piece_count(), found: tiles.len() });
+    }
+    for (class, expected, found) in [(TileClass::Corner, puzzle.corner_count(), corner_count),
+                                     (TileClass::Edge, puzzle.edge_count(), edge_count),
+                                     (TileClass::Interior, puzzle.interior_count(), interior_count)] {
+        if expected != found {
+            issues.push(TileSetIssue::WrongPieceCount { class, expected, found });
+        }
+    }
+
+    TileSetReport {
+        corner_count,
+        edge_count,
+        interior_count,
+        puzzle: *puzzle,
+        colour_counts,
+        border_colours,
+        interior_colours,
+        issues,
+    }
+}
+
+// Checks a finished or partial board against its tile set: every piece used once, grey all the way round the outside,
+// fixed pieces where they should be, and every edge where two neighbours disagree.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoardSide {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BoardIssue {
+    WrongSize { expected: (usize, usize), found: (usize, usize) },
+    EmptySquare { row: usize, col: usize },
+    DuplicatePiece { id: usize, first: (usize, usize), again: (usize, usize) },
+    UnusedPiece { id: usize },
+    NonGreyRim { row: usize, col: usize, side: BoardSide, colour: i8 },
+    // Between (row, col) and its neighbour on side, which is always Right or Bottom so each edge is only listed once
+    Mismatch { row: usize, col: usize, side: BoardSide, colours: (i8, i8) },
+    FixedPieceMoved { fixed: FixedPiece, found: Option<(usize, Orientation)> },
+}
+
+#[derive(Clone, Debug)]
+pub struct BoardReport {
+    pub width: usize,
+    pub height: usize,
+    pub placed: usize,
+    pub score: usize,     // Edges between two placed pieces that match
+    pub max_score: usize, // Every internal edge, 480 for the real puzzle
+    pub issues: Vec<BoardIssue>,
+}
+
+impl BoardReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn mismatch_count(&self) -> usize {
+        self.issues.iter().filter(|issue| matches!(issue, BoardIssue::Mismatch { .. })).count()
+    }
+}
+
+// puzzle is None when the tile set doesn't say what size the board should be
+pub fn verify_board(grid: &[Vec<Option<OrientedTile>>], tiles: &[Tile], puzzle: Option<&Puzzle>, fixed_pieces: &[FixedPiece]) -> BoardReport {
+    let mut issues = Vec::new();
+    let height = grid.len();
+    let width = grid.first().map_or(0, |row| row.len());
+    if let Some(puzzle) = puzzle {
+        if (width, height) != (puzzle.width, puzzle.height) {
+            issues.push(BoardIssue::WrongSize { expected: (puzzle.width, puzzle.height), found: (width, height) });
+        }
+    }
+
+    // Each piece once
+    let mut first_seen: Vec<Option<(usize, usize)>> = vec![None; tiles.len()];
+    let mut placed = 0;
+    for (row, row_tiles) in grid.iter().enumerate() {
+        for (col, tile) in row_tiles.iter().enumerate() {
+            let Some(oriented_tile) = tile else {
+                issues.push(BoardIssue::EmptySquare { row, col });
+                continue;
+            };
+            placed += 1;
+            match first_seen[oriented_tile.id()] {
+                Some(first) => issues.push(BoardIssue::DuplicatePiece { id: oriented_tile.id(), first, again: (row, col) }),
+                None => first_seen[oriented_tile.id()] = Some((row, col)),
+            }
+        }
+    }
+    for (id, seen) in first_seen.iter().enumerate() {
+        if seen.is_none() {
+            issues.push(BoardIssue::UnusedPiece { id });
+        }
+    }
+
+    // Grey all the way round the outside
+    for (row, row_tiles) in grid.iter().enumerate() {
+        for (col, oriented_tile) in row_tiles.iter().enumerate() {
+            let Some(oriented_tile) = oriented_tile else { continue };
+            for (side, on_rim, colour) in [(BoardSide::Top, row == 0, oriented_tile.top()),
+                                           (BoardSide::Right, col == width - 1, oriented_tile.right()),
+                                           (BoardSide::Bottom, row == height - 1, oriented_tile.bottom()),
+                                           (BoardSide::Left, col == 0, oriented_tile.left())] {
+                if on_rim && colour != -1 {
+                    issues.push(BoardIssue::NonGreyRim { row, col, side, colour });
+                }
+            }
+        }
+    }
+
+    // Every internal edge, looking right and down from each square
+    let mut score = 0;
+    for row in 0..height {
+        for col in 0..width {
+            let Some(oriented_tile) = grid[row][col] else { continue };
+            if let Some(Some(right)) = grid[row].get(col + 1) {
+                if oriented_tile.right() == right.left() {
+                    score += 1;
+                } else {
+                    issues.push(BoardIssue::Mismatch { row, col, side: BoardSide::Right, colours: (oriented_tile.right(), right.left()) });
+                }
+            }
+            if let Some(Some(below)) = grid.get(row + 1).map(|below_row| below_row[col]) {
+                if oriented_tile.bottom() == below.top() {
+                    score += 1;
+                } else {
+                    issues.push(BoardIssue::Mismatch { row, col, side: BoardSide::Bottom, colours: (oriented_tile.bottom(), below.top()) });
+                }
+            }
+        }
+    }
+
+    for fixed in fixed_pieces.iter() {
+        let found = grid.get(fixed.row).and_then(|row| row.get(fixed.col)).copied().flatten();
+        if found.is_none_or(|oriented_tile| !oriented_tile.is_placed_as(fixed.id, fixed.orientation)) {
+            issues.push(BoardIssue::FixedPieceMoved { fixed: *fixed, found: found.map(|oriented_tile| (oriented_tile.id(), oriented_tile.orientation())) });
+        }
+    }
+
+    BoardReport {
+        width,
+        height,
+        placed,
+        score,
+        max_score: width * height.saturating_sub(1) + height * width.saturating_sub(1),
+        issues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Board;
+    use crate::testing::solved_puzzle;
+
+    // 4 corners, 14 edges from 4, then 12 interior pieces from 18
+    const INTERIOR: usize = 18;
+
+    fn tile_set_issues(tamper: impl FnOnce(&mut Vec<Tile>)) -> Vec<TileSetIssue> {
+        let (mut tiles, puzzle, _) = solved_puzzle(6, 5, 12, 3, 5);
+        tamper(&mut tiles);
+        validate_tileset(&tiles, &puzzle).issues
+    }
+
+    #[test]
+    fn a_generated_tile_set_is_valid() {
+        let (tiles, puzzle, _) = solved_puzzle(6, 5, 12, 3, 5);
+        let report = validate_tileset(&tiles, &puzzle);
+        assert_eq!(report.issues, vec![]);
+        assert_eq!((report.corner_count, report.edge_count, report.interior_count), (4, 14, 12));
+        assert_eq!(report.border_colours, vec![0, 1, 2]);
+        assert_eq!(report.interior_colours, (3..12).collect::<Vec<i8>>());
+    }
+
+    #[test]
+    fn grey_has_to_be_bottom_and_left() {
+        let issues = tile_set_issues(|tiles| tiles[INTERIOR].top = -1);
+        assert!(issues.contains(&TileSetIssue::BadGreyPlacement { id: INTERIOR }), "{:?}", issues);
+        let issues = tile_set_issues(|tiles| tiles[4].right = -1);
+        assert!(issues.contains(&TileSetIssue::BadGreyPlacement { id: 4 }), "{:?}", issues);
+    }
+
+    #[test]
+    fn colours_past_the_puzzles_are_bad() {
+        let issues = tile_set_issues(|tiles| tiles[INTERIOR].right = 12);
+        assert!(issues.contains(&TileSetIssue::BadColour { id: INTERIOR, colour: 12 }), "{:?}", issues);
+    }
+
+    #[test]
+    fn corners_edges_and_interior_come_in_order() {
+        let issues = tile_set_issues(|tiles| tiles.swap(4, INTERIOR));
+        assert!(issues.contains(&TileSetIssue::OutOfOrder { id: 5, class: TileClass::Edge, after: TileClass::Interior }), "{:?}", issues);
+    }
+
+    #[test]
+    fn colours_have_to_pair_up() {
+        let (tiles, _, _) = solved_puzzle(6, 5, 12, 3, 5);
+        let old = tiles[INTERIOR].top;
+        let new = if old == 3 { 4 } else { 3 };
+        let issues = tile_set_issues(|tiles| tiles[INTERIOR].top = new);
+        let odd: Vec<i8> = issues.iter().filter_map(|issue| match issue {
+            TileSetIssue::OddColourCount { colour, .. } => Some(*colour),
+            _ => None,
+        }).collect();
+        assert_eq!(odd, if old < new { vec![old, new] } else { vec![new, old] });
+    }
+
+    #[test]
+    fn border_colours_stay_on_the_rim() {
+        let issues = tile_set_issues(|tiles| {
+            tiles[INTERIOR].top = 0;
+            tiles[INTERIOR + 1].top = 0;
+        });
+        assert!(issues.contains(&TileSetIssue::SharedBorderColour { colour: 0 }), "{:?}", issues);
+    }
+
+    #[test]
+    fn counts_have_to_fit_the_board() {
+        let issues = tile_set_issues(|tiles| { tiles.pop(); });
+        assert!(issues.contains(&TileSetIssue::WrongTileCount { expected: 30, found: 29 }), "{:?}", issues);
+        assert!(issues.contains(&TileSetIssue::WrongPieceCount { class: TileClass::Interior, expected: 12, found: 11 }), "{:?}", issues);
+        let issues = tile_set_issues(|tiles| tiles[3].bottom = tiles[3].top);
+        assert!(issues.contains(&TileSetIssue::WrongPieceCount { class: TileClass::Corner, expected: 4, found: 3 }), "{:?}", issues);
+    }
+
+    // The solution, pinning whatever sits at (2, 2), after some tampering
+    fn board_report(tamper: impl FnOnce(&mut Board)) -> (Board, BoardReport) {
+        let (tiles, puzzle, mut board) = solved_puzzle(6, 5, 12, 3, 5);
+        let pinned = board[2][2].unwrap();
+        let fixed = FixedPiece { id: pinned.id(), row: 2, col: 2, orientation: pinned.orientation() };
+        tamper(&mut board);
+        let report = verify_board(&board, &tiles, Some(&puzzle), &[fixed]);
+        (board, report)
+    }
+
+    #[test]
+    fn the_solution_is_valid() {
+        let (_, report) = board_report(|_| ());
+        assert_eq!(report.issues, vec![]);
+        assert_eq!((report.placed, report.score, report.max_score), (30, 49, 49));
+    }
+
+    #[test]
+    fn pieces_can_only_be_used_once() {
+        let (board, report) = board_report(|board| board[3][3] = board[3][2]);
+        let id = board[3][3].unwrap().id();
+        assert!(report.issues.contains(&BoardIssue::DuplicatePiece { id, first: (3, 2), again: (3, 3) }), "{:?}", report.issues);
+        assert!(report.issues.iter().any(|issue| matches!(issue, BoardIssue::UnusedPiece { .. })), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn missing_pieces_leave_empty_squares() {
+        let (tiles, _, board) = solved_puzzle(6, 5, 12, 3, 5);
+        let id = board[3][3].unwrap().id();
+        let (_, report) = board_report(|board| board[3][3] = None);
+        assert_eq!(report.issues, vec![BoardIssue::EmptySquare { row: 3, col: 3 }, BoardIssue::UnusedPiece { id }]);
+        assert_eq!((report.placed, report.score), (tiles.len() - 1, 45));
+    }
+
+    #[test]
+    fn fixed_pieces_have_to_stay_put() {
+        let (board, report) = board_report(|board| {
+            let (left, right) = board[2].split_at_mut(3);
+            std::mem::swap(&mut left[2], &mut right[0]);
+        });
+        let moved = report.issues.iter().find_map(|issue| match issue {
+            BoardIssue::FixedPieceMoved { fixed, found } => Some((fixed.id, *found)),
+            _ => None,
+        });
+        let now_there = board[2][2].unwrap();
+        assert_eq!(moved, Some((board[2][3].unwrap().id(), Some((now_there.id(), now_there.orientation())))));
+    }
+
+    #[test]
+    fn mismatched_edges_are_listed_once() {
+        let (board, report) = board_report(|board| board[1][3] = board[1][3].map(|tile| tile.turned(2)));
+        let at = |row: usize, col: usize| board[row][col].unwrap();
+        // Each edge round the turned piece, in the order they're checked
+        let expected: Vec<BoardIssue> = [(0, 3, BoardSide::Bottom, (at(0, 3).bottom(), at(1, 3).top())),
+                                         (1, 2, BoardSide::Right, (at(1, 2).right(), at(1, 3).left())),
+                                         (1, 3, BoardSide::Right, (at(1, 3).right(), at(1, 4).left())),
+                                         (1, 3, BoardSide::Bottom, (at(1, 3).bottom(), at(2, 3).top()))]
+            .into_iter()
+            .filter(|(_, _, _, (a, b))| a != b)
+            .map(|(row, col, side, colours)| BoardIssue::Mismatch { row, col, side, colours })
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(report.issues, expected);
+        assert_eq!(report.score, 49 - report.mismatch_count());
+    }
+
+    #[test]
+    fn grey_has_to_face_off_the_board() {
+        let (board, report) = board_report(|board| board[0][0] = board[0][0].map(|tile| tile.turned(1)));
+        let turned = board[0][0].unwrap();
+        assert!(report.issues.contains(&BoardIssue::NonGreyRim { row: 0, col: 0, side: BoardSide::Left, colour: turned.left() }), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn boards_have_to_be_the_puzzles_size() {
+        let (_, report) = board_report(|board| { board.pop(); });
+        assert_eq!(report.issues[0], BoardIssue::WrongSize { expected: (6, 5), found: (6, 4) });
+    }
+}